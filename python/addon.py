@@ -46,26 +46,61 @@ class CustomRenderEngine(bpy.types.RenderEngine):
         else:
             color = [0.2, 0.1, 0.1, 1.0]
             
-        tris = get_triangles(depsgraph)
+        objects = get_triangles(depsgraph)
 
         cam = bpy.context.scene.camera
-        cam_pos = cam.location
+        cam_pos = cam.matrix_world.to_translation()
         
         
         cam_direction = cam.matrix_world.to_quaternion() @ Vector((0.0, 0.0, -1.0))
         cam_look = cam_pos + cam_direction
-        cam_look = ray_tracer.Vec3(cam_direction[0], cam_direction[1], cam_direction[2])
+        cam_look = ray_tracer.Vec3(cam_look[0], cam_look[1], cam_look[2])
 
         cam_pos = ray_tracer.Vec3(cam_pos[0], cam_pos[1], cam_pos[2])
         
         cam_up = cam.matrix_world.to_quaternion() @ Vector((0.0, 1.0, 0.0))
         cam_up = ray_tracer.Vec3(cam_up[0], cam_up[1], cam_up[2])
         
-        fov = bpy.data.cameras.get(bpy.context.scene.camera.name).angle_y * 180 / 3.141592653589
-        
-        py_cam = ray_tracer.Camera(cam_pos, cam_look, cam_up, fov, self.size_x/self.size_y,0.1,5)
+        # blender describes its cameras physically, so they map directly onto Camera.physical.
+        cam_data = cam.data
+        aspect = self.size_x / self.size_y
+
+        # Camera.physical always takes the sensor width across the image, so resolve blender's sensor fit.
+        if cam_data.sensor_fit == 'VERTICAL':
+            sensor_width = cam_data.sensor_height * aspect
+        elif cam_data.sensor_fit == 'AUTO' and aspect < 1.0:
+            sensor_width = cam_data.sensor_width * aspect
+        else:
+            sensor_width = cam_data.sensor_width
+
+        py_cam = ray_tracer.Camera.physical(
+            cam_pos,
+            cam_look,
+            cam_up,
+            sensor_width,
+            cam_data.lens,
+            aspect,
+            cam_data.dof.aperture_fstop,
+            cam_data.dof.focus_distance,
+            cam_data.rust_shutter_speed,
+        )
+        if cam_data.rust_use_exposure:
+            py_cam = py_cam.with_exposure_settings(
+                cam_data.dof.aperture_fstop,
+                cam_data.rust_shutter_speed,
+                cam_data.rust_iso,
+            )
+        if not cam_data.dof.use_dof:
+            py_cam = py_cam.pinhole()
+
+        # blender's shift is a fraction of the larger side of the image.
+        larger = max(self.size_x, self.size_y)
+        py_cam = py_cam.with_lens_shift(
+            cam_data.shift_x * larger / self.size_x,
+            cam_data.shift_y * larger / self.size_y,
+        )
         #py_cam = ray_tracer.Camera(cam_pos, cam_look, cam_up, 10, self.size_x/self.size_y,0.1,5)
-        rect = ray_tracer.py_render(self.size_x, self.size_y, scene.cycles.samples, py_cam, tris)
+        rect = ray_tracer.py_render(self.size_x, self.size_y, scene.cycles.samples, py_cam, objects)
         
         #pixel_count = self.size_x * self.size_y
         #rect = [color] * pixel_count
@@ -235,6 +270,18 @@ def get_panels():
 def register():
     # Register the RenderEngine
     bpy.utils.register_class(CustomRenderEngine)
+
+    # blender cameras have no shutter speed or ISO of their own, so we add them for the exposure.
+    # the exposure is only used when asked for, as most scenes aren't lit in physical units.
+    bpy.types.Camera.rust_use_exposure = bpy.props.BoolProperty(
+        name="Physical Exposure", description="Scale the image by the f-stop, shutter speed and ISO", default=False
+    )
+    bpy.types.Camera.rust_shutter_speed = bpy.props.FloatProperty(
+        name="Shutter Speed", description="Shutter speed in seconds", default=1.0 / 60.0, min=0.0001
+    )
+    bpy.types.Camera.rust_iso = bpy.props.FloatProperty(
+        name="ISO", description="Sensor sensitivity", default=100.0, min=1.0
+    )
     #bpy.utils.register_class(HelloWorldPanel)
     
     for panel in get_panels():
@@ -243,6 +290,9 @@ def register():
 
 def unregister():
     bpy.utils.unregister_class(CustomRenderEngine)
+    del bpy.types.Camera.rust_use_exposure
+    del bpy.types.Camera.rust_shutter_speed
+    del bpy.types.Camera.rust_iso
     #bpy.utils.unregister_classs(HelloWorldPanel)
 
     for panel in get_panels():
@@ -257,7 +307,9 @@ if __name__ == "__main__":
 def get_triangles(depsgraph):
         scene = depsgraph.scene
 
-        tris = []
+        # each object is sent as its world matrix, row by row, and its triangles in object space,
+        # so its location, rotation and scale are all applied by the renderer.
+        objects = []
         
         for obj in bpy.data.objects:
             if obj.type == 'MESH' and obj.hide_render == False:
@@ -266,18 +318,25 @@ def get_triangles(depsgraph):
                 eval_mesh = eval_obj.to_mesh()
         
                 eval_mesh.calc_loop_triangles()
+                eval_mesh.calc_normals_split()
                 print(eval_mesh)
         
+                tris = []
                 for t in eval_mesh.loop_triangles:
                     tri = []
                     for vert_index in t.vertices:
-                        v = eval_mesh.vertices[vert_index].co + obj.location
+                        v = eval_mesh.vertices[vert_index].co
                         tri.append(ray_tracer.Vec3(v[0], v[1], v[2]))
+                    # split normals are the face normal on flat shaded faces, so they work for both.
+                    for n in t.split_normals:
+                        tri.append(ray_tracer.Vec3(n[0], n[1], n[2]))
                     tris.append(tri)
 
-        #tris.append([ray_tracer.Vec3(-2,-2,-2), ray_tracer.Vec3(-2,-2,1), ray_tracer.Vec3(-2,1,-2)])
-        print("Length of tris: " + repr(len(tris)))
-        return tris
+                matrix = [list(row) for row in eval_obj.matrix_world]
+                objects.append((matrix, tris))
+
+        print("Length of tris: " + repr(sum(len(tris) for _, tris in objects)))
+        return objects
 
 
 class HelloWorldPanel(bpy.types.Panel):
@@ -300,4 +359,11 @@ class HelloWorldPanel(bpy.types.Panel):
         row.label(text="Sampling")
         row.prop(rscene, "samples", text="Samples")
 
+        if context.scene.camera is not None:
+            cam_data = context.scene.camera.data
+            row.label(text="Exposure")
+            row.prop(cam_data, "rust_shutter_speed")
+            row.prop(cam_data, "rust_use_exposure")
+            row.prop(cam_data, "rust_iso")
+
 bpy.utils.register_class(HelloWorldPanel)
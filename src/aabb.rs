@@ -21,7 +21,8 @@ impl AABB {
     }
 
     #[inline]
-    pub fn hit(&self, r: &Ray, tmin: f32, tmax: f32) -> bool {
+    pub fn hit(&self, r: &Ray, mut tmin: f32, mut tmax: f32) -> bool {
+        // the ray has to be inside all three slabs at once, so the range narrows with each axis.
         for i in 0..3 {
             let inv_d = r.inv_direction().get(i);
             let mut t0 = (self.min.get(i) - r.origin().get(i)) * inv_d;
@@ -30,8 +31,8 @@ impl AABB {
             if r.dir_sign()[i] {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let tmin = if t0 > tmin { t0 } else { tmin };
-            let tmax = if t1 < tmax { t1 } else { tmax };
+            tmin = if t0 > tmin { t0 } else { tmin };
+            tmax = if t1 < tmax { t1 } else { tmax };
 
             if tmax <= tmin {
                 return false;
@@ -55,6 +56,24 @@ impl AABB {
         )
     }
 
+    /// The box where both boxes overlap, or None if they don't.
+    pub fn overlap(box0: AABB, box1: AABB) -> Option<AABB> {
+        let min = Vec3::new(
+            box0.min().x().max(box1.min().x()),
+            box0.min().y().max(box1.min().y()),
+            box0.min().z().max(box1.min().z()),
+        );
+        let max = Vec3::new(
+            box0.max().x().min(box1.max().x()),
+            box0.max().y().min(box1.max().y()),
+            box0.max().z().min(box1.max().z()),
+        );
+        if min.x() > max.x() || min.y() > max.y() || min.z() > max.z() {
+            return None;
+        }
+        Some(AABB::new(min, max))
+    }
+
     pub fn longest_axis(&self) -> usize {
         let x_span = self.max().x() - self.min().x();
         let y_span = self.max().y() - self.min().y();
@@ -90,6 +109,10 @@ mod tests {
         let ray = Ray::new(Vec3::new(2.0, 1.0, 1.0), Vec3::new(-1.0, 0.0, 0.0));
 
         assert!(aabb.hit(&ray, 0.0001, 1000.0));
+
+        // inside the x and y slabs over different ranges of t, so it misses the box.
+        let ray = Ray::new(Vec3::new(2.0, 7.0, 1.0), Vec3::new(-1.0, -0.2, 0.0));
+        assert!(!aabb.hit(&ray, 0.0001, 1000.0));
     }
 }
 
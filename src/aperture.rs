@@ -0,0 +1,165 @@
+use super::util::*;
+use super::vec3::*;
+
+use std::path::Path;
+use std::sync::Arc;
+
+/// The shape of the lens opening, which is what gives out of focus highlights their shape.
+/// Every shape is sampled inside the unit disk, and scaled by the camera's lens radius.
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circular,
+    /// A regular polygon with its corners on the unit circle, as formed by straight aperture blades.
+    /// rotation is in degrees.
+    Polygonal {
+        blades: u32,
+        rotation: f32,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+impl Aperture {
+    /// Returns a random point on the aperture, in the xy plane.
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            Aperture::Circular => random_in_unit_disk(),
+            Aperture::Polygonal { blades, rotation } => random_in_polygon(*blades, *rotation),
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+/// An image defined aperture. Brighter pixels let more light through, so they are sampled more often.
+/// The image is stretched over the square [-1, 1] x [-1, 1], so anything outside the inscribed circle
+/// is still sampled; images are expected to be black there, as a real lens would be.
+#[derive(Debug)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    cdf: Vec<f32>, // running total of the pixel weights, normalized so the last entry is 1.
+}
+
+impl ApertureMask {
+    /// weights are given row by row, starting from the top of the image.
+    pub fn new(width: usize, height: usize, weights: &[f32]) -> ApertureMask {
+        assert!(
+            weights.len() == width * height,
+            "Aperture mask has {} weights, but should have {}",
+            weights.len(),
+            width * height
+        );
+
+        let mut total = 0.0;
+        let mut cdf: Vec<_> = weights
+            .iter()
+            .map(|w| {
+                total += w.max(0.0);
+                total
+            })
+            .collect();
+
+        assert!(total > 0.0, "Aperture mask lets no light through!");
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+
+        ApertureMask { width, height, cdf }
+    }
+
+    pub fn from_png(path: &Path) -> Result<ApertureMask, png::DecodingError> {
+        let (width, height, weights) = load_luminance_png(path)?;
+        Ok(ApertureMask::new(width, height, &weights))
+    }
+
+    pub fn sample(&self) -> Vec3 {
+        let r: f32 = rand::random();
+        let idx = match self.cdf.binary_search_by(|c| c.partial_cmp(&r).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i.min(self.cdf.len() - 1),
+        };
+
+        let x = (idx % self.width) as f32 + rand::random::<f32>();
+        let y = (idx / self.width) as f32 + rand::random::<f32>();
+
+        Vec3::new(
+            2.0 * x / self.width as f32 - 1.0,
+            1.0 - 2.0 * y / self.height as f32,
+            0.0,
+        )
+    }
+}
+
+fn random_in_polygon(blades: u32, rotation: f32) -> Vec3 {
+    // pick one of the triangles fanning out from the center, then a uniform point inside of it.
+    let wedge = 2.0 * std::f32::consts::PI / blades as f32;
+    let start = rotation.to_radians() + wedge * (rand::random::<f32>() * blades as f32).floor();
+
+    let corner1 = Vec3::new(start.cos(), start.sin(), 0.0);
+    let corner2 = Vec3::new((start + wedge).cos(), (start + wedge).sin(), 0.0);
+
+    let (mut r1, mut r2): (f32, f32) = (rand::random(), rand::random());
+    if r1 + r2 > 1.0 {
+        r1 = 1.0 - r1;
+        r2 = 1.0 - r2;
+    }
+    r1 * corner1 + r2 * corner2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32::consts::PI;
+
+    #[test]
+    fn polygon_samples_fill_the_polygon() {
+        // a hexagon with a corner at 90 degrees, so a flat edge along the bottom and top.
+        let aperture = Aperture::Polygonal {
+            blades: 6,
+            rotation: 90.0,
+        };
+        let inner_radius = (PI / 6.0).cos();
+        let mut wedges = [0; 6];
+        for _ in 0..6000 {
+            let p = aperture.sample();
+            assert_eq!(p.z(), 0.0);
+            for edge in 0..6 {
+                // the middle of each edge is between two corners, 60 degrees apart.
+                let angle = (120.0 + 60.0 * edge as f32).to_radians();
+                let outward = Vec3::new(angle.cos(), angle.sin(), 0.0);
+                assert!(p.dot(outward) <= inner_radius + 1e-5, "{:?} is outside", p);
+            }
+            let angle = p.y().atan2(p.x()) - PI / 2.0;
+            wedges[(angle.rem_euclid(2.0 * PI) / (PI / 3.0)) as usize % 6] += 1;
+        }
+        for &count in wedges.iter() {
+            assert!(
+                count > 800 && count < 1200,
+                "wedges got {:?} samples",
+                wedges
+            );
+        }
+    }
+
+    #[test]
+    fn masks_are_sampled_by_brightness() {
+        // only the top left and bottom right pixels let light through, the latter 3 times as much.
+        let mask = ApertureMask::new(2, 2, &[1.0, 0.0, 0.0, 3.0]);
+        let mut top_left = 0;
+        for _ in 0..4000 {
+            let p = mask.sample();
+            assert!(p.x().abs() <= 1.0 && p.y().abs() <= 1.0, "{:?}", p);
+            if p.x() < 0.0 {
+                assert!(p.y() >= 0.0, "{:?} is in a black pixel", p);
+                top_left += 1;
+            } else {
+                assert!(p.y() <= 0.0, "{:?} is in a black pixel", p);
+            }
+        }
+        assert!(
+            top_left > 850 && top_left < 1150,
+            "{} in the top left",
+            top_left
+        );
+    }
+}
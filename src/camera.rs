@@ -1,12 +1,23 @@
+use super::aperture::*;
 use super::ray::*;
 use super::vec3::*;
 
-use super::util::*;
-
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
+//implemented by anything that can turn a point on the screen into a ray into the scene.
+pub trait CameraModel: Sync + Send + std::fmt::Debug {
+    //x and y go from 0 to 1 across the screen. Returns None if the ray was blocked inside the camera.
+    fn generate_ray(&self, x: f32, y: f32) -> Option<Ray>;
+
+    //by default, leave the radiance as it is.
+    fn exposure(&self) -> f32 {
+        1.0
+    }
+}
+
 #[pyclass]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Camera {
     origin: Vec3,
     lower_left_corner: Vec3,
@@ -16,6 +27,12 @@ pub struct Camera {
     v: Vec3,
     w: Vec3,
     lens_radius: f32,
+    exposure: f32, // linear scale applied to the radiance before the output transform.
+    aperture: Aperture,
+    anamorphic_squeeze: f32,
+    cat_eye: f32,
+    time0: f32, // the shutter is open from time0 to time1.
+    time1: f32,
 }
 #[pymethods]
 impl Camera {
@@ -34,6 +51,165 @@ impl Camera {
         let half_height = (theta / 2.0).tan();
         let half_width = aspect * half_height;
 
+        Camera::from_half_extents(
+            lookfrom,
+            lookat,
+            vup,
+            half_width,
+            half_height,
+            aperture / 2.0,
+            focus_dist,
+            1.0,
+        )
+    }
+
+    /// Builds a camera the way a photographer (or Blender) describes one.
+    /// sensor_width and focal_length are in millimetres, with the sensor width spanning the
+    /// horizontal extent of the image. focus_dist is in scene units, which are taken to be metres.
+    /// shutter_speed is in seconds. The shutter opens at time 0, so anything animated blurs over
+    /// the first shutter_speed seconds. The exposure is left at 1, as scenes are rarely lit in
+    /// physical units; use with_exposure_settings to have the f-stop, shutter speed and ISO set it.
+    #[staticmethod]
+    #[allow(clippy::too_many_arguments)]
+    pub fn physical(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        sensor_width: f32,
+        focal_length: f32,
+        aspect: f32,
+        f_stop: f32,
+        focus_dist: f32,
+        shutter_speed: f32,
+    ) -> Camera {
+        let half_width = sensor_width / (2.0 * focal_length);
+        let half_height = half_width / aspect;
+
+        // the entrance pupil is focal_length / f_stop across, converted from millimetres to metres.
+        let lens_radius = focal_length / (2.0 * f_stop) * 0.001;
+
+        Camera::from_half_extents(
+            lookfrom,
+            lookat,
+            vup,
+            half_width,
+            half_height,
+            lens_radius,
+            focus_dist,
+            1.0,
+        )
+        .with_shutter(0.0, shutter_speed)
+    }
+
+    /// Returns a copy of this camera with its exposure set by the given f-stop, shutter speed (in
+    /// seconds) and ISO, for scenes lit in physical units. They must all be above 0.
+    pub fn with_exposure_settings(
+        &self,
+        f_stop: f32,
+        shutter_speed: f32,
+        iso: f32,
+    ) -> PyResult<Camera> {
+        let exposure =
+            Camera::exposure_from_settings(f_stop, shutter_speed, iso).ok_or_else(|| {
+                PyValueError::new_err(format!(
+                    "The f-stop, shutter speed and ISO must all be above 0, not {}, {} and {}!",
+                    f_stop, shutter_speed, iso
+                ))
+            })?;
+        Ok(Camera {
+            exposure,
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of this camera with depth of field disabled, keeping its exposure.
+    /// Used for Blender cameras that have an f-stop set but depth of field turned off.
+    pub fn pinhole(&self) -> Camera {
+        Camera {
+            lens_radius: 0.0,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this camera with an aperture made of at least 3 straight blades.
+    /// rotation is in degrees.
+    pub fn with_polygonal_aperture(&self, blades: u32, rotation: f32) -> PyResult<Camera> {
+        if blades < 3 {
+            return Err(PyValueError::new_err(format!(
+                "An aperture needs at least 3 blades, not {}!",
+                blades
+            )));
+        }
+        Ok(self.with_aperture(Aperture::Polygonal { blades, rotation }))
+    }
+
+    /// Returns a copy of this camera with an anamorphic lens, which squeezes the image horizontally
+    /// by the given factor. This stretches out of focus highlights vertically once desqueezed.
+    /// The squeeze must be above 0.
+    pub fn with_anamorphic_squeeze(&self, squeeze: f32) -> PyResult<Camera> {
+        if !(squeeze > 0.0 && squeeze.is_finite()) {
+            return Err(PyValueError::new_err(format!(
+                "The anamorphic squeeze must be above 0, not {}!",
+                squeeze
+            )));
+        }
+        Ok(Camera {
+            anamorphic_squeeze: squeeze,
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of this camera which sends out rays at random times between open and close,
+    /// blurring anything that moves in that time.
+    pub fn with_shutter(&self, open: f32, close: f32) -> Camera {
+        Camera {
+            time0: open,
+            time1: close,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this camera with cat's-eye vignetting, where the lens barrel clips the
+    /// aperture towards the edges of the frame. strength goes from 0 (none) to 1 (a lot).
+    pub fn with_cat_eye(&self, strength: f32) -> Camera {
+        Camera {
+            cat_eye: strength.clamp(0.0, 1.0),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this camera with the image shifted across the sensor, without turning the camera.
+    /// x and y are fractions of the image width and height.
+    pub fn with_lens_shift(&self, x: f32, y: f32) -> Camera {
+        Camera {
+            lower_left_corner: self.lower_left_corner + x * self.horizontal + y * self.vertical,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of this camera moved right and up in its own image plane, still facing the same way.
+    pub fn with_offset(&self, right: f32, up: f32) -> Camera {
+        let offset = right * self.u + up * self.v;
+        Camera {
+            origin: self.origin + offset,
+            lower_left_corner: self.lower_left_corner + offset,
+            ..self.clone()
+        }
+    }
+}
+
+impl Camera {
+    #[allow(clippy::too_many_arguments)]
+    fn from_half_extents(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        half_width: f32,
+        half_height: f32,
+        lens_radius: f32,
+        focus_dist: f32,
+        exposure: f32,
+    ) -> Camera {
         let w = (lookfrom - lookat).unit_vector();
         let u = vup.cross(w).unit_vector();
         let v = w.cross(u);
@@ -50,18 +226,208 @@ impl Camera {
             u,
             v,
             w,
-            lens_radius: (aperture / 2.0),
+            lens_radius,
+            exposure,
+            aperture: Aperture::Circular,
+            anamorphic_squeeze: 1.0,
+            cat_eye: 0.0,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
-}
 
-impl Camera {
-    pub fn get_ray(&self, x: f32, y: f32) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    pub fn with_aperture(&self, aperture: Aperture) -> Camera {
+        if let Aperture::Polygonal { blades, .. } = aperture {
+            assert!(blades >= 3, "An aperture needs at least 3 blades!");
+        }
+        Camera {
+            aperture,
+            ..self.clone()
+        }
+    }
+
+    /// Exposure scale for the given settings, using the saturation based sensitivity from
+    /// ISO 12232 (EV100 = log2(N^2 / t * 100 / S), exposure = 1 / (1.2 * 2^EV100)).
+    /// Returns None unless they are all above 0.
+    pub fn exposure_from_settings(f_stop: f32, shutter_speed: f32, iso: f32) -> Option<f32> {
+        if !(f_stop > 0.0 && shutter_speed > 0.0 && iso > 0.0) {
+            return None;
+        }
+        let ev100 = (f_stop * f_stop / shutter_speed * 100.0 / iso).log2();
+        Some(1.0 / (1.2 * 2.0f32.powf(ev100)))
+    }
+
+    /// Width of the area the camera sees, at the given distance in front of it.
+    pub fn frame_width_at(&self, distance: f32) -> f32 {
+        let focus_dist = (self.origin - self.lower_left_corner).dot(self.w);
+        self.horizontal.length() * distance / focus_dist
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Roughly how many pixels across something of the given size at p covers, on an image that is
+    /// image_width pixels wide. Returns None if p is behind the camera.
+    pub fn projected_size(&self, p: Vec3, size: f32, image_width: usize) -> Option<f32> {
+        let distance = (self.origin - p).dot(self.w);
+        if distance <= 0.0 {
+            return None;
+        }
+        Some(size / self.frame_width_at(distance) * image_width as f32)
+    }
+
+    /// Returns None if the lens barrel blocks the ray, as it does for more of them towards the edges
+    /// of the frame when there is cat's-eye vignetting.
+    pub fn get_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let rd = self.lens_radius * self.sample_lens(x, y)?;
         let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(
+        let time = self.time0 + rand::random::<f32>() * (self.time1 - self.time0);
+        Some(Ray::new_at_time(
             self.origin + offset,
             self.lower_left_corner + x * self.horizontal + y * self.vertical - self.origin - offset,
-        )
+            time,
+        ))
+    }
+
+    // picks a point on the lens for a ray through (x, y) on the screen, or None if it's blocked.
+    fn sample_lens(&self, x: f32, y: f32) -> Option<Vec3> {
+        if self.lens_radius == 0.0 {
+            return Some(Vec3::new(0.0, 0.0, 0.0));
+        }
+
+        // the lens barrel is modelled as a second unit circle, which moves away from the aperture
+        // as the pixel moves away from the center of the frame. Only their overlap lets light through,
+        // so points outside of it are blocked rather than sampled again, which both keeps the shape
+        // of the bokeh and darkens the edges of the frame by how much of the aperture is lost.
+        let barrel = self.cat_eye * Vec3::new(2.0 * x - 1.0, 2.0 * y - 1.0, 0.0);
+
+        let p = self.aperture.sample();
+        if (p + barrel).squared_length() > 1.0 {
+            return None;
+        }
+
+        Some(Vec3::new(p.x() / self.anamorphic_squeeze, p.y(), 0.0))
+    }
+}
+
+impl CameraModel for Camera {
+    fn generate_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        self.get_ray(x, y)
+    }
+
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposure_follows_ev100() {
+        // f/1 for 1 second at ISO 100 is EV 0, and each stop down, halving of the shutter time or
+        // halving of the ISO takes one more.
+        let ev0 = 1.0 / 1.2;
+        let settings = [
+            (1.0, 1.0, 100.0, ev0),
+            (2.0, 1.0, 100.0, ev0 / 4.0),
+            (1.0, 0.5, 100.0, ev0 / 2.0),
+            (1.0, 1.0, 50.0, ev0 / 2.0),
+            (16.0, 1.0 / 125.0, 100.0, ev0 / (256.0 * 125.0)),
+        ];
+        for &(f_stop, shutter_speed, iso, expected) in settings.iter() {
+            let exposure = Camera::exposure_from_settings(f_stop, shutter_speed, iso).unwrap();
+            assert!(
+                (exposure - expected).abs() < 1e-4 * expected,
+                "f/{}, {}s, ISO {} gave {}, not {}",
+                f_stop,
+                shutter_speed,
+                iso,
+                exposure,
+                expected
+            );
+        }
+
+        assert_eq!(Camera::exposure_from_settings(0.0, 1.0, 100.0), None);
+        assert_eq!(Camera::exposure_from_settings(2.8, 0.0, 100.0), None);
+        assert_eq!(Camera::exposure_from_settings(2.8, 1.0, -100.0), None);
+        assert_eq!(Camera::exposure_from_settings(2.8, 1.0, f32::NAN), None);
+    }
+
+    #[test]
+    fn physical_cameras_match_their_settings() {
+        // a 36mm wide sensor behind a 50mm lens at f/2, focused 4m away, with a 1/50s shutter.
+        let cam = Camera::physical(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            36.0,
+            50.0,
+            1.5,
+            2.0,
+            4.0,
+            0.02,
+        );
+
+        assert!((cam.frame_width_at(10.0) - 7.2).abs() < 1e-4);
+        assert!((cam.lens_radius - 0.0125).abs() < 1e-6);
+        assert!((cam.vertical.length() - cam.horizontal.length() / 1.5).abs() < 1e-4);
+        assert_eq!(cam.exposure(), 1.0);
+
+        for _ in 0..100 {
+            let r = cam.get_ray(0.5, 0.5).unwrap();
+            assert!(
+                r.time() >= 0.0 && r.time() <= 0.02,
+                "ray at time {}",
+                r.time()
+            );
+            // every ray through the middle of the frame meets at the focus distance.
+            let t = -4.0 / r.direction().z();
+            let p = r.point_at_parameter(t);
+            assert!(
+                p.x().abs() < 1e-4 && p.y().abs() < 1e-4,
+                "focused at {:?}",
+                p
+            );
+        }
+
+        let exposed = cam.with_exposure_settings(2.0, 0.02, 100.0).unwrap();
+        assert!(
+            (exposed.exposure() - Camera::exposure_from_settings(2.0, 0.02, 100.0).unwrap()).abs()
+                < 1e-9
+        );
+        assert!(cam.with_exposure_settings(2.0, 0.0, 100.0).is_err());
+    }
+
+    #[test]
+    fn anamorphic_lenses_squeeze_the_aperture() {
+        let cam = Camera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            2.0,
+            5.0,
+        );
+        let squeezed = cam.with_anamorphic_squeeze(2.0).unwrap();
+
+        // the lens is 1 across, and half as wide once squeezed.
+        let (mut widest, mut tallest) = (0.0f32, 0.0f32);
+        for _ in 0..2000 {
+            let origin = squeezed.get_ray(0.5, 0.5).unwrap().origin();
+            widest = widest.max(origin.x().abs());
+            tallest = tallest.max(origin.y().abs());
+        }
+        assert!(widest <= 0.5 && widest > 0.45, "lens is {} wide", widest);
+        assert!(tallest <= 1.0 && tallest > 0.9, "lens is {} tall", tallest);
+
+        assert!(cam.with_anamorphic_squeeze(0.0).is_err());
+        assert!(cam.with_anamorphic_squeeze(-2.0).is_err());
+        assert!(cam.with_anamorphic_squeeze(f32::NAN).is_err());
+        assert!(cam.with_polygonal_aperture(2, 0.0).is_err());
+        assert!(cam.with_polygonal_aperture(6, 0.0).is_ok());
     }
 }
@@ -3,276 +3,1094 @@ use crate::ray::*;
 
 use super::*;
 
-use std::cmp::Ordering;
+use std::sync::Arc;
 
+// the most objects that get put in a single leaf of the BVH.
+const MAX_LEAF_OBJECTS: usize = 4;
+// the number of buckets the surface area heuristic sorts objects into along each axis.
+const SAH_BINS: usize = 16;
+// the cost of stepping into a node, relative to the cost of hitting an object.
+const TRAVERSAL_COST: f32 = 0.125;
+const INTERSECTION_COST: f32 = 1.0;
+// nodes with more objects than this build their two children on different threads.
+const PARALLEL_BUILD_OBJECTS: usize = 1024;
+// spatial splits are only tried where the children of the best object split overlap by more than
+// this much of the area of the whole tree.
+const SPATIAL_SPLIT_ALPHA: f32 = 1e-5;
+// the most nodes waiting on the stack while walking the tree, which is one for each level above
+// the node being visited.
+const STACK_SIZE: usize = 64;
+// nodes deeper than this are split at the median, as the surface area heuristic can split off a few
+// objects at a time. Halving even 2^32 objects gets them into leaves well before the stack runs out.
+const MAX_SAH_DEPTH: usize = 30;
+
+/// A bounding volume hierarchy over a list of objects. The tree is flattened into a single array of
+/// nodes in depth first order, and the objects are reordered so each leaf covers a run of them,
+/// so hitting it walks the array with a stack instead of chasing boxed children. Trees with spatial
+/// splits can have an object in more than one leaf, so the objects are shared.
 #[derive(Debug)]
 pub struct BvhNode {
-    left: Option<Box<dyn Hitable>>,
-    right: Option<Box<dyn Hitable>>,
+    objects: Vec<Arc<dyn Hitable>>,
+    tree: LinearBvh,
+}
+
+// the flattened tree on its own, whose leaves point into a list of primitives kept by its owner.
+// BvhNode keeps objects in that list, and Mesh keeps triangles.
+#[derive(Debug)]
+pub(crate) struct LinearBvh {
+    nodes: Vec<LinearNode>,
+}
+
+// a node of the flattened tree, kept to 32 bytes so two fit in a cache line. The left child of an
+// interior node is the node right after it, so only the right child needs to be pointed to.
+#[derive(Debug, Clone, Copy)]
+struct LinearNode {
     aa_box: AABB,
+    offset: u32, // the first object for a leaf, the right child for an interior node.
+    count: u16,  // the number of objects in a leaf, 0 for an interior node.
+    axis: u8, // the axis an interior node was split along, so rays can visit the near child first.
+}
+
+/// Counts of the work done while hitting a BVH, for comparing trees and ways of walking them.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraversalStats {
+    pub nodes_visited: usize,
+    pub objects_tested: usize,
+}
+
+// how a node's objects get shared out between its two children.
+#[derive(Debug, Clone, Copy)]
+enum Split {
+    // half on each side of the median along the longest axis.
+    Median,
+    // at the bin boundary, along any axis, with the lowest surface area heuristic cost.
+    Sah,
 }
 
 impl BvhNode {
-    pub fn new(mut objects: Vec<Box<dyn Hitable>>) -> BvhNode {
-        // find the enclosing bounding box.
-        let mut main_box = objects[0].bounding_box();
-        //we account for the first box in main_box, so we can skip in when iterating though the list.
-        for obj in objects.iter().skip(1) {
-            main_box = match main_box {
-                Some(temp) => match obj.bounding_box() {
-                    Some(aa_box) => Some(AABB::surrounding_box(temp, aa_box)),
-                    None => Some(temp),
-                },
-                None => match obj.bounding_box() {
-                    Some(aa_box) => Some(aa_box),
-                    None => None,
-                },
-            }
+    /// Builds the tree by splitting the objects in half along their longest axis. This is quick to
+    /// build, but the tree is usually slower to hit than one from new_sah.
+    pub fn new(objects: Vec<Box<dyn Hitable>>) -> BvhNode {
+        BvhNode::build(objects, Split::Median)
+    }
+
+    /// Builds the tree with the surface area heuristic. The objects are sorted into bins along all
+    /// three axes, and each node is split at whichever bin boundary is cheapest to hit, or made a
+    /// leaf when it is small and hitting its objects directly is cheaper. Big subtrees are built
+    /// in parallel.
+    pub fn new_sah(objects: Vec<Box<dyn Hitable>>) -> BvhNode {
+        BvhNode::build(objects, Split::Sah)
+    }
+
+    /// Builds the tree like new_sah, but also tries splitting space itself where the children of
+    /// a node would overlap a lot, as they do around long thin triangles. Objects crossing the
+    /// plane go in both children, clipped to each side, and max_duplication limits how many
+    /// extra references that makes, as a fraction of the number of objects. The tree is built on
+    /// one thread.
+    pub fn new_sbvh(objects: Vec<Box<dyn Hitable>>, max_duplication: f32) -> BvhNode {
+        assert!(!objects.is_empty(), "Can't make a BVH with no objects!");
+        assert!(
+            max_duplication >= 0.0,
+            "A BVH's max duplication must be at least 0, not {}!",
+            max_duplication
+        );
+        let objects: Vec<Arc<dyn Hitable>> = objects.into_iter().map(Arc::from).collect();
+        let refs: Vec<_> = objects
+            .iter()
+            .enumerate()
+            .map(|(object, obj)| Reference {
+                object,
+                aa_box: obj
+                    .bounding_box()
+                    .expect("Encountered an object with no bounding box!"),
+            })
+            .collect();
+        let root_area = references_box(&refs).area();
+
+        let mut budget = (max_duplication * objects.len() as f32) as usize;
+        let mut order = Vec::with_capacity(objects.len() + budget);
+        let mut nodes = Vec::with_capacity(2 * objects.len() / MAX_LEAF_OBJECTS + 1);
+        build_spatial_node(
+            &objects,
+            refs,
+            root_area,
+            0,
+            &mut budget,
+            &mut order,
+            &mut nodes,
+        );
+
+        let objects = order.iter().map(|&i| Arc::clone(&objects[i])).collect();
+        BvhNode {
+            objects,
+            tree: LinearBvh { nodes },
         }
-        let axis = main_box.unwrap().longest_axis();
+    }
 
-        let comparator = match axis {
-            0 => compare_box_by_x_axis,
-            1 => compare_box_by_y_axis,
-            2 => compare_box_by_z_axis,
-            _ => {
-                panic!("Got a number out of range! Recieved: {}", axis);
-            }
-        };
+    fn build(objects: Vec<Box<dyn Hitable>>, split: Split) -> BvhNode {
+        assert!(!objects.is_empty(), "Can't make a BVH with no objects!");
+        let boxes: Vec<_> = objects
+            .iter()
+            .map(|obj| {
+                obj.bounding_box()
+                    .expect("Encountered an object with no bounding box!")
+            })
+            .collect();
 
-        objects.sort_by(|left, right| comparator(left, right));
+        // sort objects by their place in the tree, and then put them in that order.
+        let (tree, order) = LinearBvh::build(&boxes, split);
+        let objects: Vec<Arc<dyn Hitable>> = objects.into_iter().map(Arc::from).collect();
+        let objects = order.iter().map(|&i| Arc::clone(&objects[i])).collect();
+        BvhNode { objects, tree }
+    }
 
-        let (left, right) = match objects.len() {
-            0 => (None, None),
-            1 => (Some(objects.remove(0)), None),
-            2 => (Some(objects.remove(0)), Some(objects.remove(0))),
-            _ => {
-                let mid = objects.len() / 2;
+    /// The expected cost of hitting the tree with a ray that hits its bounding box, by the surface
+    /// area heuristic, in units of hitting one object. Lower is better.
+    pub fn sah_cost(&self) -> f32 {
+        self.tree.sah_cost()
+    }
+}
 
-                let left_objs: Vec<_> = objects.drain(0..mid).collect();
-                let right_objs = objects;
+impl LinearBvh {
+    // builds the tree over the boxes with the surface area heuristic, returning it along with the
+    // order the primitives need to be put in so that each leaf covers a run of them.
+    pub(crate) fn new_sah(boxes: &[AABB]) -> (LinearBvh, Vec<usize>) {
+        LinearBvh::build(boxes, Split::Sah)
+    }
 
-                let left: Box<dyn Hitable> = Box::new(BvhNode::new(left_objs));
-                let right: Box<dyn Hitable> = Box::new(BvhNode::new(right_objs));
-                (Some(left), Some(right))
-            }
-        };
+    fn build(boxes: &[AABB], split: Split) -> (LinearBvh, Vec<usize>) {
+        let centroids: Vec<_> = boxes.iter().map(|b| 0.5 * (b.min() + b.max())).collect();
+        let mut order: Vec<usize> = (0..boxes.len()).collect();
+        let mut nodes = Vec::with_capacity(2 * boxes.len() / MAX_LEAF_OBJECTS + 1);
+        build_node(boxes, &centroids, &mut order, 0, 0, split, &mut nodes);
+        (LinearBvh { nodes }, order)
+    }
 
-        BvhNode {
-            left,
-            right,
-            aa_box: main_box.unwrap(),
+    pub(crate) fn bounding_box(&self) -> AABB {
+        self.nodes[0].aa_box
+    }
+
+    fn sah_cost(&self) -> f32 {
+        let root_area = self.nodes[0].aa_box.area();
+        self.nodes
+            .iter()
+            .map(|node| {
+                let cost = if node.count > 0 {
+                    INTERSECTION_COST * node.count as f32
+                } else {
+                    TRAVERSAL_COST
+                };
+                cost * node.aa_box.area() / root_area
+            })
+            .sum()
+    }
+}
+
+// builds the node for the objects in order, which start at offset in the final ordering and
+// depth levels below the root, pushing it and all its children on to nodes.
+fn build_node(
+    boxes: &[AABB],
+    centroids: &[Vec3],
+    order: &mut [usize],
+    offset: usize,
+    depth: usize,
+    split: Split,
+    nodes: &mut Vec<LinearNode>,
+) {
+    let aa_box = order.iter().skip(1).fold(boxes[order[0]], |acc, &i| {
+        AABB::surrounding_box(acc, boxes[i])
+    });
+    let leaf = LinearNode {
+        aa_box,
+        offset: offset as u32,
+        count: order.len() as u16,
+        axis: 0,
+    };
+
+    let split = if depth < MAX_SAH_DEPTH {
+        split
+    } else {
+        Split::Median
+    };
+    let chosen = match split {
+        Split::Median if order.len() <= MAX_LEAF_OBJECTS => None,
+        Split::Median => Some(median_split(centroids, order)),
+        Split::Sah if order.len() == 1 => None,
+        Split::Sah => sah_split(boxes, centroids, order, aa_box.area()),
+    };
+    let (mid, axis) = match chosen {
+        Some(chosen) => chosen,
+        None => {
+            nodes.push(leaf);
+            return;
         }
+    };
+
+    let idx = nodes.len();
+    nodes.push(LinearNode {
+        aa_box,
+        offset: 0,
+        count: 0,
+        axis: axis as u8,
+    });
+
+    let parallel = order.len() >= PARALLEL_BUILD_OBJECTS;
+    let (left, right) = order.split_at_mut(mid);
+    if !parallel {
+        build_node(boxes, centroids, left, offset, depth + 1, split, nodes);
+        nodes[idx].offset = nodes.len() as u32;
+        build_node(
+            boxes,
+            centroids,
+            right,
+            offset + mid,
+            depth + 1,
+            split,
+            nodes,
+        );
+        return;
+    }
+
+    // each side is built into its own list, and then moved into place after this node, which
+    // means pointing their right children past everything in front of them.
+    let build_side = |order: &mut [usize], offset: usize| {
+        let mut side = vec![];
+        build_node(boxes, centroids, order, offset, depth + 1, split, &mut side);
+        side
+    };
+    let (left_nodes, right_nodes) = rayon::join(
+        || build_side(left, offset),
+        || build_side(right, offset + mid),
+    );
+    append_nodes(nodes, left_nodes);
+    nodes[idx].offset = nodes.len() as u32;
+    append_nodes(nodes, right_nodes);
+}
+
+// moves a subtree that was built on its own to the end of nodes.
+fn append_nodes(nodes: &mut Vec<LinearNode>, subtree: Vec<LinearNode>) {
+    let start = nodes.len() as u32;
+    nodes.extend(subtree.into_iter().map(|node| match node.count {
+        0 => LinearNode {
+            offset: node.offset + start,
+            ..node
+        },
+        _ => node,
+    }));
+}
+
+// puts the objects in order with the lower half along the longest axis first, returning how many
+// are in that half and the axis.
+fn median_split(centroids: &[Vec3], order: &mut [usize]) -> (usize, usize) {
+    let axis = centroid_box(centroids, order).longest_axis();
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&l, &r| {
+        centroids[l]
+            .get(axis)
+            .partial_cmp(&centroids[r].get(axis))
+            .unwrap()
+    });
+    (mid, axis)
+}
+
+// finds the cheapest bin boundary to split at along any axis, and puts the objects in order with
+// the ones on the lower side of it first, returning how many there are and the axis. Returns None
+// when the objects are few enough, and the split costly enough, that they are better off in a leaf.
+fn sah_split(
+    boxes: &[AABB],
+    centroids: &[Vec3],
+    order: &mut [usize],
+    area: f32,
+) -> Option<(usize, usize)> {
+    let n = order.len();
+    let (cost, axis, split_bin) = match best_object_split(boxes, centroids, order, area) {
+        Some(best) => best,
+        // every centroid is in the same place, so there's nothing to tell them apart by.
+        None if n <= MAX_LEAF_OBJECTS => return None,
+        None => return Some((n / 2, 0)),
+    };
+    if n <= MAX_LEAF_OBJECTS && INTERSECTION_COST * n as f32 <= cost {
+        return None;
     }
+    Some((partition_by_bin(centroids, order, axis, split_bin), axis))
+}
 
-    pub fn new_sah(mut l: Vec<Box<dyn Hitable>>) -> BvhNode {
-        let n = l.len();
+// the cheapest (cost, axis, bin) to split the objects in order at, with the objects whose centroids
+// are in bins below it going to the left. Returns None if the centroids can't be split apart.
+fn best_object_split(
+    boxes: &[AABB],
+    centroids: &[Vec3],
+    order: &[usize],
+    area: f32,
+) -> Option<(f32, usize, usize)> {
+    let n = order.len();
+    let bounds = centroid_box(centroids, order);
 
-        let mut left_area = Vec::with_capacity(n);
-        let mut right_area = Vec::with_capacity(n);
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if bounds.max().get(axis) <= bounds.min().get(axis) {
+            continue;
+        }
+        let mut counts = [0usize; SAH_BINS];
+        let mut bin_boxes: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+        for &i in order.iter() {
+            let b = centroid_bin(&bounds, centroids[i], axis);
+            counts[b] += 1;
+            bin_boxes[b] = merge(bin_boxes[b], Some(boxes[i]));
+        }
 
-        // find the enclosing bounding box.
-        let mut main_box = l[0].bounding_box();
-        //we account for the first box in main_box, so we can skip in when iterating though the list.
-        for obj in l.iter().skip(1) {
-            main_box = match main_box {
-                Some(temp) => match obj.bounding_box() {
-                    Some(aa_box) => Some(AABB::surrounding_box(temp, aa_box)),
-                    None => Some(temp),
-                },
-                None => match obj.bounding_box() {
-                    Some(aa_box) => Some(aa_box),
-                    None => None,
-                },
-            }
+        // right_cost[b] is the count times the area of everything in bins b and up.
+        let mut right_cost = [0.0; SAH_BINS];
+        let mut right_box: Option<AABB> = None;
+        let mut right_count = 0;
+        for b in (1..SAH_BINS).rev() {
+            right_box = merge(right_box, bin_boxes[b]);
+            right_count += counts[b];
+            right_cost[b] = right_count as f32 * right_box.map_or(0.0, |aa_box| aa_box.area());
         }
 
-        //check to make sure we have enough items in the list.
-        match l.len() {
-            0 => {
-                return BvhNode {
-                    right: None,
-                    left: None,
-                    aa_box: main_box.unwrap(),
-                }
+        let mut left_box: Option<AABB> = None;
+        let mut left_count = 0;
+        for b in 1..SAH_BINS {
+            left_box = merge(left_box, bin_boxes[b - 1]);
+            left_count += counts[b - 1];
+            if left_count == 0 || left_count == n {
+                continue;
             }
-            1 => {
-                return BvhNode {
-                    right: Some(l.remove(0)),
-                    left: None,
-                    aa_box: main_box.unwrap(),
-                }
-            }
-            2 => {
-                return BvhNode {
-                    right: Some(l.remove(0)),
-                    left: Some(l.remove(0)),
-                    aa_box: main_box.unwrap(),
-                }
+            let left_cost = left_count as f32 * left_box.map_or(0.0, |aa_box| aa_box.area());
+            let cost = TRAVERSAL_COST + INTERSECTION_COST * (left_cost + right_cost[b]) / area;
+            if !matches!(best, Some((best_cost, _, _)) if best_cost <= cost) {
+                best = Some((cost, axis, b));
             }
-            _ => {}
         }
+    }
+    best
+}
 
-        assert!(l.len() > 2, "Something went wrong with the match!");
+// which of the bins spread over bounds a centroid falls in along the axis.
+fn centroid_bin(bounds: &AABB, centroid: Vec3, axis: usize) -> usize {
+    let extent = bounds.max().get(axis) - bounds.min().get(axis);
+    let b = (SAH_BINS as f32 * (centroid.get(axis) - bounds.min().get(axis)) / extent) as usize;
+    b.min(SAH_BINS - 1)
+}
 
-        let axis = main_box
-            .expect("can't have objects with no bounding box!")
-            .longest_axis();
+// puts the objects with centroids in bins below split_bin first, returning how many there are.
+fn partition_by_bin(
+    centroids: &[Vec3],
+    order: &mut [usize],
+    axis: usize,
+    split_bin: usize,
+) -> usize {
+    let bounds = centroid_box(centroids, order);
+    let mut mid = 0;
+    for j in 0..order.len() {
+        if centroid_bin(&bounds, centroids[order[j]], axis) < split_bin {
+            order.swap(mid, j);
+            mid += 1;
+        }
+    }
+    mid
+}
 
-        let comparator = match axis {
-            0 => compare_box_by_x_axis,
-            1 => compare_box_by_y_axis,
-            2 => compare_box_by_z_axis,
-            _ => {
-                panic!("Got a number out of range! Recieved: {}", axis);
+// a reference to an object while building a spatial split BVH, which only covers the part of the
+// object inside aa_box once the object has been split between nodes.
+#[derive(Debug, Clone, Copy)]
+struct Reference {
+    object: usize,
+    aa_box: AABB,
+}
+
+fn references_box(refs: &[Reference]) -> AABB {
+    refs.iter().skip(1).fold(refs[0].aa_box, |acc, r| {
+        AABB::surrounding_box(acc, r.aa_box)
+    })
+}
+
+// builds the node for the references, depth levels below the root, pushing it and all its children
+// on to nodes, and the objects of its leaves on to order. Objects split between nodes use up the budget.
+fn build_spatial_node(
+    objects: &[Arc<dyn Hitable>],
+    refs: Vec<Reference>,
+    root_area: f32,
+    depth: usize,
+    budget: &mut usize,
+    order: &mut Vec<usize>,
+    nodes: &mut Vec<LinearNode>,
+) {
+    let n = refs.len();
+    let aa_box = references_box(&refs);
+    let area = aa_box.area();
+    let boxes: Vec<_> = refs.iter().map(|r| r.aa_box).collect();
+    let centroids: Vec<_> = boxes.iter().map(|b| 0.5 * (b.min() + b.max())).collect();
+    let mut local: Vec<usize> = (0..n).collect();
+
+    let deep = depth >= MAX_SAH_DEPTH;
+    let object = if n > 1 && !deep {
+        best_object_split(&boxes, &centroids, &local, area)
+    } else {
+        None
+    };
+    // splitting space is only worth trying where the object split leaves its children overlapping.
+    let overlap = object.map_or(root_area, |(_, axis, split_bin)| {
+        let bounds = centroid_box(&centroids, &local);
+        let (mut left, mut right) = (None, None);
+        for (&aa_box, &centroid) in boxes.iter().zip(centroids.iter()) {
+            if centroid_bin(&bounds, centroid, axis) < split_bin {
+                left = merge(left, Some(aa_box));
+            } else {
+                right = merge(right, Some(aa_box));
             }
-        };
+        }
+        match (left, right) {
+            (Some(l), Some(r)) => AABB::overlap(l, r).map_or(0.0, |o| o.area()),
+            _ => 0.0,
+        }
+    });
+    let spatial = if n > 1 && !deep && *budget > 0 && overlap / root_area > SPATIAL_SPLIT_ALPHA {
+        best_spatial_split(objects, &refs, &aa_box, area, *budget)
+    } else {
+        None
+    };
 
-        l.sort_by(|left, right| comparator(left, right));
-        /*
-        for i in 0..n {
-            boxes.push(l[i].bounding_box());
-        } */
-        let boxes: Vec<_> = l
-            .iter()
-            .map(|i| {
-                i.bounding_box()
-                    .expect("items with no bounding box are not allowed!")
-            })
-            .collect();
+    let object_cost = object.map_or(f32::MAX, |(cost, _, _)| cost);
+    let spatial_cost = spatial.map_or(f32::MAX, |(cost, _, _)| cost);
+    let splittable = object.is_some() || spatial.is_some();
+    if n == 1
+        || (n <= MAX_LEAF_OBJECTS
+            && (!splittable || INTERSECTION_COST * n as f32 <= object_cost.min(spatial_cost)))
+    {
+        nodes.push(LinearNode {
+            aa_box,
+            offset: order.len() as u32,
+            count: n as u16,
+            axis: 0,
+        });
+        order.extend(refs.iter().map(|r| r.object));
+        return;
+    }
 
-        assert!(boxes.len() == l.len(), "Boxes is shorter than the input!");
+    let spatial_children = match spatial {
+        Some((_, axis, plane)) if spatial_cost < object_cost => {
+            spatial_partition(objects, &refs, axis, plane).map(|(left, right)| (left, right, axis))
+        }
+        _ => None,
+    };
+    let (left, right, axis) = match (spatial_children, object) {
+        (Some(children), _) => {
+            *budget -= children.0.len() + children.1.len() - n;
+            children
+        }
+        (None, Some((_, axis, split_bin))) => {
+            let mid = partition_by_bin(&centroids, &mut local, axis, split_bin);
+            let left = local[..mid].iter().map(|&i| refs[i]).collect();
+            let right = local[mid..].iter().map(|&i| refs[i]).collect();
+            (left, right, axis)
+        }
+        // too deep for the surface area heuristic, or nothing to tell the references apart by,
+        // so they're shared out in half.
+        (None, None) => {
+            let (mid, axis) = median_split(&centroids, &mut local);
+            let left = local[..mid].iter().map(|&i| refs[i]).collect();
+            let right = local[mid..].iter().map(|&i| refs[i]).collect();
+            (left, right, axis)
+        }
+    };
 
-        left_area.push(boxes[0].area());
-        let mut left_box = boxes[0];
+    let idx = nodes.len();
+    nodes.push(LinearNode {
+        aa_box,
+        offset: 0,
+        count: 0,
+        axis: axis as u8,
+    });
+    build_spatial_node(objects, left, root_area, depth + 1, budget, order, nodes);
+    nodes[idx].offset = nodes.len() as u32;
+    build_spatial_node(objects, right, root_area, depth + 1, budget, order, nodes);
+}
 
-        for i in 1..n - 1 {
-            left_box = AABB::surrounding_box(left_box, boxes[i]);
-            left_area.push(left_box.area());
+// the cheapest (cost, axis, plane) to split space at, out of the boundaries between bins spread
+// evenly over aa_box. References crossing a plane count towards both sides, clipped to each bin
+// they cross, and planes that would duplicate more of them than the budget allows are skipped.
+fn best_spatial_split(
+    objects: &[Arc<dyn Hitable>],
+    refs: &[Reference],
+    aa_box: &AABB,
+    area: f32,
+    budget: usize,
+) -> Option<(f32, usize, f32)> {
+    let n = refs.len();
+    let mut best: Option<(f32, usize, f32)> = None;
+    for axis in 0..3 {
+        let low = aa_box.min().get(axis);
+        let width = (aa_box.max().get(axis) - low) / SAH_BINS as f32;
+        if width <= 0.0 {
+            continue;
         }
-        assert!(
-            left_area.len() == n - 1,
-            "left area length was {}, but should have been {}",
-            left_area.len(),
-            n - 1
-        );
+        let bin = |x: f32| (((x - low) / width).max(0.0) as usize).min(SAH_BINS - 1);
 
-        right_area.push(boxes[n - 1].area());
-        let mut right_box = boxes[n - 1];
-        for i in (1..n - 1).rev() {
-            right_box = AABB::surrounding_box(right_box, boxes[i]);
-            right_area.insert(0, right_box.area());
+        // how many references start and end in each bin, and the box around their parts in it.
+        let mut entries = [0usize; SAH_BINS];
+        let mut exits = [0usize; SAH_BINS];
+        let mut bin_boxes: [Option<AABB>; SAH_BINS] = [None; SAH_BINS];
+        for r in refs.iter() {
+            let (first, last) = (bin(r.aa_box.min().get(axis)), bin(r.aa_box.max().get(axis)));
+            entries[first] += 1;
+            exits[last] += 1;
+            if first == last {
+                bin_boxes[first] = merge(bin_boxes[first], Some(r.aa_box));
+                continue;
+            }
+            for (b, bin_box) in bin_boxes.iter_mut().enumerate().take(last + 1).skip(first) {
+                let slab = slab(
+                    &r.aa_box,
+                    axis,
+                    low + b as f32 * width,
+                    low + (b + 1) as f32 * width,
+                );
+                *bin_box = merge(*bin_box, objects[r.object].clipped_box(&slab));
+            }
+        }
+
+        // right[b] is the count and area of everything in bins b and up.
+        let mut right = [(0, 0.0); SAH_BINS];
+        let mut right_box: Option<AABB> = None;
+        let mut right_count = 0;
+        for b in (1..SAH_BINS).rev() {
+            right_box = merge(right_box, bin_boxes[b]);
+            right_count += exits[b];
+            right[b] = (right_count, right_box.map_or(0.0, |aa_box| aa_box.area()));
         }
-        assert!(
-            left_area.len() == n - 1,
-            "right area length was {}, but should have been {}",
-            right_area.len(),
-            n - 1
-        );
 
-        let mut min_sah = f32::MAX;
-        let mut min_sah_idx = 0;
-        for i in 0..(n - 1) {
-            let sah = (i as f32) * left_area[i] + ((n - i - 1) as f32) * right_area[i];
-            if sah < min_sah {
-                min_sah_idx = i;
-                min_sah = sah;
+        let mut left_box: Option<AABB> = None;
+        let mut left_count = 0;
+        for b in 1..SAH_BINS {
+            left_box = merge(left_box, bin_boxes[b - 1]);
+            left_count += entries[b - 1];
+            let (right_count, right_area) = right[b];
+            // a side can keep every reference, as that still uses up some of the budget, so the
+            // splitting has to stop somewhere.
+            if left_count == 0 || right_count == 0 || left_count + right_count - n > budget {
+                continue;
+            }
+            let left_area = left_box.map_or(0.0, |aa_box| aa_box.area());
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (left_count as f32 * left_area + right_count as f32 * right_area)
+                    / area;
+            if !matches!(best, Some((best_cost, _, _)) if best_cost <= cost) {
+                best = Some((cost, axis, low + b as f32 * width));
             }
         }
-        let left: Box<dyn Hitable>;
-        let right: Box<dyn Hitable>;
+    }
+    best
+}
 
-        if min_sah_idx == 0 {
-            //left = l[0];
-            left = l.remove(0);
-        } else {
-            left = Box::new(BvhNode::new_sah(l.drain(0..min_sah_idx + 1).collect()));
+// shares the references out on either side of the plane, clipping the ones that cross it into a
+// reference on each side. Returns None if either side would be left empty.
+fn spatial_partition(
+    objects: &[Arc<dyn Hitable>],
+    refs: &[Reference],
+    axis: usize,
+    plane: f32,
+) -> Option<(Vec<Reference>, Vec<Reference>)> {
+    let (mut left, mut right) = (vec![], vec![]);
+    for r in refs.iter() {
+        if r.aa_box.max().get(axis) <= plane {
+            left.push(*r);
+            continue;
+        }
+        if r.aa_box.min().get(axis) >= plane {
+            right.push(*r);
+            continue;
+        }
+        let object = &objects[r.object];
+        let below = object.clipped_box(&slab(&r.aa_box, axis, f32::NEG_INFINITY, plane));
+        let above = object.clipped_box(&slab(&r.aa_box, axis, plane, f32::INFINITY));
+        match (below, above) {
+            (Some(below), Some(above)) => {
+                left.push(Reference {
+                    aa_box: below,
+                    ..*r
+                });
+                right.push(Reference {
+                    aa_box: above,
+                    ..*r
+                });
+            }
+            (None, Some(_)) => right.push(*r),
+            _ => left.push(*r),
         }
+    }
+    if left.is_empty() || right.is_empty() {
+        return None;
+    }
+    Some((left, right))
+}
 
-        if min_sah_idx == n - 2 {
-            //right = l[min_sah_idx + 1 - left.len()];
-            right = l.pop().unwrap(); // removes the last element.
-        } else {
-            right = Box::new(BvhNode::new_sah(l));
+// the part of aa_box between low and high along the axis.
+fn slab(aa_box: &AABB, axis: usize, low: f32, high: f32) -> AABB {
+    let set = |v: Vec3, x: f32| match axis {
+        0 => Vec3::new(x, v.y(), v.z()),
+        1 => Vec3::new(v.x(), x, v.z()),
+        _ => Vec3::new(v.x(), v.y(), x),
+    };
+    AABB::new(
+        set(aa_box.min(), low.max(aa_box.min().get(axis))),
+        set(aa_box.max(), high.min(aa_box.max().get(axis))),
+    )
+}
+
+fn centroid_box(centroids: &[Vec3], order: &[usize]) -> AABB {
+    let first = centroids[order[0]];
+    order
+        .iter()
+        .skip(1)
+        .fold(AABB::new(first, first), |acc, &i| {
+            AABB::surrounding_box(acc, AABB::new(centroids[i], centroids[i]))
+        })
+}
+
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(AABB::surrounding_box(a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+impl BvhNode {
+    /// Hits the tree like hit does, while counting the nodes and objects it looks at.
+    pub fn hit_with_stats(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        stats: &mut TraversalStats,
+    ) -> Option<HitRecord> {
+        self.traverse(r, t_min, t_max, true, stats)
+    }
+
+    #[inline]
+    fn traverse(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        ordered: bool,
+        stats: &mut TraversalStats,
+    ) -> Option<HitRecord> {
+        self.tree
+            .traverse(r, t_min, t_max, ordered, stats, |i, closest_so_far| {
+                self.objects[i].hit(r, t_min, closest_so_far)
+            })
+    }
+}
+
+impl LinearBvh {
+    // walks the tree with a stack, calling hit_primitive with each primitive in the leaves the ray
+    // reaches and the closest hit so far. When ordered, the child on the side the ray comes from is
+    // visited first, so a hit in it can cull the other child before its primitives are tested.
+    #[inline]
+    pub(crate) fn traverse<'a>(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        ordered: bool,
+        stats: &mut TraversalStats,
+        mut hit_primitive: impl FnMut(usize, f32) -> Option<HitRecord<'a>>,
+    ) -> Option<HitRecord<'a>> {
+        let mut closest_so_far = t_max;
+        let mut rec = None;
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let idx = stack[stack_len];
+            let node = &self.nodes[idx];
+            stats.nodes_visited += 1;
+
+            // this also skips nodes that start beyond the closest hit so far.
+            if !node.aa_box.hit(r, t_min, closest_so_far) {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.offset as usize;
+                for i in start..start + node.count as usize {
+                    stats.objects_tested += 1;
+                    if let Some(record) = hit_primitive(i, closest_so_far) {
+                        closest_so_far = record.t;
+                        rec = Some(record);
+                    }
+                }
+            } else {
+                // the far child goes on the stack first, so the near one is popped first.
+                let (near, far) = if ordered && r.dir_sign()[node.axis as usize] {
+                    (node.offset as usize, idx + 1)
+                } else {
+                    (idx + 1, node.offset as usize)
+                };
+                stack[stack_len] = far;
+                stack[stack_len + 1] = near;
+                stack_len += 2;
+            }
         }
+        rec
+    }
 
-        let left = Some(left);
-        let right = Some(right);
+    // whether occludes_primitive is true for any primitive in the leaves the ray reaches.
+    #[inline]
+    pub(crate) fn occluded(
+        &self,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+        mut occludes_primitive: impl FnMut(usize) -> bool,
+    ) -> bool {
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_len = 1;
 
-        BvhNode {
-            left,
-            right,
-            aa_box: main_box.unwrap(),
+        while stack_len > 0 {
+            stack_len -= 1;
+            let idx = stack[stack_len];
+            let node = &self.nodes[idx];
+
+            if !node.aa_box.hit(r, t_min, t_max) {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.offset as usize;
+                if (start..start + node.count as usize).any(&mut occludes_primitive) {
+                    return true;
+                }
+            } else {
+                // any hit will do, but the near child is still the likeliest place to find one.
+                let (near, far) = if r.dir_sign()[node.axis as usize] {
+                    (node.offset as usize, idx + 1)
+                } else {
+                    (idx + 1, node.offset as usize)
+                };
+                stack[stack_len] = far;
+                stack[stack_len + 1] = near;
+                stack_len += 2;
+            }
         }
+        false
     }
 }
 
 impl Hitable for BvhNode {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        if !self.aa_box.hit(r, t_min, t_max) {
-            return None;
+        self.traverse(r, t_min, t_max, true, &mut TraversalStats::default())
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.tree.occluded(r, t_min, t_max, |i| {
+            self.objects[i].occluded(r, t_min, t_max)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.tree.bounding_box())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::hitable_list::*;
+    use crate::hit::sphere::*;
+    use crate::hit::triangle::*;
+    use crate::material::lambertian::*;
+
+    fn spheres(count: usize) -> Vec<Box<dyn Hitable>> {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        (0..count)
+            .map(|i| {
+                let i = i as f32;
+                let center = Vec3::new(
+                    10.0 * (i * 0.37).sin(),
+                    10.0 * (i * 0.71).cos(),
+                    10.0 * (i * 1.13).sin(),
+                );
+                let sphere: Box<dyn Hitable> = Box::new(Sphere::new(
+                    center,
+                    0.3 + 0.2 * (i * 0.53).cos(),
+                    Arc::clone(&mat),
+                ));
+                sphere
+            })
+            .collect()
+    }
+
+    // the spheres of the random scene, scattered the same way every time.
+    pub(super) fn sphere_grid() -> Vec<Box<dyn Hitable>> {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let mut objects: Vec<Box<dyn Hitable>> = vec![Box::new(Sphere::new(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::clone(&mat),
+        ))];
+        for a in -11..11 {
+            for b in -11..11 {
+                let jitter = ((a * 31 + b * 17) as f32).sin();
+                let center = Vec3::new(a as f32 + 0.45 * (1.0 + jitter), 0.2, b as f32 + 0.45);
+                objects.push(Box::new(Sphere::new(center, 0.2, Arc::clone(&mat))));
+            }
+        }
+        for &x in [-4.0, 0.0, 4.0].iter() {
+            objects.push(Box::new(Sphere::new(
+                Vec3::new(x, 1.0, 0.0),
+                1.0,
+                Arc::clone(&mat),
+            )));
         }
+        objects
+    }
 
-        let hit_left = match &self.left {
-            Some(obj) => obj.hit(r, t_min, t_max),
-            None => None,
-        };
-        let hit_right = match &self.right {
-            Some(obj) => obj.hit(r, t_min, t_max),
-            None => None,
-        };
+    // rays from the random scene's camera, fanned out over the spheres.
+    pub(super) fn camera_rays() -> Vec<Ray> {
+        let origin = Vec3::new(13.0, 2.0, 3.0);
+        (0..256)
+            .map(|i| {
+                let target = Vec3::new((i % 16) as f32 - 8.0, 0.0, (i / 16) as f32 - 8.0);
+                Ray::new(origin, target - origin)
+            })
+            .collect()
+    }
 
-        match (hit_left, hit_right) {
-            (None, None) => None,
-            (None, Some(h)) => Some(h),
-            (Some(h), None) => Some(h),
-            (Some(left), Some(right)) => {
-                if left.t < right.t {
-                    Some(left)
-                } else {
-                    Some(right)
-                }
+    #[test]
+    fn bvh_nodes_are_32_bytes() {
+        assert_eq!(std::mem::size_of::<LinearNode>(), 32);
+    }
+
+    #[test]
+    fn bvh_matches_list() {
+        // enough spheres that the top of the trees get built in parallel.
+        let count = 2 * PARALLEL_BUILD_OBJECTS;
+        let list = HitableList::new(spheres(count));
+        let bvhs = [
+            BvhNode::new(spheres(count)),
+            BvhNode::new_sah(spheres(count)),
+        ];
+
+        let mut hits = 0;
+        for i in 0..200 {
+            let i = i as f32;
+            let origin = Vec3::new(20.0 * (i * 0.29).cos(), 15.0, 20.0 * (i * 0.61).sin());
+            let target = Vec3::new(8.0 * (i * 1.7).sin(), 8.0 * (i * 0.43).cos(), 0.0);
+            let r = Ray::new(origin, target - origin);
+
+            let expected = list.hit(&r, 0.001, f32::MAX).map(|rec| rec.t);
+            hits += expected.is_some() as usize;
+            for bvh in bvhs.iter() {
+                assert_eq!(bvh.hit(&r, 0.001, f32::MAX).map(|rec| rec.t), expected);
             }
         }
+        assert!(hits > 20, "Only {} of the rays hit anything!", hits);
     }
 
-    fn bounding_box(&self) -> Option<AABB> {
-        Some(self.aa_box)
+    // tight clumps of small spheres far apart from each other, with one big sphere over them all.
+    fn clusters(count: usize) -> Vec<Box<dyn Hitable>> {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let mut objects: Vec<Box<dyn Hitable>> = (0..count)
+            .map(|i| {
+                let cluster = (i % 8) as f32;
+                let f = i as f32;
+                let center = Vec3::new(
+                    30.0 * (cluster * 0.9).cos() + 2.0 * (f * 0.37).sin(),
+                    30.0 * (cluster * 2.3).sin() + 2.0 * (f * 0.71).cos(),
+                    2.0 * (f * 1.13).sin(),
+                );
+                Box::new(Sphere::new(center, 0.1, Arc::clone(&mat))) as Box<dyn Hitable>
+            })
+            .collect();
+        objects.push(Box::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -40.0),
+            35.0,
+            Arc::clone(&mat),
+        )));
+        objects
     }
-}
 
-fn get_boxes(left: &Box<dyn Hitable>, right: &Box<dyn Hitable>) -> (AABB, AABB) {
-    let l_box = left.bounding_box();
-    let r_box = right.bounding_box();
+    #[test]
+    fn sah_is_cheaper_than_median_on_clusters() {
+        let median = BvhNode::new(clusters(400));
+        let sah = BvhNode::new_sah(clusters(400));
+        assert!(
+            sah.sah_cost() < median.sah_cost(),
+            "The SAH tree costs {}, but the median tree costs {}!",
+            sah.sah_cost(),
+            median.sah_cost()
+        );
+    }
 
-    let l_box = match l_box {
-        Some(bounds) => bounds,
-        None => panic!("Encountered an object with no bounding box!"),
-    };
+    #[test]
+    fn parallel_builds_match_list() {
+        // enough objects that several levels of the tree get built on other threads.
+        let count = 4 * PARALLEL_BUILD_OBJECTS;
+        let list = HitableList::new(clusters(count));
+        let bvh = BvhNode::new_sah(clusters(count));
+        assert_eq!(bvh.objects.len(), count + 1);
 
-    let r_box = match r_box {
-        Some(bounds) => bounds,
-        None => panic!("Encountered an object with no bounding box!"),
-    };
-    (l_box, r_box)
-}
+        let mut hits = 0;
+        for i in 0..400 {
+            let i = i as f32;
+            // from just outside a cluster, so the rays don't graze the small spheres from far away.
+            let cluster = (i * 0.5).floor() % 8.0;
+            let target = Vec3::new(
+                30.0 * (cluster * 0.9).cos() + 2.0 * (i * 1.7).sin(),
+                30.0 * (cluster * 2.3).sin() + 2.0 * (i * 0.43).cos(),
+                0.0,
+            );
+            let origin = target + Vec3::new(3.0 * (i * 0.29).cos(), 3.0 * (i * 0.61).sin(), 4.0);
+            let r = Ray::new(origin, target - origin);
 
-fn compare_box_by_x_axis(left: &Box<dyn Hitable>, right: &Box<dyn Hitable>) -> Ordering {
-    let (l_box, r_box) = get_boxes(left, right);
+            let expected = list.hit(&r, 0.001, f32::MAX).map(|rec| rec.t);
+            hits += expected.is_some() as usize;
+            assert_eq!(bvh.hit(&r, 0.001, f32::MAX).map(|rec| rec.t), expected);
+            assert_eq!(bvh.occluded(&r, 0.001, f32::MAX), expected.is_some());
+        }
+        assert!(hits > 40, "Only {} of the rays hit anything!", hits);
+    }
 
-    l_box.min().x().partial_cmp(&r_box.min().x()).unwrap()
-}
+    // how many nodes deep the tree under node idx goes.
+    fn depth(bvh: &LinearBvh, idx: usize) -> usize {
+        let node = &bvh.nodes[idx];
+        if node.count > 0 {
+            1
+        } else {
+            1 + depth(bvh, idx + 1).max(depth(bvh, node.offset as usize))
+        }
+    }
 
-fn compare_box_by_y_axis(left: &Box<dyn Hitable>, right: &Box<dyn Hitable>) -> Ordering {
-    let (l_box, r_box) = get_boxes(left, right);
+    #[test]
+    fn deep_trees_fit_the_traversal_stack() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        // a pile of spheres in the same place, which can't be told apart, and a row of them getting
+        // further apart, which the surface area heuristic splits off one at a time.
+        let objects = || -> Vec<Box<dyn Hitable>> {
+            let pile =
+                (0..200).map(|_| Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, Arc::clone(&mat)));
+            let row = (0..120).map(|i| {
+                let x = 2.0 * 2.0f32.powi(i);
+                Sphere::new(Vec3::new(x, 0.0, 0.0), 0.1 * x, Arc::clone(&mat))
+            });
+            pile.chain(row)
+                .map(|sphere| Box::new(sphere) as Box<dyn Hitable>)
+                .collect()
+        };
+        let list = HitableList::new(objects());
+        let bvhs = [
+            BvhNode::new(objects()),
+            BvhNode::new_sah(objects()),
+            BvhNode::new_sbvh(objects(), 0.5),
+        ];
 
-    l_box.min().z().partial_cmp(&r_box.min().z()).unwrap()
-}
+        for bvh in bvhs.iter() {
+            assert!(
+                depth(&bvh.tree, 0) < STACK_SIZE,
+                "The tree is {} deep!",
+                depth(&bvh.tree, 0)
+            );
+        }
+        for i in 0..120 {
+            // along the row, starting at each of its spheres in turn.
+            let x = 2.0 * 2.0f32.powi(i) * 1.2;
+            let r = Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::new(1.0, 0.01, 0.0));
+            let back = Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.01));
+            for r in [r, back].iter() {
+                let expected = list.hit(r, 0.001, f32::MAX).map(|rec| rec.t);
+                for bvh in bvhs.iter() {
+                    assert_eq!(bvh.hit(r, 0.001, f32::MAX).map(|rec| rec.t), expected);
+                    assert_eq!(bvh.occluded(r, 0.001, f32::MAX), expected.is_some());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn occluded_agrees_with_hit() {
+        let count = 2 * PARALLEL_BUILD_OBJECTS;
+        let list = HitableList::new(spheres(count));
+        let bvh = BvhNode::new_sah(spheres(count));
+
+        for i in 0..200 {
+            let i = i as f32;
+            let origin = Vec3::new(20.0 * (i * 0.29).cos(), 15.0, 20.0 * (i * 0.61).sin());
+            let target = Vec3::new(8.0 * (i * 1.7).sin(), 8.0 * (i * 0.43).cos(), 0.0);
+            let r = Ray::new(origin, target - origin);
 
-fn compare_box_by_z_axis(left: &Box<dyn Hitable>, right: &Box<dyn Hitable>) -> Ordering {
-    let (l_box, r_box) = get_boxes(left, right);
+            // only part of the way along, so some rays stop short of everything.
+            let t_max = 0.5 + 0.5 * (i * 0.37).sin().abs();
+            let expected = list.hit(&r, 0.001, t_max).is_some();
+            assert_eq!(list.occluded(&r, 0.001, t_max), expected);
+            assert_eq!(bvh.occluded(&r, 0.001, t_max), expected);
+        }
+    }
+
+    #[test]
+    fn sbvh_splits_long_thin_triangles() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        // diagonal slivers scattered over the scene, whose boxes are mostly empty and overlap a lot.
+        let triangles = || -> Vec<Box<dyn Hitable>> {
+            (0..200)
+                .map(|i| {
+                    let i = i as f32;
+                    let start = Vec3::new(
+                        10.0 * (i * 0.37).sin(),
+                        10.0 * (i * 0.71).cos(),
+                        2.0 * (i * 1.13).sin(),
+                    );
+                    let triangle: Box<dyn Hitable> = Box::new(Triangle::new(
+                        start,
+                        start + Vec3::new(6.0, 6.0, 0.0),
+                        start + Vec3::new(6.0, 6.2, 0.1),
+                        Arc::clone(&mat),
+                    ));
+                    triangle
+                })
+                .collect()
+        };
+        let list = HitableList::new(triangles());
+        let sah = BvhNode::new_sah(triangles());
+        let sbvh = BvhNode::new_sbvh(triangles(), 0.5);
+        let unsplit = BvhNode::new_sbvh(triangles(), 0.0);
 
-    l_box.min().z().partial_cmp(&r_box.min().z()).unwrap()
+        assert!(sbvh.objects.len() > 200 && sbvh.objects.len() <= 300);
+        assert_eq!(unsplit.objects.len(), 200);
+        assert!(sbvh.sah_cost() < sah.sah_cost());
+
+        for i in 0..200 {
+            let i = i as f32;
+            let origin = Vec3::new(15.0 * (i * 0.29).cos(), 15.0 * (i * 0.83).sin(), 20.0);
+            let target = Vec3::new(10.0 * (i * 1.7).sin(), 10.0 * (i * 0.43).cos(), 0.0);
+            let r = Ray::new(origin, target - origin);
+
+            let expected = list.hit(&r, 0.001, f32::MAX).map(|rec| rec.t);
+            assert_eq!(sbvh.hit(&r, 0.001, f32::MAX).map(|rec| rec.t), expected);
+            assert_eq!(unsplit.hit(&r, 0.001, f32::MAX).map(|rec| rec.t), expected);
+            assert_eq!(sbvh.occluded(&r, 0.001, f32::MAX), expected.is_some());
+        }
+    }
+
+    #[test]
+    fn ordered_traversal_tests_fewer_objects() {
+        let bvh = BvhNode::new_sah(sphere_grid());
+        let mut ordered = TraversalStats::default();
+        let mut unordered = TraversalStats::default();
+        for r in camera_rays().iter() {
+            let near = bvh.traverse(r, 0.001, f32::MAX, true, &mut ordered);
+            let far = bvh.traverse(r, 0.001, f32::MAX, false, &mut unordered);
+            assert_eq!(near.map(|rec| rec.t), far.map(|rec| rec.t));
+        }
+        assert!(ordered.objects_tested < unordered.objects_tested);
+        assert!(ordered.nodes_visited < unordered.nodes_visited);
+    }
 }
 
 #[cfg(test)]
@@ -284,6 +1102,7 @@ mod benches {
     extern crate test;
     use test::Bencher;
 
+    use super::tests::*;
     use crate::hit::triangle::*;
     use crate::material::dielectric::*;
 
@@ -296,14 +1115,6 @@ mod benches {
             Arc::new(Dielectric::new(1.35)),
         );
 
-        let triangle2 = Triangle::new(
-            Vec3::new(1.0, 3.1, 4.2),
-            Vec3::new(1.0, 4.1, 5.2),
-            Vec3::new(3.0, 5.1, 6.2),
-            Arc::new(Dielectric::new(1.35)),
-        );
-
-        //let bvh = BvhNode::new_sah(vec![Box::new(triangle1), Box::new(triangle2)]);
         let bvh = BvhNode::new_sah(vec![Box::new(triangle1)]);
 
         let ray = Ray::new(Vec3::new(0.0, 1.1, 2.2), Vec3::new(2.2, 0.0, 1.1));
@@ -312,4 +1123,31 @@ mod benches {
 
         b.iter(|| test::black_box(bvh.hit(r, 0.0, 1000.0)));
     }
+
+    #[bench]
+    fn hit_sphere_grid_median(b: &mut Bencher) {
+        let bvh = BvhNode::new(sphere_grid());
+        let rays = camera_rays();
+        b.iter(|| {
+            for r in rays.iter() {
+                test::black_box(bvh.hit(test::black_box(r), 0.001, f32::MAX));
+            }
+        });
+    }
+
+    #[bench]
+    fn hit_sphere_grid_sah(b: &mut Bencher) {
+        let bvh = BvhNode::new_sah(sphere_grid());
+        let rays = camera_rays();
+        b.iter(|| {
+            for r in rays.iter() {
+                test::black_box(bvh.hit(test::black_box(r), 0.001, f32::MAX));
+            }
+        });
+    }
+
+    #[bench]
+    fn build_sphere_grid_sah(b: &mut Bencher) {
+        b.iter(|| test::black_box(BvhNode::new_sah(sphere_grid())));
+    }
 }
@@ -0,0 +1,194 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::disk::*;
+use super::*;
+
+/// A cone with a round base, narrowing to a point at its apex.
+#[derive(Debug)]
+pub struct Cone {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    capped: bool,
+    material: Arc<dyn Material>,
+}
+
+impl Cone {
+    /// A closed cone, with a disk over the base.
+    pub fn new(base: Vec3, apex: Vec3, radius: f32, material: Arc<dyn Material>) -> Cone {
+        Cone {
+            frame: Frame::new(base, apex - base),
+            height: (apex - base).length(),
+            radius,
+            capped: true,
+            material,
+        }
+    }
+
+    /// A cone that is open at the base.
+    pub fn open(base: Vec3, apex: Vec3, radius: f32, material: Arc<dyn Material>) -> Cone {
+        Cone {
+            capped: false,
+            ..Cone::new(base, apex, radius, material)
+        }
+    }
+
+    fn side_area(&self) -> f32 {
+        let slant = (self.radius * self.radius + self.height * self.height).sqrt();
+        std::f32::consts::PI * self.radius * slant
+    }
+
+    fn cap_area(&self) -> f32 {
+        std::f32::consts::PI * self.radius * self.radius
+    }
+
+    // the outward normal on the side, at a local point.
+    fn side_normal(&self, p: Vec3) -> Vec3 {
+        let k = self.radius / self.height;
+        Vec3::new(p.x(), p.y(), k * k * (self.height - p.z())).unit_vector()
+    }
+
+    // the nearest hit on the side, from a local origin and direction, and the local point there.
+    fn hit_side(&self, o: Vec3, d: Vec3, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+        // x^2 + y^2 = (k (h - z))^2, cut off between the base and the apex.
+        let k2 = (self.radius / self.height) * (self.radius / self.height);
+        let oz = self.height - o.z();
+        let a = d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z();
+        let b = o.x() * d.x() + o.y() * d.y() + k2 * oz * d.z();
+        let c = o.x() * o.x() + o.y() * o.y() - k2 * oz * oz;
+        // the roots in order, and how many of them there are.
+        let (roots, count) = if a != 0.0 {
+            let discriminant = b * b - a * c;
+            if discriminant > 0.0 {
+                let t0 = (-b - discriminant.sqrt()) / a;
+                let t1 = (-b + discriminant.sqrt()) / a;
+                ([t0.min(t1), t0.max(t1)], 2)
+            } else {
+                ([0.0; 2], 0)
+            }
+        } else if b != 0.0 {
+            // the ray is parallel to the slope, so only crosses the cone once.
+            ([-c / (2.0 * b), 0.0], 1)
+        } else {
+            ([0.0; 2], 0)
+        };
+
+        roots[..count]
+            .iter()
+            .map(|&t| (t, o + t * d))
+            .find(|&(t, p)| t > t_min && t < t_max && p.z() >= 0.0 && p.z() < self.height)
+    }
+}
+
+impl Hitable for Cone {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let o = self.frame.to_local(r.origin());
+        let d = self.frame.to_local_vector(r.direction());
+        let pi = std::f32::consts::PI;
+
+        // (t, local outward normal, u, v, local dpdu, local dpdv) of the closest hit so far.
+        let mut closest: Option<(f32, Vec3, f32, f32, Vec3, Vec3)> = None;
+        let mut closest_so_far = t_max;
+
+        if let Some((t, p)) = self.hit_side(o, d, t_min, closest_so_far) {
+            let phi = p.y().atan2(p.x());
+            let phi = if phi < 0.0 { phi + 2.0 * pi } else { phi };
+            let v = p.z() / self.height;
+            closest = Some((
+                t,
+                self.side_normal(p),
+                phi / (2.0 * pi),
+                v,
+                2.0 * pi * Vec3::new(-p.y(), p.x(), 0.0),
+                Vec3::new(-p.x() / (1.0 - v), -p.y() / (1.0 - v), self.height),
+            ));
+            closest_so_far = t;
+        }
+
+        if self.capped {
+            if let Some((t, p)) = hit_local_disk(o, d, 0.0, self.radius, 0.0, t_min, closest_so_far)
+            {
+                let (u, v, dpdu, dpdv) = local_disk_surface(p, self.radius, 0.0);
+                closest = Some((t, Vec3::new(0.0, 0.0, -1.0), u, v, dpdu, dpdv));
+            }
+        }
+
+        let (t, normal, u, v, dpdu, dpdv) = closest?;
+        Some(
+            HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                r,
+                self.frame.to_world_vector(normal),
+                &*self.material,
+            )
+            .with_surface(
+                u,
+                v,
+                self.frame.to_world_vector(dpdu),
+                self.frame.to_world_vector(dpdv),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let o = self.frame.to_local(r.origin());
+        let d = self.frame.to_local_vector(r.direction());
+        self.hit_side(o, d, t_min, t_max).is_some()
+            || (self.capped && hit_local_disk(o, d, 0.0, self.radius, 0.0, t_min, t_max).is_some())
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.frame.aa_box(
+            Vec3::new(-self.radius, -self.radius, 0.0),
+            Vec3::new(self.radius, self.radius, self.height),
+        ))
+    }
+}
+
+impl Sampleable for Cone {
+    fn area(&self) -> f32 {
+        if self.capped {
+            self.side_area() + self.cap_area()
+        } else {
+            self.side_area()
+        }
+    }
+
+    fn sample(&self) -> (Vec3, Vec3) {
+        if rand::random::<f32>() * self.area() < self.side_area() {
+            // the circles get smaller towards the apex, so fewer points go there.
+            let v = 1.0 - (1.0 - rand::random::<f32>()).sqrt();
+            let phi = 2.0 * std::f32::consts::PI * rand::random::<f32>();
+            let radius = self.radius * (1.0 - v);
+            let p = Vec3::new(radius * phi.cos(), radius * phi.sin(), v * self.height);
+            (
+                self.frame.to_world(p),
+                self.frame.to_world_vector(self.side_normal(p)),
+            )
+        } else {
+            let p = sample_local_disk(self.radius, 0.0);
+            (self.frame.to_world(p), -self.frame.w())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn cone_occluded_agrees_with_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let base = Vec3::new(0.0, -1.0, 0.0);
+        let top = Vec3::new(0.0, 1.0, 0.0);
+        check_occluded(&Cone::new(base, top, 0.8, Arc::clone(&mat)));
+        check_occluded(&Cone::open(base, top, 0.8, mat));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// The left solid, with the right one cut out of it.
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// A boolean combination of two solids. Both need to be closed, with their outward normals
+/// pointing out, so each hit along a ray either enters or leaves them. Csg nodes are closed
+/// themselves, so they can be combined further.
+#[derive(Debug)]
+pub struct Csg {
+    left: Box<dyn Hitable>,
+    right: Box<dyn Hitable>,
+    operation: CsgOperation,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg {
+            left,
+            right,
+            operation,
+        }
+    }
+
+    pub fn union(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Hitable>, right: Box<dyn Hitable>) -> Csg {
+        Csg::new(CsgOperation::Difference, left, right)
+    }
+
+    pub fn operation(&self) -> CsgOperation {
+        self.operation
+    }
+}
+
+// how far past a hit to look for the next one, relative to the size of t.
+const CROSSING_EPSILON: f32 = 1e-4;
+
+// walks through every place a ray crosses the surface of a solid, one hit at a time.
+struct Crossings<'a, 'r> {
+    solid: &'a dyn Hitable,
+    r: &'r Ray,
+    t_max: f32,
+    next: Option<HitRecord<'a>>,
+    // whether the ray is inside the solid just before the next crossing.
+    inside: bool,
+}
+
+impl<'a, 'r> Crossings<'a, 'r> {
+    fn new(solid: &'a dyn Hitable, r: &'r Ray, t_min: f32, t_max: f32) -> Crossings<'a, 'r> {
+        let next = solid.hit(r, t_min, t_max);
+        Crossings {
+            solid,
+            r,
+            t_max,
+            next,
+            // leaving through the first surface means the ray started inside.
+            inside: matches!(next, Some(rec) if !rec.front_face),
+        }
+    }
+
+    fn advance(&mut self) {
+        if let Some(rec) = self.next {
+            self.inside = rec.front_face;
+            let t = rec.t + CROSSING_EPSILON * rec.t.abs().max(1.0);
+            self.next = self.solid.hit(self.r, t, self.t_max);
+        }
+    }
+}
+
+impl Hitable for Csg {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut left = Crossings::new(&*self.left, r, t_min, t_max);
+        let mut right = Crossings::new(&*self.right, r, t_min, t_max);
+        let mut inside = self.operation.contains(left.inside, right.inside);
+
+        // merge the crossings of both solids in order, until one of them changes whether the ray
+        // is inside the combination.
+        loop {
+            let rec = match (left.next, right.next) {
+                (None, None) => return None,
+                (Some(l), Some(r)) if l.t <= r.t => {
+                    left.advance();
+                    l
+                }
+                (Some(l), None) => {
+                    left.advance();
+                    l
+                }
+                (_, Some(r)) => {
+                    right.advance();
+                    r
+                }
+            };
+
+            let now_inside = self.operation.contains(left.inside, right.inside);
+            if now_inside != inside {
+                // the normal already faces against the ray, so only which side is the front needs
+                // fixing. It changes on the surfaces cut out by a difference, which face inwards.
+                let mut rec = rec;
+                rec.front_face = now_inside;
+                return Some(rec);
+            }
+            inside = now_inside;
+        }
+    }
+
+    // the crossings have to be merged to know if any of them count, but rays that miss the box
+    // around the combination, which can be much smaller than its parts, can't hit it at all.
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        if let Some(aa_box) = self.bounding_box() {
+            if !aa_box.hit(r, t_min, t_max) {
+                return false;
+            }
+        }
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let left = self.left.bounding_box();
+        let right = self.right.bounding_box();
+        match self.operation {
+            CsgOperation::Union => match (left, right) {
+                (Some(l), Some(r)) => Some(AABB::surrounding_box(l, r)),
+                _ => None,
+            },
+            CsgOperation::Intersection => match (left, right) {
+                (Some(l), Some(r)) => Some(AABB::new(
+                    Vec3::new(
+                        l.min().x().max(r.min().x()),
+                        l.min().y().max(r.min().y()),
+                        l.min().z().max(r.min().z()),
+                    ),
+                    Vec3::new(
+                        l.max().x().min(r.max().x()),
+                        l.max().y().min(r.max().y()),
+                        l.max().z().min(r.max().z()),
+                    ),
+                )),
+                (Some(b), None) | (None, Some(b)) => Some(b),
+                (None, None) => None,
+            },
+            CsgOperation::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::hit::instancing::*;
+    use crate::hit::sphere::*;
+    use crate::material::lambertian::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn csg_picks_the_right_surfaces() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let sphere = || -> Box<dyn Hitable> {
+            Box::new(Sphere::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                1.0,
+                Arc::clone(&grey),
+            ))
+        };
+        // a box covering the half of the sphere with x > 0.
+        let half = || -> Box<dyn Hitable> {
+            Box::new(RectangularBox::new(
+                Vec3::new(0.0, -2.0, -2.0),
+                Vec3::new(2.0, 2.0, 2.0),
+                Arc::clone(&grey),
+            ))
+        };
+        let along_x = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let hit = |csg: &Csg| {
+            let rec = csg.hit(&along_x, 0.001, f32::MAX).unwrap();
+            (rec.t, rec.front_face, rec.normal.x())
+        };
+
+        // entering the sphere, as it sticks out of the box on that side.
+        let (t, front_face, normal) = hit(&Csg::union(sphere(), half()));
+        assert!((t - 4.0).abs() < 1e-4 && front_face && normal < 0.0);
+
+        // the flat face of the box, which cuts the sphere in half.
+        let (t, front_face, normal) = hit(&Csg::intersection(sphere(), half()));
+        assert!((t - 5.0).abs() < 1e-4 && front_face && normal < 0.0);
+
+        // the sphere's near side, then the flat face of the cut, which faces out of the solid.
+        let csg = Csg::difference(sphere(), half());
+        let (t, front_face, _) = hit(&csg);
+        assert!((t - 4.0).abs() < 1e-4 && front_face);
+        let rec = csg.hit(&along_x, 4.5, f32::MAX).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-4 && !rec.front_face && rec.normal.x() < 0.0);
+
+        // starting inside the box, the cut sphere is never reached.
+        let inside = Ray::new(Vec3::new(1.5, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(csg.hit(&inside, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn csg_occluded_agrees_with_hit() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let sphere = |center: Vec3| Box::new(Sphere::new(center, 1.0, Arc::clone(&grey)));
+        check_occluded(&Csg::difference(
+            sphere(Vec3::new(0.0, 0.0, 0.0)),
+            sphere(Vec3::new(0.8, 0.0, 0.0)),
+        ));
+    }
+}
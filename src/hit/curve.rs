@@ -0,0 +1,355 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveType {
+    /// A flat strip that always turns to face the ray, which is what hair materials expect.
+    Ribbon,
+    /// Hit like a ribbon, but with normals that curve around it as if it were a tube.
+    Cylinder,
+}
+
+/// A cubic Bézier curve with a width that changes linearly along it, from width[0] at the start to
+/// width[1] at the end. Curves are meant to be thin, like hairs or blades of grass, and are hit by
+/// splitting them into pieces until each piece is close enough to a straight line.
+#[derive(Debug)]
+pub struct Curve {
+    control_points: [Vec3; 4],
+    width: [f32; 2],
+    curve_type: CurveType,
+    material: Arc<dyn Material>,
+}
+
+impl Curve {
+    pub fn bezier(
+        control_points: [Vec3; 4],
+        start_width: f32,
+        end_width: f32,
+        curve_type: CurveType,
+        material: Arc<dyn Material>,
+    ) -> Curve {
+        Curve {
+            control_points,
+            width: [start_width, end_width],
+            curve_type,
+            material,
+        }
+    }
+
+    /// A segment of a uniform cubic B-spline, which is converted to the Bézier curve with the same shape.
+    pub fn b_spline(
+        control_points: [Vec3; 4],
+        start_width: f32,
+        end_width: f32,
+        curve_type: CurveType,
+        material: Arc<dyn Material>,
+    ) -> Curve {
+        let [p0, p1, p2, p3] = control_points;
+        Curve::bezier(
+            [
+                (p0 + 4.0 * p1 + p2) / 6.0,
+                (2.0 * p1 + p2) / 3.0,
+                (p1 + 2.0 * p2) / 3.0,
+                (p1 + 4.0 * p2 + p3) / 6.0,
+            ],
+            start_width,
+            end_width,
+            curve_type,
+            material,
+        )
+    }
+
+    /// Makes a strand, such as a hair, from a B-spline through points, with one curve for each
+    /// segment. The width narrows evenly from the root to the tip. As with any B-spline, the strand
+    /// doesn't quite reach the first and last points, unless they are repeated three times.
+    pub fn b_spline_strand(
+        points: &[Vec3],
+        root_width: f32,
+        tip_width: f32,
+        curve_type: CurveType,
+        material: Arc<dyn Material>,
+    ) -> Vec<Curve> {
+        assert!(
+            points.len() >= 4,
+            "A strand needs at least 4 points, not {}!",
+            points.len()
+        );
+        let segments = points.len() - 3;
+        let width = |i: usize| {
+            let s = i as f32 / segments as f32;
+            root_width + s * (tip_width - root_width)
+        };
+        points
+            .windows(4)
+            .enumerate()
+            .map(|(i, p)| {
+                Curve::b_spline(
+                    [p[0], p[1], p[2], p[3]],
+                    width(i),
+                    width(i + 1),
+                    curve_type,
+                    Arc::clone(&material),
+                )
+            })
+            .collect()
+    }
+
+    pub fn control_points(&self) -> [Vec3; 4] {
+        self.control_points
+    }
+
+    fn width_at(&self, u: f32) -> f32 {
+        self.width[0] + u * (self.width[1] - self.width[0])
+    }
+
+    // the ray's frame, the control points in it, and how many times to split the curve.
+    fn local_curve(&self, r: &Ray) -> (Frame, [Vec3; 4], u32) {
+        let frame = Frame::new(r.origin(), r.direction());
+        let cp = [
+            frame.to_local(self.control_points[0]),
+            frame.to_local(self.control_points[1]),
+            frame.to_local(self.control_points[2]),
+            frame.to_local(self.control_points[3]),
+        ];
+
+        // split the curve often enough that each piece is within a fraction of its width of a
+        // straight line, using how far the control points bend away from one.
+        let bend = (0..2).fold(0.0f32, |bend, i| {
+            let d = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+            bend.max(d.x().abs()).max(d.y().abs()).max(d.z().abs())
+        });
+        let epsilon = 0.05 * self.width[0].max(self.width[1]);
+        let depth = if bend > 0.0 && epsilon > 0.0 {
+            ((std::f32::consts::SQRT_2 * 6.0 * bend / (8.0 * epsilon)).log2() / 2.0)
+                .clamp(0.0, 10.0) as u32
+        } else {
+            0
+        };
+        (frame, cp, depth)
+    }
+
+    // splits the part of the curve from u0 to u1, given in the ray's frame, until it is flat enough,
+    // returning the distance along the ray and the u and v of the closest hit. With any_hit, the
+    // first hit found is returned instead, for rays that only need to know about one.
+    #[allow(clippy::too_many_arguments)]
+    fn hit_piece(
+        &self,
+        cp: &[Vec3; 4],
+        u0: f32,
+        u1: f32,
+        depth: u32,
+        z_min: f32,
+        z_max: f32,
+        any_hit: bool,
+    ) -> Option<(f32, f32, f32)> {
+        // the ray runs along z from the origin, so it can only hit pieces that cover the origin.
+        let half_width = 0.5 * self.width_at(u0).max(self.width_at(u1));
+        let (min, max) = cp.iter().skip(1).fold((cp[0], cp[0]), |(min, max), p| {
+            (
+                Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z())),
+                Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z())),
+            )
+        });
+        if min.x() - half_width > 0.0
+            || max.x() + half_width < 0.0
+            || min.y() - half_width > 0.0
+            || max.y() + half_width < 0.0
+            || min.z() - half_width > z_max
+            || max.z() + half_width < z_min
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let split = subdivide_bezier(cp);
+            let middle = 0.5 * (u0 + u1);
+            let start = [split[0], split[1], split[2], split[3]];
+            let end = [split[3], split[4], split[5], split[6]];
+            let first = self.hit_piece(&start, u0, middle, depth - 1, z_min, z_max, any_hit);
+            if any_hit && first.is_some() {
+                return first;
+            }
+            let z_max = first.map_or(z_max, |(z, _, _)| z);
+            return self
+                .hit_piece(&end, middle, u1, depth - 1, z_min, z_max, any_hit)
+                .or(first);
+        }
+
+        // the piece is nearly straight, so stop at its ends, which are square to its ends' tangents.
+        let start_edge = (cp[1].y() - cp[0].y()) * -cp[0].y() + cp[0].x() * (cp[0].x() - cp[1].x());
+        let end_edge = (cp[2].y() - cp[3].y()) * -cp[3].y() + cp[3].x() * (cp[3].x() - cp[2].x());
+        if start_edge < 0.0 || end_edge < 0.0 {
+            return None;
+        }
+
+        // the closest point to the ray on the line from the start to the end of the piece.
+        let segment = Vec3::new(cp[3].x() - cp[0].x(), cp[3].y() - cp[0].y(), 0.0);
+        let length2 = segment.squared_length();
+        if length2 == 0.0 {
+            return None;
+        }
+        let w = (Vec3::new(-cp[0].x(), -cp[0].y(), 0.0).dot(segment) / length2).clamp(0.0, 1.0);
+        let u = u0 + w * (u1 - u0);
+        let width = self.width_at(u);
+
+        let (p, dpdw) = eval_bezier(cp, w);
+        let distance2 = p.x() * p.x() + p.y() * p.y();
+        if distance2 > 0.25 * width * width || p.z() < z_min || p.z() > z_max {
+            return None;
+        }
+
+        // v runs across the width of the curve, to the left of the tangent as the ray sees it.
+        let side = Vec3::new(-dpdw.y(), dpdw.x(), 0.0);
+        let side = if side.squared_length() > 0.0 {
+            side.unit_vector()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = 0.5 - Vec3::new(p.x(), p.y(), 0.0).dot(side) / width;
+        Some((p.z(), u, v.clamp(0.0, 1.0)))
+    }
+}
+
+fn eval_bezier(cp: &[Vec3; 4], u: f32) -> (Vec3, Vec3) {
+    let lerp = |a: Vec3, b: Vec3| a + u * (b - a);
+    let cp1 = [lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3])];
+    let cp2 = [lerp(cp1[0], cp1[1]), lerp(cp1[1], cp1[2])];
+    // the derivative vanishes where control points meet, so use the whole curve's direction there.
+    let derivative = if (cp2[1] - cp2[0]).squared_length() > 0.0 {
+        3.0 * (cp2[1] - cp2[0])
+    } else {
+        cp[3] - cp[0]
+    };
+    (lerp(cp2[0], cp2[1]), derivative)
+}
+
+// the control points of the two halves of the curve, with the middle point shared between them.
+fn subdivide_bezier(cp: &[Vec3; 4]) -> [Vec3; 7] {
+    [
+        cp[0],
+        (cp[0] + cp[1]) / 2.0,
+        (cp[0] + 2.0 * cp[1] + cp[2]) / 4.0,
+        (cp[0] + 3.0 * cp[1] + 3.0 * cp[2] + cp[3]) / 8.0,
+        (cp[1] + 2.0 * cp[2] + cp[3]) / 4.0,
+        (cp[2] + cp[3]) / 2.0,
+        cp[3],
+    ]
+}
+
+impl Hitable for Curve {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let length = r.direction().length();
+        let (frame, cp, depth) = self.local_curve(r);
+        let (z, u, v) =
+            self.hit_piece(&cp, 0.0, 1.0, depth, t_min * length, t_max * length, false)?;
+        let t = z / length;
+
+        let (_, dpdu) = eval_bezier(&self.control_points, u);
+        let tangent = dpdu.unit_vector();
+        let (_, local_dpdu) = eval_bezier(&cp, u);
+        let side = Vec3::new(-local_dpdu.y(), local_dpdu.x(), 0.0);
+        let side = if side.squared_length() > 0.0 {
+            frame.to_world_vector(side.unit_vector())
+        } else {
+            frame.w().cross(tangent).unit_vector()
+        };
+
+        // the ribbon faces back along the ray, as squarely as it can while lying along the curve.
+        let towards = -frame.w();
+        let facing = towards - tangent * tangent.dot(towards);
+        let facing = if facing.squared_length() > 0.0 {
+            facing.unit_vector()
+        } else {
+            tangent.cross(side).unit_vector()
+        };
+        let normal = match self.curve_type {
+            CurveType::Ribbon => facing,
+            CurveType::Cylinder => {
+                let theta = (v - 0.5) * std::f32::consts::PI;
+                theta.cos() * facing + theta.sin() * side
+            }
+        };
+
+        Some(
+            HitRecord::new(t, r.point_at_parameter(t), r, normal, &*self.material).with_surface(
+                u,
+                v,
+                dpdu,
+                self.width_at(u) * side,
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let length = r.direction().length();
+        let (_, cp, depth) = self.local_curve(r);
+        self.hit_piece(&cp, 0.0, 1.0, depth, t_min * length, t_max * length, true)
+            .is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let half_width = 0.5 * self.width[0].max(self.width[1]);
+        let cp = &self.control_points;
+        let (min, max) = cp.iter().skip(1).fold((cp[0], cp[0]), |(min, max), p| {
+            (
+                Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z())),
+                Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z())),
+            )
+        });
+        let padding = Vec3::new(half_width, half_width, half_width);
+        Some(AABB::new(min - padding, max + padding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn curves_are_hit_across_their_width() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        // an arch in the xy plane, rising to y = 1.5 in the middle.
+        let cp = [
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 2.0, 0.0),
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        ];
+        let ribbon = Curve::bezier(cp, 0.2, 0.2, CurveType::Ribbon, Arc::clone(&mat));
+        let tube = Curve::bezier(cp, 0.2, 0.2, CurveType::Cylinder, mat);
+
+        let towards = |y: f32| Ray::new(Vec3::new(0.0, y, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = ribbon.hit(&towards(1.5), 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-3 && (rec.u - 0.5).abs() < 1e-3);
+        assert!((rec.v - 0.5).abs() < 1e-2 && rec.normal.z() > 0.99);
+        assert!(rec.tangent().x().abs() > 0.99);
+
+        // near the edge, the tube's normal leans out to that side.
+        let rec = tube.hit(&towards(1.58), 0.001, f32::MAX).unwrap();
+        assert!(rec.normal.y() > 0.5 && rec.normal.z() > 0.0);
+        let rec = tube.hit(&towards(1.42), 0.001, f32::MAX).unwrap();
+        assert!(rec.normal.y() < -0.5);
+
+        assert!(ribbon.hit(&towards(1.65), 0.001, f32::MAX).is_none());
+        assert!(ribbon.hit(&towards(1.0), 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn curve_occluded_agrees_with_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let cp = [
+            Vec3::new(-1.5, -1.0, 0.0),
+            Vec3::new(-0.5, 1.0, 0.5),
+            Vec3::new(0.5, -1.0, -0.5),
+            Vec3::new(1.5, 1.0, 0.0),
+        ];
+        check_occluded(&Curve::bezier(cp, 0.8, 0.5, CurveType::Cylinder, mat));
+    }
+}
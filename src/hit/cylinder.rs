@@ -0,0 +1,184 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::disk::*;
+use super::*;
+
+/// A cylinder running from the center of its base to the center of its top.
+#[derive(Debug)]
+pub struct Cylinder {
+    frame: Frame,
+    height: f32,
+    radius: f32,
+    capped: bool,
+    material: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    /// A closed cylinder, with disks over both ends.
+    pub fn new(base: Vec3, top: Vec3, radius: f32, material: Arc<dyn Material>) -> Cylinder {
+        Cylinder {
+            frame: Frame::new(base, top - base),
+            height: (top - base).length(),
+            radius,
+            capped: true,
+            material,
+        }
+    }
+
+    /// A tube, open at both ends.
+    pub fn open(base: Vec3, top: Vec3, radius: f32, material: Arc<dyn Material>) -> Cylinder {
+        Cylinder {
+            capped: false,
+            ..Cylinder::new(base, top, radius, material)
+        }
+    }
+
+    fn side_area(&self) -> f32 {
+        2.0 * std::f32::consts::PI * self.radius * self.height
+    }
+
+    fn cap_area(&self) -> f32 {
+        std::f32::consts::PI * self.radius * self.radius
+    }
+
+    // the nearest hit on the side, from a local origin and direction, and the local point there.
+    fn hit_side(&self, o: Vec3, d: Vec3, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+        let a = d.x() * d.x() + d.y() * d.y();
+        if a <= 0.0 {
+            return None;
+        }
+        let b = o.x() * d.x() + o.y() * d.y();
+        let c = o.x() * o.x() + o.y() * o.y() - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        [
+            (-b - discriminant.sqrt()) / a,
+            (-b + discriminant.sqrt()) / a,
+        ]
+        .iter()
+        .map(|&t| (t, o + t * d))
+        .find(|&(t, p)| t > t_min && t < t_max && p.z() >= 0.0 && p.z() <= self.height)
+    }
+}
+
+impl Hitable for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let o = self.frame.to_local(r.origin());
+        let d = self.frame.to_local_vector(r.direction());
+        let pi = std::f32::consts::PI;
+
+        // (t, local point, local outward normal, u, v, local dpdu, local dpdv) of the closest hit so far.
+        let mut closest: Option<(f32, Vec3, Vec3, f32, f32, Vec3, Vec3)> = None;
+        let mut closest_so_far = t_max;
+
+        if let Some((t, p)) = self.hit_side(o, d, t_min, closest_so_far) {
+            let phi = p.y().atan2(p.x());
+            let phi = if phi < 0.0 { phi + 2.0 * pi } else { phi };
+            closest = Some((
+                t,
+                p,
+                Vec3::new(p.x(), p.y(), 0.0) / self.radius,
+                phi / (2.0 * pi),
+                p.z() / self.height,
+                2.0 * pi * Vec3::new(-p.y(), p.x(), 0.0),
+                Vec3::new(0.0, 0.0, self.height),
+            ));
+            closest_so_far = t;
+        }
+
+        if self.capped {
+            for &(z, normal) in [(0.0, -1.0), (self.height, 1.0)].iter() {
+                if let Some((t, p)) =
+                    hit_local_disk(o, d, z, self.radius, 0.0, t_min, closest_so_far)
+                {
+                    let (u, v, dpdu, dpdv) = local_disk_surface(p, self.radius, 0.0);
+                    closest = Some((t, p, Vec3::new(0.0, 0.0, normal), u, v, dpdu, dpdv));
+                    closest_so_far = t;
+                }
+            }
+        }
+
+        let (t, _, normal, u, v, dpdu, dpdv) = closest?;
+        Some(
+            HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                r,
+                self.frame.to_world_vector(normal),
+                &*self.material,
+            )
+            .with_surface(
+                u,
+                v,
+                self.frame.to_world_vector(dpdu),
+                self.frame.to_world_vector(dpdv),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let o = self.frame.to_local(r.origin());
+        let d = self.frame.to_local_vector(r.direction());
+        self.hit_side(o, d, t_min, t_max).is_some()
+            || (self.capped
+                && [0.0, self.height]
+                    .iter()
+                    .any(|&z| hit_local_disk(o, d, z, self.radius, 0.0, t_min, t_max).is_some()))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.frame.aa_box(
+            Vec3::new(-self.radius, -self.radius, 0.0),
+            Vec3::new(self.radius, self.radius, self.height),
+        ))
+    }
+}
+
+impl Sampleable for Cylinder {
+    fn area(&self) -> f32 {
+        if self.capped {
+            self.side_area() + 2.0 * self.cap_area()
+        } else {
+            self.side_area()
+        }
+    }
+
+    fn sample(&self) -> (Vec3, Vec3) {
+        // pick the side or one of the caps in proportion to their areas.
+        let pick = rand::random::<f32>() * self.area();
+        if pick < self.side_area() {
+            let phi = 2.0 * std::f32::consts::PI * rand::random::<f32>();
+            let normal = Vec3::new(phi.cos(), phi.sin(), 0.0);
+            let p = self.radius * normal + Vec3::new(0.0, 0.0, rand::random::<f32>() * self.height);
+            (self.frame.to_world(p), self.frame.to_world_vector(normal))
+        } else {
+            let top = pick > self.side_area() + self.cap_area();
+            let z = if top { self.height } else { 0.0 };
+            let normal = Vec3::new(0.0, 0.0, if top { 1.0 } else { -1.0 });
+            let p = sample_local_disk(self.radius, 0.0) + Vec3::new(0.0, 0.0, z);
+            (self.frame.to_world(p), self.frame.to_world_vector(normal))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn cylinder_occluded_agrees_with_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let base = Vec3::new(0.0, -1.0, 0.0);
+        let top = Vec3::new(0.0, 1.0, 0.0);
+        check_occluded(&Cylinder::new(base, top, 0.5, Arc::clone(&mat)));
+        check_occluded(&Cylinder::open(base, top, 0.5, mat));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::*;
+
+/// A flat disk facing along normal, or an annulus if inner_radius is more than 0.
+#[derive(Debug)]
+pub struct Disk {
+    frame: Frame,
+    radius: f32,
+    inner_radius: f32,
+    material: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(center: Vec3, normal: Vec3, radius: f32, material: Arc<dyn Material>) -> Disk {
+        Disk::annulus(center, normal, radius, 0.0, material)
+    }
+
+    pub fn annulus(
+        center: Vec3,
+        normal: Vec3,
+        radius: f32,
+        inner_radius: f32,
+        material: Arc<dyn Material>,
+    ) -> Disk {
+        assert!(
+            0.0 <= inner_radius && inner_radius < radius,
+            "A disk's inner radius of {} must be between 0 and its radius of {}!",
+            inner_radius,
+            radius
+        );
+        Disk {
+            frame: Frame::new(center, normal),
+            radius,
+            inner_radius,
+            material,
+        }
+    }
+}
+
+/// Hits the disk lying in the plane at height z of a local frame, returning t and the local hit point.
+pub(crate) fn hit_local_disk(
+    origin: Vec3,
+    direction: Vec3,
+    z: f32,
+    radius: f32,
+    inner_radius: f32,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, Vec3)> {
+    if direction.z() == 0.0 {
+        return None;
+    }
+    let t = (z - origin.z()) / direction.z();
+    if t <= t_min || t >= t_max {
+        return None;
+    }
+    let p = origin + t * direction;
+    let distance2 = p.x() * p.x() + p.y() * p.y();
+    if distance2 > radius * radius || distance2 < inner_radius * inner_radius {
+        return None;
+    }
+    Some((t, p))
+}
+
+/// u goes around the disk, and v from the outside edge in. The derivatives are in the local frame.
+pub(crate) fn local_disk_surface(
+    p: Vec3,
+    radius: f32,
+    inner_radius: f32,
+) -> (f32, f32, Vec3, Vec3) {
+    let pi = std::f32::consts::PI;
+    let distance = (p.x() * p.x() + p.y() * p.y()).sqrt();
+    let phi = p.y().atan2(p.x());
+    let phi = if phi < 0.0 { phi + 2.0 * pi } else { phi };
+
+    let dpdu = 2.0 * pi * Vec3::new(-p.y(), p.x(), 0.0);
+    let dpdv = if distance > 0.0 {
+        -(radius - inner_radius) / distance * Vec3::new(p.x(), p.y(), 0.0)
+    } else {
+        Vec3::new(0.0, 0.0, 0.0)
+    };
+    (
+        phi / (2.0 * pi),
+        (radius - distance) / (radius - inner_radius),
+        dpdu,
+        dpdv,
+    )
+}
+
+/// A uniformly random point on a disk or annulus in the local xy plane.
+pub(crate) fn sample_local_disk(radius: f32, inner_radius: f32) -> Vec3 {
+    let inner2 = inner_radius * inner_radius;
+    let r = (inner2 + rand::random::<f32>() * (radius * radius - inner2)).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * rand::random::<f32>();
+    Vec3::new(r * phi.cos(), r * phi.sin(), 0.0)
+}
+
+impl Disk {
+    // where the ray hits the disk, and the point it hits in the disk's own space.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3)> {
+        let origin = self.frame.to_local(r.origin());
+        let direction = self.frame.to_local_vector(r.direction());
+        hit_local_disk(
+            origin,
+            direction,
+            0.0,
+            self.radius,
+            self.inner_radius,
+            t_min,
+            t_max,
+        )
+    }
+}
+
+impl Hitable for Disk {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, p) = self.intersect(r, t_min, t_max)?;
+
+        let (u, v, dpdu, dpdv) = local_disk_surface(p, self.radius, self.inner_radius);
+        Some(
+            HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                r,
+                self.frame.w(),
+                &*self.material,
+            )
+            .with_surface(
+                u,
+                v,
+                self.frame.to_world_vector(dpdu),
+                self.frame.to_world_vector(dpdv),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    // padded a little, as the disk is flat.
+    fn bounding_box(&self) -> Option<AABB> {
+        let extent = Vec3::new(self.radius, self.radius, 0.0001);
+        Some(self.frame.aa_box(-extent, extent))
+    }
+}
+
+impl Sampleable for Disk {
+    fn area(&self) -> f32 {
+        std::f32::consts::PI * (self.radius * self.radius - self.inner_radius * self.inner_radius)
+    }
+
+    fn sample(&self) -> (Vec3, Vec3) {
+        let p = sample_local_disk(self.radius, self.inner_radius);
+        (self.frame.to_world(p), self.frame.w())
+    }
+}
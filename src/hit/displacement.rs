@@ -0,0 +1,277 @@
+use crate::colour::*;
+use crate::texture::*;
+use crate::vec3::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::mesh::*;
+
+/// A texture that moves the surface of a mesh, read through the mesh's texture coordinates.
+#[derive(Debug, Clone)]
+pub struct Displacement {
+    texture: Arc<ImageTexture>,
+    kind: DisplacementKind,
+    scale: f32,
+    midlevel: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DisplacementKind {
+    // the brightness of the texture moves points along their normals.
+    Scalar,
+    // the red, green and blue of the texture move points along x, y and z.
+    Vector,
+}
+
+impl Displacement {
+    /// Moves each point along its normal by the brightness of the texture there, times the scale.
+    pub fn scalar(texture: Arc<ImageTexture>, scale: f32) -> Displacement {
+        Displacement {
+            texture,
+            kind: DisplacementKind::Scalar,
+            scale,
+            midlevel: 0.0,
+        }
+    }
+
+    /// Moves each point by the colour of the texture there, read as an object space offset and
+    /// multiplied by the scale.
+    pub fn vector(texture: Arc<ImageTexture>, scale: f32) -> Displacement {
+        Displacement {
+            texture,
+            kind: DisplacementKind::Vector,
+            scale,
+            midlevel: 0.0,
+        }
+    }
+
+    /// The texture value that leaves the surface where it is. Anything below it pushes the surface
+    /// in, and anything above pulls it out. Defaults to 0.
+    pub fn with_midlevel(mut self, midlevel: f32) -> Displacement {
+        self.midlevel = midlevel;
+        self
+    }
+
+    // how far a point with the given normal and texture coordinates moves.
+    fn offset(&self, normal: Vec3, uv: [f32; 2]) -> Vec3 {
+        let [r, g, b, _] = self.texture.sample(uv[0], uv[1]);
+        match self.kind {
+            DisplacementKind::Scalar => {
+                let height = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+                (height - self.midlevel) * self.scale * normal
+            }
+            DisplacementKind::Vector => {
+                Vec3::new(r - self.midlevel, g - self.midlevel, b - self.midlevel) * self.scale
+            }
+        }
+    }
+}
+
+// the vertex buffers of a mesh while it is being cut up.
+struct Vertices {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    colours: Option<Vec<Colour>>,
+}
+
+impl Vertices {
+    // adds a vertex half way between a and b, and returns its index.
+    fn midpoint(&mut self, a: u32, b: u32) -> u32 {
+        let (a, b) = (a as usize, b as usize);
+        self.positions
+            .push((self.positions[a] + self.positions[b]) * 0.5);
+        self.normals
+            .push((self.normals[a] + self.normals[b]).unit_vector());
+        let (uv_a, uv_b) = (self.uvs[a], self.uvs[b]);
+        self.uvs
+            .push([(uv_a[0] + uv_b[0]) * 0.5, (uv_a[1] + uv_b[1]) * 0.5]);
+        if let Some(colours) = self.colours.as_mut() {
+            colours.push((colours[a] + colours[b]) * 0.5);
+        }
+        (self.positions.len() - 1) as u32
+    }
+}
+
+impl Mesh {
+    /// Makes a new mesh with its surface moved by the displacement. The triangles are first cut up
+    /// until no edge is longer than max_edge_length, so the texture has enough points to move, and
+    /// the mesh's BVH is then built around the moved points, so its boxes hold the whole of the
+    /// displaced surface. The mesh needs texture coordinates to read the displacement with. If it
+    /// has no normals, smooth ones are worked out first, and the normals are worked out again
+    /// from the displaced surface.
+    pub fn with_displacement(self, displacement: &Displacement, max_edge_length: f32) -> Mesh {
+        assert!(
+            max_edge_length > 0.0,
+            "A displaced mesh must have a max edge length above 0, not {}!",
+            max_edge_length
+        );
+        let uvs = self
+            .uvs()
+            .expect("A mesh must have uvs to be displaced!")
+            .to_vec();
+        let positions = self.positions().to_vec();
+        let normals = match self.normals() {
+            Some(normals) => normals.to_vec(),
+            None => smooth_normals(&positions, &positions, self.indices()),
+        };
+        let mut vertices = Vertices {
+            positions,
+            normals,
+            uvs,
+            colours: self.colours().map(|colours| colours.to_vec()),
+        };
+        let indices = tessellate(&mut vertices, self.indices().to_vec(), max_edge_length);
+
+        let displaced: Vec<Vec3> = (0..vertices.positions.len())
+            .map(|i| {
+                vertices.positions[i] + displacement.offset(vertices.normals[i], vertices.uvs[i])
+            })
+            .collect();
+        // vertices that were in the same place before are welded together while finding normals,
+        // so seams in the texture coordinates don't show up as seams in the shading.
+        let normals = smooth_normals(&vertices.positions, &displaced, &indices);
+
+        let mesh = Mesh::new(displaced, indices, Arc::clone(self.material()))
+            .with_normals(normals)
+            .with_uvs(vertices.uvs);
+        match vertices.colours {
+            Some(colours) => mesh.with_colours(colours),
+            None => mesh,
+        }
+    }
+}
+
+// cuts every edge longer than max_edge_length in half, over and over until there are none left.
+// Edges are cut for every triangle that shares them at once, so the triangles stay joined up.
+fn tessellate(
+    vertices: &mut Vertices,
+    mut indices: Vec<[u32; 3]>,
+    max_edge_length: f32,
+) -> Vec<[u32; 3]> {
+    let max_squared = max_edge_length * max_edge_length;
+    loop {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        for tri in indices.iter() {
+            for i in 0..3 {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                let key = (a.min(b), a.max(b));
+                let length = (vertices.positions[a as usize] - vertices.positions[b as usize])
+                    .squared_length();
+                if length > max_squared && !midpoints.contains_key(&key) {
+                    let m = vertices.midpoint(key.0, key.1);
+                    midpoints.insert(key, m);
+                }
+            }
+        }
+        if midpoints.is_empty() {
+            return indices;
+        }
+
+        let mut split = Vec::with_capacity(indices.len() * 4);
+        for tri in indices.iter() {
+            let mid = |i: usize| {
+                let (a, b) = (tri[i], tri[(i + 1) % 3]);
+                midpoints.get(&(a.min(b), a.max(b))).cloned()
+            };
+            let cuts = [mid(0), mid(1), mid(2)];
+            match cuts.iter().filter(|m| m.is_some()).count() {
+                0 => split.push(*tri),
+                1 => {
+                    // turned so the cut edge runs from a to b.
+                    let i = cuts.iter().position(|m| m.is_some()).unwrap();
+                    let (a, b, c) = (tri[i], tri[(i + 1) % 3], tri[(i + 2) % 3]);
+                    let m = cuts[i].unwrap();
+                    split.push([a, m, c]);
+                    split.push([m, b, c]);
+                }
+                2 => {
+                    // turned so the edge that isn't cut runs from c back to a.
+                    let i = (cuts.iter().position(|m| m.is_none()).unwrap() + 1) % 3;
+                    let (a, b, c) = (tri[i], tri[(i + 1) % 3], tri[(i + 2) % 3]);
+                    let (ab, bc) = (cuts[i].unwrap(), cuts[(i + 1) % 3].unwrap());
+                    split.push([ab, b, bc]);
+                    split.push([a, ab, bc]);
+                    split.push([a, bc, c]);
+                }
+                _ => {
+                    let (ab, bc, ca) = (cuts[0].unwrap(), cuts[1].unwrap(), cuts[2].unwrap());
+                    split.push([tri[0], ab, ca]);
+                    split.push([ab, tri[1], bc]);
+                    split.push([ca, bc, tri[2]]);
+                    split.push([ab, bc, ca]);
+                }
+            }
+        }
+        indices = split;
+    }
+}
+
+// area weighted normals of the surface made by positions, with vertices that share a place in
+// welded_by summed together.
+fn smooth_normals(welded_by: &[Vec3], positions: &[Vec3], indices: &[[u32; 3]]) -> Vec<Vec3> {
+    let key = |p: Vec3| (p.x().to_bits(), p.y().to_bits(), p.z().to_bits());
+    let mut sums: HashMap<(u32, u32, u32), Vec3> = HashMap::new();
+    for tri in indices.iter() {
+        let [a, b, c] = tri.map(|i| positions[i as usize]);
+        let normal = (b - a).cross(c - a);
+        for &i in tri.iter() {
+            let sum = sums
+                .entry(key(welded_by[i as usize]))
+                .or_insert_with(|| Vec3::new(0.0, 0.0, 0.0));
+            *sum = *sum + normal;
+        }
+    }
+    welded_by
+        .iter()
+        .map(|&p| match sums.get(&key(p)) {
+            Some(n) if n.squared_length() > 0.0 => n.unit_vector(),
+            _ => Vec3::new(0.0, 1.0, 0.0),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::*;
+    use crate::material::lambertian::*;
+    use crate::ray::*;
+
+    #[test]
+    fn displaced_quad_is_lifted_and_cut_up() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let positions = vec![
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(-1.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+        ];
+        let uvs = vec![[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
+        let mesh = Mesh::new(positions, vec![[0, 1, 2], [0, 2, 3]], mat).with_uvs(uvs);
+
+        let texture = Arc::new(ImageTexture::new(2, 2, vec![[0.75, 0.75, 0.75, 1.0]; 4]));
+        let displacement = Displacement::scalar(texture, 2.0).with_midlevel(0.5);
+        let mesh = mesh.with_displacement(&displacement, 0.3);
+
+        assert!(mesh.triangle_count() > 2);
+        for tri in mesh.indices() {
+            for i in 0..3 {
+                let a = mesh.positions()[tri[i] as usize];
+                let b = mesh.positions()[tri[(i + 1) % 3] as usize];
+                assert!((a - b).length() <= 0.3);
+            }
+        }
+        for p in mesh.positions() {
+            assert!((p.y() - 0.5).abs() < 1e-5);
+        }
+
+        let r = Ray::new(Vec3::new(0.3, 2.0, -0.2), Vec3::new(0.0, -1.0, 0.0));
+        let rec = mesh
+            .hit(&r, 0.001, f32::MAX)
+            .expect("The ray should hit the mesh!");
+        assert!((rec.t - 1.5).abs() < 1e-4);
+        let aa_box = mesh.bounding_box().unwrap();
+        assert!(aa_box.min().y() > 0.4 && aa_box.max().y() < 0.6);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::vec3::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::triangle::*;
+use super::*;
+
+/// A terrain made from a grid of heights, with each square of the grid split into two triangles.
+/// The grid lies in the xz plane, with the heights going up y, and rays find the squares they
+/// cross by walking down a quadtree that keeps the lowest and highest height under each node.
+#[derive(Debug)]
+pub struct Heightfield {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    min: Vec3,
+    size: Vec3,
+    // each level of the quadtree holds the (lowest, highest) heights of its nodes, row by row,
+    // starting with the squares of the grid themselves and ending with a single root.
+    levels: Vec<QuadtreeLevel>,
+    material: Arc<dyn Material>,
+}
+
+#[derive(Debug)]
+struct QuadtreeLevel {
+    width: usize,
+    depth: usize,
+    bounds: Vec<(f32, f32)>,
+}
+
+impl Heightfield {
+    /// Heights are given row by row, with width heights along x in each row and depth rows along z.
+    /// The grid is stretched over the box from min to min + size, with heights of 0 on the bottom
+    /// of it and heights of 1 on the top.
+    pub fn new(
+        width: usize,
+        depth: usize,
+        heights: Vec<f32>,
+        min: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Heightfield {
+        assert!(
+            width >= 2 && depth >= 2,
+            "A heightfield needs at least 2 by 2 heights, not {} by {}!",
+            width,
+            depth
+        );
+        assert!(
+            heights.len() == width * depth,
+            "Heightfield has {} heights, but should have {}",
+            heights.len(),
+            width * depth
+        );
+
+        let mut heightfield = Heightfield {
+            width,
+            depth,
+            heights,
+            normals: vec![],
+            min,
+            size,
+            levels: vec![],
+            material,
+        };
+        heightfield.normals = heightfield.vertex_normals();
+        heightfield.levels = heightfield.build_quadtree();
+        heightfield
+    }
+
+    /// Loads the heights from a greyscale png, where the top row of the image is at min z.
+    /// 16 bit images keep their precision, which avoids terracing on gentle slopes.
+    pub fn from_png(
+        path: &Path,
+        min: Vec3,
+        size: Vec3,
+        material: Arc<dyn Material>,
+    ) -> Result<Heightfield, png::DecodingError> {
+        let (width, depth, heights) = load_luminance_png(path)?;
+        Ok(Heightfield::new(width, depth, heights, min, size, material))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn point(&self, x: usize, z: usize) -> Vec3 {
+        Vec3::new(
+            self.min.x() + self.size.x() * x as f32 / (self.width - 1) as f32,
+            self.min.y() + self.size.y() * self.heights[z * self.width + x],
+            self.min.z() + self.size.z() * z as f32 / (self.depth - 1) as f32,
+        )
+    }
+
+    // smooth normals from the slope across each point, or to one side of it on the edges.
+    fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = Vec::with_capacity(self.width * self.depth);
+        for z in 0..self.depth {
+            for x in 0..self.width {
+                let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+                let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+                let along_x = self.point(x1, z) - self.point(x0, z);
+                let along_z = self.point(x, z1) - self.point(x, z0);
+                normals.push(along_z.cross(along_x).unit_vector());
+            }
+        }
+        normals
+    }
+
+    fn build_quadtree(&self) -> Vec<QuadtreeLevel> {
+        let (width, depth) = (self.width - 1, self.depth - 1);
+        let mut bounds = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let corners = [
+                    self.heights[z * self.width + x],
+                    self.heights[z * self.width + x + 1],
+                    self.heights[(z + 1) * self.width + x],
+                    self.heights[(z + 1) * self.width + x + 1],
+                ];
+                let low = corners.iter().cloned().fold(f32::INFINITY, f32::min);
+                let high = corners.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                bounds.push((low, high));
+            }
+        }
+
+        let mut levels = vec![QuadtreeLevel {
+            width,
+            depth,
+            bounds,
+        }];
+        while let Some(below) = levels.last().filter(|l| l.width > 1 || l.depth > 1) {
+            let (width, depth) = (below.width.div_ceil(2), below.depth.div_ceil(2));
+            let mut bounds = Vec::with_capacity(width * depth);
+            for z in 0..depth {
+                for x in 0..width {
+                    let mut bound = (f32::INFINITY, f32::NEG_INFINITY);
+                    let (children, count) = children(x, z, below.width, below.depth);
+                    for &(cx, cz) in children[..count].iter() {
+                        let child = below.bounds[cz * below.width + cx];
+                        bound = (bound.0.min(child.0), bound.1.max(child.1));
+                    }
+                    bounds.push(bound);
+                }
+            }
+            levels.push(QuadtreeLevel {
+                width,
+                depth,
+                bounds,
+            });
+        }
+        levels
+    }
+
+    // the world space box around a node of the quadtree.
+    fn node_box(&self, level: usize, x: usize, z: usize) -> AABB {
+        let (low, high) = self.levels[level].bounds[z * self.levels[level].width + x];
+        let cells = 1 << level;
+        let x0 = x * cells;
+        let z0 = z * cells;
+        let x1 = (x0 + cells).min(self.width - 1);
+        let z1 = (z0 + cells).min(self.depth - 1);
+        let cell_x = self.size.x() / (self.width - 1) as f32;
+        let cell_z = self.size.z() / (self.depth - 1) as f32;
+        // padded a little, so flat areas still have boxes with some thickness.
+        AABB::new(
+            Vec3::new(
+                self.min.x() + x0 as f32 * cell_x,
+                self.min.y() + low * self.size.y() - 0.0001,
+                self.min.z() + z0 as f32 * cell_z,
+            ),
+            Vec3::new(
+                self.min.x() + x1 as f32 * cell_x,
+                self.min.y() + high * self.size.y() + 0.0001,
+                self.min.z() + z1 as f32 * cell_z,
+            ),
+        )
+    }
+
+    fn hit_node(
+        &self,
+        level: usize,
+        x: usize,
+        z: usize,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord> {
+        if !self.node_box(level, x, z).hit(r, t_min, t_max) {
+            return None;
+        }
+        if level == 0 {
+            return self.hit_cell(x, z, r, t_min, t_max);
+        }
+
+        // visit the children nearest the ray's origin first, so the far ones can often be skipped.
+        let below = &self.levels[level - 1];
+        let (mut order, count) = children(x, z, below.width, below.depth);
+        let order = &mut order[..count];
+        let (flip_x, flip_z) = (r.dir_sign()[0], r.dir_sign()[2]);
+        order.sort_unstable_by_key(|&(cx, cz)| {
+            ((cx % 2 == 1) != flip_x) as u8 + ((cz % 2 == 1) != flip_z) as u8
+        });
+
+        let mut closest_so_far = t_max;
+        let mut rec = None;
+        for &(cx, cz) in order.iter() {
+            if let Some(record) = self.hit_node(level - 1, cx, cz, r, t_min, closest_so_far) {
+                closest_so_far = record.t;
+                rec = Some(record);
+            }
+        }
+        rec
+    }
+
+    // like hit_node, but stops at the first triangle in the way, without looking for the nearest.
+    fn occluded_node(
+        &self,
+        level: usize,
+        x: usize,
+        z: usize,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> bool {
+        if !self.node_box(level, x, z).hit(r, t_min, t_max) {
+            return false;
+        }
+        if level == 0 {
+            let p = [
+                self.point(x, z),
+                self.point(x, z + 1),
+                self.point(x + 1, z + 1),
+                self.point(x + 1, z),
+            ];
+            return [[0, 1, 2], [0, 2, 3]].iter().any(|&[a, b, c]| {
+                moller_trumbore(r, p[a], p[b] - p[a], p[c] - p[a], t_min, t_max).is_some()
+            });
+        }
+
+        let below = &self.levels[level - 1];
+        let (order, count) = children(x, z, below.width, below.depth);
+        order[..count]
+            .iter()
+            .any(|&(cx, cz)| self.occluded_node(level - 1, cx, cz, r, t_min, t_max))
+    }
+
+    fn hit_cell(&self, x: usize, z: usize, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let corners = [(x, z), (x, z + 1), (x + 1, z + 1), (x + 1, z)];
+        let mut closest_so_far = t_max;
+        let mut rec = None;
+        // the two triangles, wound so their normals face up.
+        for &[a, b, c] in [[0, 1, 2], [0, 2, 3]].iter() {
+            let ids = [corners[a], corners[b], corners[c]];
+            let p = [
+                self.point(ids[0].0, ids[0].1),
+                self.point(ids[1].0, ids[1].1),
+                self.point(ids[2].0, ids[2].1),
+            ];
+            let edge1 = p[1] - p[0];
+            let edge2 = p[2] - p[0];
+            if let Some((t, u, v)) = moller_trumbore(r, p[0], edge1, edge2, t_min, closest_so_far) {
+                let uv = |(x, z): (usize, usize)| {
+                    [
+                        x as f32 / (self.width - 1) as f32,
+                        z as f32 / (self.depth - 1) as f32,
+                    ]
+                };
+                let (tex_u, tex_v, dpdu, dpdv) =
+                    triangle_surface(p, Some([uv(ids[0]), uv(ids[1]), uv(ids[2])]), u, v);
+                let normal = |(x, z): (usize, usize)| self.normals[z * self.width + x];
+
+                closest_so_far = t;
+                rec = Some(
+                    HitRecord::new(
+                        t,
+                        r.point_at_parameter(t),
+                        r,
+                        edge1.cross(edge2).unit_vector(),
+                        &*self.material,
+                    )
+                    .with_surface(tex_u, tex_v, dpdu, dpdv)
+                    .with_shading_normal(interpolate_normal(
+                        [normal(ids[0]), normal(ids[1]), normal(ids[2])],
+                        u,
+                        v,
+                    )),
+                );
+            }
+        }
+        rec
+    }
+}
+
+// the nodes in the level below that make up node (x, z), which is fewer than 4 on the far edges
+// of grids that don't divide evenly.
+fn children(x: usize, z: usize, width: usize, depth: usize) -> ([(usize, usize); 4], usize) {
+    let mut children = [(0, 0); 4];
+    let mut count = 0;
+    for cz in 2 * z..(2 * z + 2).min(depth) {
+        for cx in 2 * x..(2 * x + 2).min(width) {
+            children[count] = (cx, cz);
+            count += 1;
+        }
+    }
+    (children, count)
+}
+
+impl Hitable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.hit_node(self.levels.len() - 1, 0, 0, r, t_min, t_max)
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.occluded_node(self.levels.len() - 1, 0, 0, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.node_box(self.levels.len() - 1, 0, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::hit::mesh::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn heightfield_matches_its_triangles() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        // an uneven size, so the quadtree has partly empty nodes on its far edges.
+        let (width, depth) = (9, 7);
+        let heights: Vec<f32> = (0..width * depth)
+            .map(|i| 0.5 + 0.4 * ((i % width) as f32 * 1.3).sin() * ((i / width) as f32).cos())
+            .collect();
+        let min = Vec3::new(-4.0, 0.0, -3.0);
+        let size = Vec3::new(8.0, 2.0, 6.0);
+        let field = Heightfield::new(width, depth, heights, min, size, Arc::clone(&mat));
+
+        let mut positions = vec![];
+        for z in 0..depth {
+            for x in 0..width {
+                positions.push(field.point(x, z));
+            }
+        }
+        let mut indices = vec![];
+        for z in 0..depth as u32 - 1 {
+            for x in 0..width as u32 - 1 {
+                let i = z * width as u32 + x;
+                let w = width as u32;
+                indices.push([i, i + w, i + w + 1]);
+                indices.push([i, i + w + 1, i + 1]);
+            }
+        }
+        let mesh = Mesh::new(positions, indices, mat);
+
+        for i in 0..200 {
+            let i = i as f32;
+            let origin = Vec3::new(6.0 * (i * 0.37).sin(), 4.0, 5.0 * (i * 0.71).cos());
+            let target = Vec3::new(4.0 * (i * 1.13).cos(), 0.5, 3.0 * (i * 0.53).sin());
+            let r = Ray::new(origin, target - origin);
+
+            let expected = mesh.hit(&r, 0.001, f32::MAX).map(|rec| rec.t);
+            let found = field.hit(&r, 0.001, f32::MAX);
+            assert_eq!(found.map(|rec| rec.t), expected);
+            assert_eq!(field.occluded(&r, 0.001, f32::MAX), expected.is_some());
+            if let Some(rec) = found {
+                assert!((0.0..=1.0).contains(&rec.u) && (0.0..=1.0).contains(&rec.v));
+                assert!(rec.normal.dot(rec.geometric_normal) > 0.0);
+            }
+        }
+    }
+}
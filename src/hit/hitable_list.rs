@@ -26,6 +26,12 @@ impl Hitable for HitableList {
         rec
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.hitables
+            .iter()
+            .any(|elem| elem.occluded(r, t_min, t_max))
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         if self.hitables.is_empty() {
             return None;
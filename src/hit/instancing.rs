@@ -1,3 +1,4 @@
+use crate::hit::bvh::*;
 use crate::hit::hitable_list::*;
 use crate::hit::rectangle::*;
 use crate::hit::HitRecord;
@@ -5,6 +6,7 @@ use crate::hit::Hitable;
 use crate::hit::AABB;
 use crate::material::Material;
 use crate::ray::Ray;
+use crate::transform::*;
 use crate::vec3::Vec3;
 
 use std::sync::Arc;
@@ -28,6 +30,10 @@ impl<T: Hitable> Hitable for FlipFace<T> {
         })
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.obj.occluded(r, t_min, t_max)
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         self.obj.bounding_box()
     }
@@ -48,19 +54,19 @@ impl<T: Hitable> Translate<T> {
 
 impl<T: Hitable> Hitable for Translate<T> {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let moved_r = Ray::new(r.origin() - self.offset, r.direction());
+        let moved_r = Ray::new_at_time(r.origin() - self.offset, r.direction(), r.time());
 
-        self.obj.hit(&moved_r, t_min, t_max).map(|rec| {
-            HitRecord::new(
-                rec.t,
-                rec.position + self.offset,
-                &moved_r,
-                rec.normal,
-                rec.material,
-            )
+        self.obj.hit(&moved_r, t_min, t_max).map(|mut rec| {
+            rec.position = rec.position + self.offset;
+            rec
         })
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let moved_r = Ray::new_at_time(r.origin() - self.offset, r.direction(), r.time());
+        self.obj.occluded(&moved_r, t_min, t_max)
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         self.obj.bounding_box().map(|output_box| {
             AABB::new(
@@ -71,106 +77,311 @@ impl<T: Hitable> Hitable for Translate<T> {
     }
 }
 
-#[derive(Debug)]
-pub struct RotateY<T: Hitable> {
-    obj: T,
+// turns a ray the opposite way to an object rotated around the y axis by the angle with the given
+// sine and cosine, so it can hit the object as it was before the rotation.
+fn rotate_ray_y(r: &Ray, sin_theta: f32, cos_theta: f32) -> Ray {
+    let mut origin = r.origin();
+    let mut direction = r.direction();
+
+    origin.set(
+        0,
+        cos_theta * r.origin().get(0) - sin_theta * r.origin().get(2),
+    );
+    origin.set(
+        2,
+        sin_theta * r.origin().get(0) + cos_theta * r.origin().get(2),
+    );
+
+    direction.set(
+        0,
+        cos_theta * r.direction().get(0) - sin_theta * r.direction().get(2),
+    );
+    direction.set(
+        2,
+        sin_theta * r.direction().get(0) + cos_theta * r.direction().get(2),
+    );
+
+    Ray::new_at_time(origin, direction, r.time())
+}
+
+// hits obj as if it had been rotated around the y axis by the angle with the given sine and cosine.
+fn hit_rotated_y<'a, T: Hitable>(
+    obj: &'a T,
+    r: &Ray,
+    t_min: f32,
+    t_max: f32,
     sin_theta: f32,
     cos_theta: f32,
+) -> Option<HitRecord<'a>> {
+    let r_rotated = rotate_ray_y(r, sin_theta, cos_theta);
+
+    // the record is kept as it is, other than turning it back into world space,
+    // so which side of the surface was hit and its surface coordinates carry through.
+    let rotate_back = |p: Vec3| {
+        Vec3::new(
+            cos_theta * p.x() + sin_theta * p.z(),
+            p.y(),
+            -sin_theta * p.x() + cos_theta * p.z(),
+        )
+    };
+
+    obj.hit(&r_rotated, t_min, t_max).map(|mut rec| {
+        rec.position = rotate_back(rec.position);
+        rec.normal = rotate_back(rec.normal);
+        rec.geometric_normal = rotate_back(rec.geometric_normal);
+        rec.dpdu = rotate_back(rec.dpdu);
+        rec.dpdv = rotate_back(rec.dpdv);
+        rec
+    })
+}
+
+/// Places an object with any affine transform, such as the object matrices from Blender.
+/// Rays are moved into the object's space to hit it, and the hit is moved back out again.
+#[derive(Debug)]
+pub struct Transformed<T: Hitable> {
+    obj: T,
+    transform: Transform,
     aa_box: Option<AABB>,
 }
 
-impl<T: Hitable> RotateY<T> {
-    pub fn new(obj: T, angle: f32) -> RotateY<T> {
-        let radians = angle.to_radians();
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
+impl<T: Hitable> Transformed<T> {
+    pub fn new(obj: T, transform: Transform) -> Transformed<T> {
+        let aa_box = obj.bounding_box().map(|b| transform.aa_box(&b));
+        Transformed {
+            obj,
+            transform,
+            aa_box,
+        }
+    }
 
-        let aa_box = obj.bounding_box().map(|bbox| {
-            let mut min = Vec3::new(f32::MAX, f32::MAX, f32::MAX);
-            let mut max = Vec3::new(f32::MIN, f32::MIN, f32::MIN);
-
-            for i in 0..2 {
-                for j in 0..2 {
-                    for k in 0..2 {
-                        let x = i as f32 * bbox.max().x() + (1 - i) as f32 * bbox.min().x();
-                        let y = j as f32 * bbox.max().y() + (1 - j) as f32 * bbox.min().y();
-                        let z = k as f32 * bbox.max().z() + (1 - k) as f32 * bbox.min().z();
-
-                        let x = cos_theta * x + sin_theta * z;
-                        let z = -sin_theta * x + cos_theta * z;
-
-                        let tester = Vec3::new(x, y, z);
-
-                        for c in 0..3 {
-                            min.set(c, min.get(c).min(tester.get(c)));
-                            max.set(c, max.get(c).max(tester.get(c)));
-                        }
-                    }
-                }
-            }
-            AABB::new(min, max)
-        });
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
 
-        RotateY {
-            obj,
-            sin_theta,
-            cos_theta,
+impl<T: Hitable> Hitable for Transformed<T> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        // the direction isn't normalized, so t is the same in both spaces.
+        let object_r = self.transform.inverse().ray(r);
+
+        self.obj
+            .hit(&object_r, t_min, t_max)
+            .map(|rec| transform_record(rec, &self.transform))
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.obj
+            .occluded(&self.transform.inverse().ray(r), t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.aa_box
+    }
+}
+
+/// A placed copy of a prototype that's shared between every copy, such as a mesh with its own BVH.
+/// Each instance only holds its transform and an optional material to use instead of the prototype's,
+/// so thousands of them take little more memory than one. The scene's BVH is then built over the instances.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    prototype: Arc<dyn Hitable>,
+    transform: Transform,
+    material: Option<Arc<dyn Material>>,
+    aa_box: Option<AABB>,
+}
+
+impl Instance {
+    pub fn new(prototype: Arc<dyn Hitable>, transform: Transform) -> Instance {
+        let aa_box = prototype.bounding_box().map(|b| transform.aa_box(&b));
+        Instance {
+            prototype,
+            transform,
+            material: None,
             aa_box,
         }
     }
+
+    /// Makes a prototype out of several objects, with a BVH over them so they are quick to hit.
+    pub fn build_prototype(objects: Vec<Box<dyn Hitable>>) -> Arc<dyn Hitable> {
+        Arc::new(BvhNode::new_sah(objects))
+    }
+
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> Instance {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn prototype(&self) -> &Arc<dyn Hitable> {
+        &self.prototype
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
 }
 
-impl<T: Hitable> Hitable for RotateY<T> {
+impl Hitable for Instance {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut origin = r.origin();
-        let mut direction = r.direction();
+        let object_r = self.transform.inverse().ray(r);
 
-        origin.set(
-            0,
-            self.cos_theta * r.origin().get(0) - self.sin_theta * r.origin().get(2),
-        );
-        origin.set(
-            2,
-            self.sin_theta * r.origin().get(0) + self.cos_theta * r.origin().get(2),
-        );
+        self.prototype.hit(&object_r, t_min, t_max).map(|rec| {
+            let mut rec = transform_record(rec, &self.transform);
+            if let Some(material) = &self.material {
+                rec.material = &**material;
+            }
+            rec
+        })
+    }
 
-        direction.set(
-            0,
-            self.cos_theta * r.direction().get(0) - self.sin_theta * r.direction().get(2),
-        );
-        direction.set(
-            2,
-            self.sin_theta * r.direction().get(0) + self.cos_theta * r.direction().get(2),
-        );
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.prototype
+            .occluded(&self.transform.inverse().ray(r), t_min, t_max)
+    }
 
-        let r_rotated = Ray::new(origin, direction);
+    fn bounding_box(&self) -> Option<AABB> {
+        self.aa_box
+    }
+}
 
-        self.obj.hit(&r_rotated, t_min, t_max).map(|rec| {
-            let mut position = rec.position;
-            let mut normal = rec.normal;
+// moves a hit from object space into world space. The normals still face against the ray afterwards,
+// as the inverse transpose keeps the sign of their dot product with any transformed direction.
+pub(crate) fn transform_record<'a>(mut rec: HitRecord<'a>, transform: &Transform) -> HitRecord<'a> {
+    rec.position = transform.point(rec.position);
+    rec.normal = transform.normal(rec.normal).unit_vector();
+    rec.geometric_normal = transform.normal(rec.geometric_normal).unit_vector();
+    rec.dpdu = transform.vector(rec.dpdu);
+    rec.dpdv = transform.vector(rec.dpdv);
+    rec
+}
 
-            position.set(
-                0,
-                self.cos_theta * rec.position.get(0) + self.sin_theta * rec.position.get(2),
-            );
-            position.set(
-                2,
-                -self.sin_theta * rec.position.get(0) + self.cos_theta * rec.position.get(2),
-            );
+// linearly interpolates between keyframes, which are sorted by time.
+// Before the first and after the last keyframe, the value holds still.
+fn interpolate<V: Copy>(keyframes: &[(f32, V)], time: f32, lerp: impl Fn(V, V, f32) -> V) -> V {
+    let next = keyframes
+        .iter()
+        .position(|&(t, _)| t > time)
+        .unwrap_or(keyframes.len());
 
-            normal.set(
-                0,
-                self.cos_theta * rec.normal.get(0) + self.sin_theta * rec.normal.get(2),
-            );
-            normal.set(
-                2,
-                -self.sin_theta * rec.normal.get(0) + self.cos_theta * rec.normal.get(2),
-            );
+    if next == 0 {
+        return keyframes[0].1;
+    }
+    if next == keyframes.len() {
+        return keyframes[next - 1].1;
+    }
+
+    let (t0, v0) = keyframes[next - 1];
+    let (t1, v1) = keyframes[next];
+    lerp(v0, v1, (time - t0) / (t1 - t0))
+}
+
+fn sort_keyframes<V>(keyframes: &mut [(f32, V)]) {
+    assert!(
+        !keyframes.is_empty(),
+        "Animations need at least one keyframe!"
+    );
+    keyframes.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+}
+
+/// Like Translate, but the offset moves between keyframes of (time, offset), following the time of each ray.
+#[derive(Debug)]
+pub struct AnimatedTranslate<T: Hitable> {
+    obj: T,
+    keyframes: Vec<(f32, Vec3)>,
+}
+
+impl<T: Hitable> AnimatedTranslate<T> {
+    pub fn new(obj: T, mut keyframes: Vec<(f32, Vec3)>) -> AnimatedTranslate<T> {
+        sort_keyframes(&mut keyframes);
+        AnimatedTranslate { obj, keyframes }
+    }
+
+    fn offset(&self, time: f32) -> Vec3 {
+        interpolate(&self.keyframes, time, |a, b, s| a + s * (b - a))
+    }
+}
+
+impl<T: Hitable> Hitable for AnimatedTranslate<T> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let offset = self.offset(r.time());
+        let moved_r = Ray::new_at_time(r.origin() - offset, r.direction(), r.time());
 
-            HitRecord::new(rec.t, position, &r_rotated, normal, rec.material)
+        self.obj.hit(&moved_r, t_min, t_max).map(|mut rec| {
+            rec.position = rec.position + offset;
+            rec
         })
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let offset = self.offset(r.time());
+        let moved_r = Ray::new_at_time(r.origin() - offset, r.direction(), r.time());
+        self.obj.occluded(&moved_r, t_min, t_max)
+    }
+
+    //the offset moves in straight lines between keyframes, so the boxes at each keyframe cover all of it.
+    fn bounding_box(&self) -> Option<AABB> {
+        let output_box = self.obj.bounding_box()?;
+        self.keyframes
+            .iter()
+            .map(|&(_, offset)| AABB::new(output_box.min() + offset, output_box.max() + offset))
+            .fold(None, |acc, b| match acc {
+                Some(acc) => Some(AABB::surrounding_box(acc, b)),
+                None => Some(b),
+            })
+    }
+}
+
+/// Rotates an object around the y axis by an angle (in degrees) that moves between keyframes of
+/// (time, angle), following the time of each ray.
+#[derive(Debug)]
+pub struct AnimatedRotateY<T: Hitable> {
+    obj: T,
+    keyframes: Vec<(f32, f32)>,
+    aa_box: Option<AABB>,
+}
+
+impl<T: Hitable> AnimatedRotateY<T> {
+    pub fn new(obj: T, mut keyframes: Vec<(f32, f32)>) -> AnimatedRotateY<T> {
+        sort_keyframes(&mut keyframes);
+
+        // any angle can come up between keyframes, so we cover every point of the
+        // box swept all the way around the y axis.
+        let aa_box = obj.bounding_box().map(|bbox| {
+            let mut radius: f32 = 0.0;
+            for &x in [bbox.min().x(), bbox.max().x()].iter() {
+                for &z in [bbox.min().z(), bbox.max().z()].iter() {
+                    radius = radius.max((x * x + z * z).sqrt());
+                }
+            }
+            AABB::new(
+                Vec3::new(-radius, bbox.min().y(), -radius),
+                Vec3::new(radius, bbox.max().y(), radius),
+            )
+        });
+
+        AnimatedRotateY {
+            obj,
+            keyframes,
+            aa_box,
+        }
+    }
+
+    fn radians(&self, time: f32) -> f32 {
+        interpolate(&self.keyframes, time, |a, b, s| a + s * (b - a)).to_radians()
+    }
+}
+
+impl<T: Hitable> Hitable for AnimatedRotateY<T> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let radians = self.radians(r.time());
+        hit_rotated_y(&self.obj, r, t_min, t_max, radians.sin(), radians.cos())
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let radians = self.radians(r.time());
+        let r_rotated = rotate_ray_y(r, radians.sin(), radians.cos());
+        self.obj.occluded(&r_rotated, t_min, t_max)
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         self.aa_box
     }
@@ -248,7 +459,194 @@ impl Hitable for RectangularBox {
         self.sides.hit(r, t_min, t_max)
     }
 
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.sides.occluded(r, t_min, t_max)
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         self.sides.bounding_box()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::colour::*;
+    use crate::hit::check_occluded;
+    use crate::hit::sphere::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn instances_share_their_prototype() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let red: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.8, 0.1, 0.1)));
+        let prototype: Arc<dyn Hitable> =
+            Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, grey));
+
+        let instances: Vec<_> = (0..100)
+            .map(|i| {
+                let offset = Vec3::new(3.0 * i as f32, 0.0, 0.0);
+                Instance::new(
+                    Arc::clone(&prototype),
+                    Transform::new(
+                        Matrix4::translation(offset) * Matrix4::scale(Vec3::new(2.0, 2.0, 2.0)),
+                    ),
+                )
+                .with_material(Arc::clone(&red))
+            })
+            .collect();
+        assert_eq!(Arc::strong_count(&prototype), 101);
+
+        // the sphere at x = 30 has been scaled up to a radius of 2.
+        let r = Ray::new(Vec3::new(30.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = instances[10].hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 8.0).abs() < 1e-4);
+        assert!((rec.normal.z() - 1.0).abs() < 1e-4);
+        assert!(std::ptr::eq(
+            rec.material as *const dyn Material as *const u8,
+            &*red as *const dyn Material as *const u8
+        ));
+        assert!(instances[9].hit(&r, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn occluded_agrees_with_hit() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let sphere = |center: Vec3| Sphere::new(center, 1.0, Arc::clone(&grey));
+        let cube = || {
+            RectangularBox::new(
+                Vec3::new(0.5, -0.5, -0.5),
+                Vec3::new(1.5, 0.5, 0.5),
+                Arc::clone(&grey),
+            )
+        };
+        check_occluded(&FlipFace::new(sphere(Vec3::new(0.0, 0.0, 0.0))));
+        check_occluded(&Translate::new(
+            sphere(Vec3::new(0.0, 0.0, 0.0)),
+            Vec3::new(1.0, -1.0, 0.5),
+        ));
+        check_occluded(&Transformed::new(
+            cube(),
+            Transform::new(
+                Matrix4::translation(Vec3::new(-1.0, 0.0, 0.0))
+                    * Matrix4::rotation_y(30.0)
+                    * Matrix4::scale(Vec3::new(1.0, 2.0, 1.0)),
+            ),
+        ));
+        check_occluded(&AnimatedTranslate::new(
+            sphere(Vec3::new(0.0, 0.0, 0.0)),
+            vec![
+                (0.0, Vec3::new(0.0, 1.0, 0.0)),
+                (1.0, Vec3::new(0.0, 2.0, 0.0)),
+            ],
+        ));
+        check_occluded(&AnimatedRotateY::new(cube(), vec![(0.0, 0.0), (1.0, 90.0)]));
+    }
+
+    // checks the box holds every point in points, give or take rounding.
+    fn assert_contains(bbox: &AABB, points: &[Vec3], time: f32) {
+        for p in points {
+            for axis in 0..3 {
+                assert!(
+                    p.get(axis) >= bbox.min().get(axis) - 1e-4
+                        && p.get(axis) <= bbox.max().get(axis) + 1e-4,
+                    "{:?} at time {} is outside {:?}",
+                    p,
+                    time,
+                    bbox
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn animated_translations_follow_the_keyframes() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        // right, then up, given out of order.
+        let moving = AnimatedTranslate::new(
+            Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, grey),
+            vec![
+                (1.0, Vec3::new(4.0, 4.0, 0.0)),
+                (0.0, Vec3::new(0.0, 0.0, 0.0)),
+                (0.5, Vec3::new(4.0, 0.0, 0.0)),
+            ],
+        );
+        let bbox = moving.bounding_box().unwrap();
+
+        for i in 0..=20 {
+            let time = i as f32 / 20.0;
+            let center = if time < 0.5 {
+                Vec3::new(8.0 * time, 0.0, 0.0)
+            } else {
+                Vec3::new(4.0, 8.0 * (time - 0.5), 0.0)
+            };
+            let corner = Vec3::new(1.0, 1.0, 1.0);
+            assert_contains(&bbox, &[center - corner, center + corner], time);
+
+            let r = Ray::new_at_time(
+                center + Vec3::new(0.0, 0.0, 10.0),
+                Vec3::new(0.0, 0.0, -1.0),
+                time,
+            );
+            let rec = moving.hit(&r, 0.001, f32::MAX).unwrap();
+            assert!(
+                (rec.t - 9.0).abs() < 1e-4,
+                "hit at {} at time {}",
+                rec.t,
+                time
+            );
+            assert!((rec.position - center - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+
+            // a ray through where the sphere started misses once it has moved away.
+            let r = Ray::new_at_time(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0), time);
+            assert_eq!(moving.hit(&r, 0.001, f32::MAX).is_some(), time < 0.125);
+        }
+    }
+
+    #[test]
+    fn animated_rotations_follow_the_keyframes() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        // a unit cube centered 2.5 along x, turning a quarter of the way around the y axis.
+        let turning = AnimatedRotateY::new(
+            RectangularBox::new(Vec3::new(2.0, -0.5, -0.5), Vec3::new(3.0, 0.5, 0.5), grey),
+            vec![(0.0, 0.0), (1.0, 90.0)],
+        );
+        let bbox = turning.bounding_box().unwrap();
+
+        for i in 0..=20 {
+            let time = i as f32 / 20.0;
+            let radians = (90.0 * time).to_radians();
+            // turning anticlockwise seen from above takes x towards -z.
+            let outwards = Vec3::new(radians.cos(), 0.0, -radians.sin());
+            let sideways = Vec3::new(radians.sin(), 0.0, radians.cos());
+            let up = Vec3::new(0.0, 1.0, 0.0);
+            let corners: Vec<Vec3> = [2.0, 3.0]
+                .iter()
+                .flat_map(|&out| {
+                    [-0.5, 0.5].iter().flat_map(move |&side| {
+                        [-0.5, 0.5]
+                            .iter()
+                            .map(move |&y| out * outwards + side * sideways + y * up)
+                    })
+                })
+                .collect();
+            assert_contains(&bbox, &corners, time);
+
+            // straight in towards the y axis, onto the cube's outer face.
+            let r = Ray::new_at_time(10.0 * outwards, -outwards, time);
+            let rec = turning.hit(&r, 0.001, f32::MAX).unwrap();
+            assert!(
+                (rec.t - 7.0).abs() < 1e-3,
+                "hit at {} at time {}",
+                rec.t,
+                time
+            );
+            assert!(
+                (rec.normal - outwards).length() < 1e-3,
+                "normal {:?}",
+                rec.normal
+            );
+        }
+    }
+}
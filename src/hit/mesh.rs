@@ -0,0 +1,344 @@
+use crate::aabb::*;
+use crate::colour::*;
+use crate::ray::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::bvh::*;
+use super::triangle::*;
+use super::*;
+
+/// A triangle mesh with shared vertex buffers. Each triangle is just three indices into the buffers,
+/// and the mesh builds the same kind of BVH as BvhNode::new_sah over its triangles, so the whole
+/// mesh is a single object in the scene.
+#[derive(Debug)]
+pub struct Mesh {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<[f32; 2]>>,
+    colours: Option<Vec<Colour>>,
+    indices: Vec<[u32; 3]>, // reordered while building the BVH, so each leaf covers a run of triangles.
+    material: Arc<dyn Material>,
+    bvh: LinearBvh,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Vec3>, indices: Vec<[u32; 3]>, material: Arc<dyn Material>) -> Mesh {
+        assert!(!indices.is_empty(), "Can't make a mesh with no triangles!");
+        for tri in indices.iter() {
+            for &i in tri.iter() {
+                assert!(
+                    (i as usize) < positions.len(),
+                    "Mesh index {} is out of range of its {} vertices!",
+                    i,
+                    positions.len()
+                );
+            }
+        }
+
+        let boxes: Vec<_> = indices
+            .iter()
+            .map(|&tri| triangle_box(&positions, tri))
+            .collect();
+
+        // sort triangles by their place in the tree, and then put the indices in that order.
+        let (bvh, order) = LinearBvh::new_sah(&boxes);
+        let indices = order.iter().map(|&i| indices[i]).collect();
+        Mesh {
+            positions,
+            normals: None,
+            uvs: None,
+            colours: None,
+            indices,
+            material,
+            bvh,
+        }
+    }
+
+    /// Adds a normal per vertex, in the same order as the positions, which are blended across each
+    /// triangle to smooth out the shading.
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Mesh {
+        assert!(
+            normals.len() == self.positions.len(),
+            "Mesh has {} vertices, but {} normals!",
+            self.positions.len(),
+            normals.len()
+        );
+        self.normals = Some(normals);
+        self
+    }
+
+    /// Adds texture coordinates per vertex, in the same order as the positions.
+    pub fn with_uvs(mut self, uvs: Vec<[f32; 2]>) -> Mesh {
+        assert!(
+            uvs.len() == self.positions.len(),
+            "Mesh has {} vertices, but {} uvs!",
+            self.positions.len(),
+            uvs.len()
+        );
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Adds a linear colour per vertex, in the same order as the positions, which tints the material.
+    pub fn with_colours(mut self, colours: Vec<Colour>) -> Mesh {
+        assert!(
+            colours.len() == self.positions.len(),
+            "Mesh has {} vertices, but {} colours!",
+            self.positions.len(),
+            colours.len()
+        );
+        self.colours = Some(colours);
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> Option<&[Vec3]> {
+        self.normals.as_deref()
+    }
+
+    pub fn uvs(&self) -> Option<&[[f32; 2]]> {
+        self.uvs.as_deref()
+    }
+
+    pub fn colours(&self) -> Option<&[Colour]> {
+        self.colours.as_deref()
+    }
+
+    /// The triangles of the mesh. These are not in the order they were given in, as building the BVH sorts them.
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+
+    fn corners(&self, tri: usize) -> (Vec3, Vec3, Vec3) {
+        let [a, b, c] = self.indices[tri];
+        (
+            self.positions[a as usize],
+            self.positions[b as usize],
+            self.positions[c as usize],
+        )
+    }
+
+    fn hit_triangle(&self, tri: usize, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (a, b, c) = self.corners(tri);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let (t, u, v) = moller_trumbore(r, a, edge1, edge2, t_min, t_max)?;
+
+        let [ia, ib, ic] = self.indices[tri];
+        let uvs = self
+            .uvs
+            .as_ref()
+            .map(|uvs| [uvs[ia as usize], uvs[ib as usize], uvs[ic as usize]]);
+        let (tex_u, tex_v, dpdu, dpdv) = triangle_surface([a, b, c], uvs, u, v);
+
+        let rec = HitRecord::new(
+            t,
+            r.point_at_parameter(t),
+            r,
+            edge1.cross(edge2).unit_vector(),
+            &*self.material,
+        )
+        .with_surface(tex_u, tex_v, dpdu, dpdv)
+        .with_barycentric(u, v);
+        let rec = match &self.colours {
+            Some(colours) => rec.with_colour(
+                (1.0 - u - v) * colours[ia as usize]
+                    + u * colours[ib as usize]
+                    + v * colours[ic as usize],
+            ),
+            None => rec,
+        };
+
+        Some(match &self.normals {
+            Some(normals) => {
+                let corners = [
+                    normals[ia as usize],
+                    normals[ib as usize],
+                    normals[ic as usize],
+                ];
+                rec.with_shading_normal(interpolate_normal(corners, u, v))
+            }
+            None => rec,
+        })
+    }
+}
+
+// padded a little, so triangles lying flat along an axis still have a box that can be hit.
+fn triangle_box(positions: &[Vec3], [a, b, c]: [u32; 3]) -> AABB {
+    let (a, b, c) = (
+        positions[a as usize],
+        positions[b as usize],
+        positions[c as usize],
+    );
+    let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+    AABB::new(
+        Vec3::new(
+            a.x().min(b.x()).min(c.x()), //small
+            a.y().min(b.y()).min(c.y()),
+            a.z().min(b.z()).min(c.z()),
+        ) - pad,
+        Vec3::new(
+            a.x().max(b.x()).max(c.x()), // big
+            a.y().max(b.y()).max(c.y()),
+            a.z().max(b.z()).max(c.z()),
+        ) + pad,
+    )
+}
+
+impl Hitable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut stats = TraversalStats::default();
+        self.bvh
+            .traverse(r, t_min, t_max, true, &mut stats, |tri, closest_so_far| {
+                self.hit_triangle(tri, r, t_min, closest_so_far)
+            })
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.bvh.occluded(r, t_min, t_max, |tri| {
+            let (a, b, c) = self.corners(tri);
+            moller_trumbore(r, a, b - a, c - a, t_min, t_max).is_some()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bvh.bounding_box())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::material::lambertian::*;
+    use crate::Colour;
+
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn mesh_hits_match_triangles() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+
+        // a bumpy grid, with plenty of triangles lying flat along the axes.
+        let n = 16;
+        let mut positions = vec![];
+        for i in 0..=n {
+            for j in 0..=n {
+                let height = if (i + j) % 3 == 0 { 0.0 } else { 0.3 };
+                positions.push(Vec3::new(i as f32, height, j as f32));
+            }
+        }
+        let mut indices = vec![];
+        for i in 0..n {
+            for j in 0..n {
+                let a = (i * (n + 1) + j) as u32;
+                let b = a + 1;
+                let c = a + (n + 1) as u32;
+                indices.push([a, b, c]);
+                indices.push([b, c + 1, c]);
+            }
+        }
+
+        let triangles: Vec<_> = indices
+            .iter()
+            .map(|&[a, b, c]| {
+                Triangle::new(
+                    positions[a as usize],
+                    positions[b as usize],
+                    positions[c as usize],
+                    Arc::clone(&mat),
+                )
+            })
+            .collect();
+        let mesh = Mesh::new(positions, indices, Arc::clone(&mat));
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let origin = Vec3::new(
+                rng.gen::<f32>() * n as f32,
+                5.0,
+                rng.gen::<f32>() * n as f32,
+            );
+            let direction = Vec3::new(rng.gen::<f32>() - 0.5, -1.0, rng.gen::<f32>() - 0.5);
+            let r = Ray::new(origin, direction);
+
+            let expected = triangles
+                .iter()
+                .filter_map(|tri| tri.hit(&r, 0.001, f32::MAX))
+                .map(|rec| rec.t)
+                .fold(None, |acc: Option<f32>, t| {
+                    Some(acc.map_or(t, |a| a.min(t)))
+                });
+            let found = mesh.hit(&r, 0.001, f32::MAX).map(|rec| rec.t);
+
+            assert_eq!(expected, found);
+        }
+    }
+
+    #[test]
+    fn badly_spread_meshes_match_triangles() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+
+        // a pile of triangles in the same place, which can't be told apart, and a row of them getting
+        // further apart, which the surface area heuristic splits off one at a time.
+        let mut positions = vec![];
+        for _ in 0..200 {
+            positions.push(Vec3::new(-1.0, -1.0, 0.0));
+            positions.push(Vec3::new(1.0, -1.0, 0.0));
+            positions.push(Vec3::new(0.0, 1.0, 0.0));
+        }
+        for i in 0..120 {
+            let x = 2.0 * 2.0f32.powi(i);
+            let size = 0.1 * x;
+            positions.push(Vec3::new(x, -size, -size));
+            positions.push(Vec3::new(x, size, -size));
+            positions.push(Vec3::new(x, 0.0, size));
+        }
+        let indices: Vec<_> = (0..positions.len() as u32 / 3)
+            .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+            .collect();
+        let triangles: Vec<_> = indices
+            .iter()
+            .map(|&[a, b, c]| {
+                Triangle::new(
+                    positions[a as usize],
+                    positions[b as usize],
+                    positions[c as usize],
+                    Arc::clone(&mat),
+                )
+            })
+            .collect();
+        let mesh = Mesh::new(positions, indices, Arc::clone(&mat));
+
+        for i in 0..120 {
+            // along the row, starting just past each of its triangles in turn.
+            let x = 2.0 * 2.0f32.powi(i) * 1.2;
+            let r = Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::new(1.0, 0.01, 0.0));
+            let back = Ray::new(Vec3::new(x, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.01));
+            for r in [r, back].iter() {
+                let expected = triangles
+                    .iter()
+                    .filter_map(|tri| tri.hit(r, 0.001, f32::MAX))
+                    .map(|rec| rec.t)
+                    .fold(None, |acc: Option<f32>, t| {
+                        Some(acc.map_or(t, |a| a.min(t)))
+                    });
+                assert_eq!(mesh.hit(r, 0.001, f32::MAX).map(|rec| rec.t), expected);
+                assert_eq!(mesh.occluded(r, 0.001, f32::MAX), expected.is_some());
+            }
+        }
+    }
+}
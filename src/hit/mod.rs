@@ -1,24 +1,51 @@
 pub mod bvh;
+pub mod cone;
 pub mod constant_medium;
+pub mod csg;
+pub mod curve;
+pub mod cylinder;
+pub mod disk;
+pub mod displacement;
+pub mod heightfield;
 pub mod hitable_list;
 pub mod instancing;
+pub mod mesh;
+pub mod quad;
 pub mod rectangle;
+pub mod sdf;
 pub mod sphere;
+pub mod subdivision;
+pub mod torus;
 pub mod triangle;
 
 use super::aabb::*;
+use super::colour::*;
 use super::material::*;
 use super::ray::*;
+use super::util::*;
 use super::vec3::*;
 
 //data about a ray hitting something.
+//both normals face against the incoming ray.
 #[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub t: f32,
     pub position: Vec3,
-    pub normal: Vec3,
+    pub normal: Vec3,           //the normal used for shading, which may be smoothed.
+    pub geometric_normal: Vec3, //the normal of the actual surface that was hit.
     pub front_face: bool,
     pub material: &'a dyn Material,
+
+    //where on the surface the hit was, for textures. u and v are usually between 0 and 1.
+    pub u: f32,
+    pub v: f32,
+    //how the position changes with u and v. Both are zero on surfaces without a parameterisation.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    //the barycentric coordinates of the hit, for triangles.
+    pub barycentric: Option<[f32; 2]>,
+    //the colour blended from the vertices, for meshes that have them.
+    pub colour: Option<Colour>,
 }
 
 impl<'a> HitRecord<'a> {
@@ -40,14 +67,220 @@ impl<'a> HitRecord<'a> {
             t,
             position,
             normal,
+            geometric_normal: normal,
             front_face,
             material,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
+            barycentric: None,
+            colour: None,
+        }
+    }
+
+    //replaces the shading normal with an outward facing one, such as one interpolated from vertex normals.
+    pub fn with_shading_normal(mut self, outward_normal: Vec3) -> HitRecord<'a> {
+        self.normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        self
+    }
+
+    //sets the surface coordinates of the hit, and how the position changes along them.
+    pub fn with_surface(mut self, u: f32, v: f32, dpdu: Vec3, dpdv: Vec3) -> HitRecord<'a> {
+        self.u = u;
+        self.v = v;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn with_barycentric(mut self, u: f32, v: f32) -> HitRecord<'a> {
+        self.barycentric = Some([u, v]);
+        self
+    }
+
+    pub fn with_colour(mut self, colour: Colour) -> HitRecord<'a> {
+        self.colour = Some(colour);
+        self
+    }
+
+    //a unit vector along dpdu, at right angles to the shading normal. It's worked out when asked
+    //for, as most materials don't need it.
+    pub fn tangent(&self) -> Vec3 {
+        let tangent = self.dpdu - self.normal * self.normal.dot(self.dpdu);
+        if tangent.squared_length() > 0.0 {
+            tangent.unit_vector()
+        } else {
+            //there's no dpdu, or it runs along the normal, which happens at the poles of a sphere.
+            //the tangent frame is still there, even if it's an arbitrary one.
+            orthonormal_basis(self.normal).0
         }
     }
+
+    //completes the tangent frame, with the normal and the tangent.
+    pub fn bitangent(&self) -> Vec3 {
+        self.normal.cross(self.tangent())
+    }
 }
 
 //implemented by objects in the scene, so they can be hit by the rays.
 pub trait Hitable: Sync + Send + std::fmt::Debug {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
     fn bounding_box(&self) -> Option<AABB>;
+
+    /// Whether anything is hit between t_min and t_max, for shadow rays and the like, which only need
+    /// to know if something is in the way. Objects can stop at the first hit they find, rather than
+    /// searching for the nearest, and skip filling in a HitRecord.
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    /// The box around just the part of the object inside bounds, used when a BVH splits objects
+    /// between its nodes. By default this is where the bounding box overlaps bounds, and objects
+    /// can give tighter boxes by clipping their actual shape.
+    fn clipped_box(&self, bounds: &AABB) -> Option<AABB> {
+        AABB::overlap(self.bounding_box()?, *bounds)
+    }
+}
+
+//implemented by shapes that can be used as area lights, by picking points on their surface.
+pub trait Sampleable: Hitable {
+    fn area(&self) -> f32;
+    //returns a uniformly random point on the surface, and the outward normal there.
+    fn sample(&self) -> (Vec3, Vec3);
+}
+
+//checks an object's occluded agrees with its hit, for rays fired from all around it at times
+//across the shutter, which stop at different distances so that some fall short.
+#[cfg(test)]
+pub(crate) fn check_occluded(object: &dyn Hitable) {
+    let mut hits = 0;
+    for i in 0..200 {
+        let i = i as f32;
+        let origin = Vec3::new(
+            6.0 * (i * 0.29).cos(),
+            3.0 * (i * 0.83).sin(),
+            6.0 * (i * 0.29).sin(),
+        );
+        let target = Vec3::new((i * 1.7).sin(), 1.5 * (i * 0.43).cos(), (i * 0.91).sin());
+        let r = Ray::new_at_time(origin, target - origin, (i * 0.13).fract());
+
+        let t_max = 0.6 + 0.6 * (i * 0.37).sin().abs();
+        let expected = object.hit(&r, 0.001, t_max).is_some();
+        hits += expected as usize;
+        assert_eq!(object.occluded(&r, 0.001, t_max), expected, "ray {}", i);
+    }
+    assert!(hits > 10, "Only {} of the rays hit {:?}!", hits, object);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cone::*;
+    use super::cylinder::*;
+    use super::disk::*;
+    use super::quad::*;
+    use super::sphere::*;
+    use super::torus::*;
+    use super::*;
+    use crate::material::lambertian::*;
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+
+    //checks each shape's area is as expected, and that its samples land on the front of its
+    //surface, by shooting a short ray back at each one along its normal.
+    #[test]
+    fn samples_lie_on_surface() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let normal = Vec3::new(1.0, 2.0, -1.0);
+        let base = Vec3::new(0.0, 1.0, 0.0);
+        let top = Vec3::new(1.0, 3.0, 2.0);
+        let apex = Vec3::new(0.0, 1.0, 4.0);
+        let u = Vec3::new(2.0, 0.0, 1.0);
+        let v = Vec3::new(0.0, 3.0, 0.0);
+        let shapes: Vec<(Box<dyn Sampleable>, f32)> = vec![
+            (
+                Box::new(Sphere::new(Vec3::new(1.0, 2.0, 3.0), 2.0, grey.clone())),
+                16.0 * PI,
+            ),
+            (
+                Box::new(Disk::new(
+                    Vec3::new(1.0, 0.0, 2.0),
+                    normal,
+                    1.5,
+                    grey.clone(),
+                )),
+                PI * 1.5 * 1.5,
+            ),
+            (
+                Box::new(Disk::annulus(
+                    Vec3::new(1.0, 0.0, 2.0),
+                    normal,
+                    1.5,
+                    0.5,
+                    grey.clone(),
+                )),
+                PI * (1.5 * 1.5 - 0.5 * 0.5),
+            ),
+            (
+                Box::new(Cylinder::new(base, top, 0.5, grey.clone())),
+                2.0 * PI * 0.5 * 3.0 + 2.0 * PI * 0.5 * 0.5,
+            ),
+            (
+                Box::new(Cylinder::open(base, top, 0.5, grey.clone())),
+                2.0 * PI * 0.5 * 3.0,
+            ),
+            (
+                Box::new(Cone::new(base, apex, 3.0, grey.clone())),
+                PI * 3.0 * 5.0 + PI * 3.0 * 3.0,
+            ),
+            (
+                Box::new(Cone::open(base, apex, 3.0, grey.clone())),
+                PI * 3.0 * 5.0,
+            ),
+            (
+                Box::new(Quad::new(Vec3::new(-1.0, 0.5, 2.0), u, v, grey.clone())),
+                3.0 * 5.0f32.sqrt(),
+            ),
+            (
+                Box::new(Torus::new(
+                    Vec3::new(0.0, 1.0, 2.0),
+                    Vec3::new(1.0, 1.0, 0.0),
+                    2.0,
+                    0.5,
+                    grey,
+                )),
+                4.0 * PI * PI * 2.0 * 0.5,
+            ),
+        ];
+
+        for (shape, area) in &shapes {
+            assert!(
+                (shape.area() - area).abs() < 1e-4 * area,
+                "{:?}: area {} isn't {}",
+                shape,
+                shape.area(),
+                area
+            );
+            for _ in 0..1000 {
+                let (p, normal) = shape.sample();
+                assert!((normal.length() - 1.0).abs() < 1e-4, "normal {:?}", normal);
+                let r = Ray::new(p + 1e-3 * normal, -normal);
+                let rec = shape
+                    .hit(&r, 0.0, 2e-3)
+                    .unwrap_or_else(|| panic!("{:?}: sample {:?} isn't on the surface", shape, p));
+                assert!(
+                    (rec.t - 1e-3).abs() < 1e-4,
+                    "{:?}: sample {:?} hit at {}",
+                    shape,
+                    p,
+                    rec.t
+                );
+                assert!(rec.front_face, "{:?}: sample {:?} faces inwards", shape, p);
+            }
+        }
+    }
 }
@@ -0,0 +1,106 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::*;
+
+/// A parallelogram with one corner at corner, and two edges along u and v.
+/// The front of it faces along u x v.
+#[derive(Debug)]
+pub struct Quad {
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    // n / (n . n) for the unnormalised n = u x v, which gives the coordinates along the edges.
+    w: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Quad {
+    pub fn new(corner: Vec3, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Quad {
+        let n = u.cross(v);
+        assert!(
+            n.length() > 0.0,
+            "A quad's edges {:?} and {:?} must not be parallel!",
+            u,
+            v
+        );
+        Quad {
+            corner,
+            u,
+            v,
+            normal: n.unit_vector(),
+            w: n / n.dot(n),
+            material,
+        }
+    }
+}
+
+impl Quad {
+    // where the ray hits the quad, along with the point it hits and how far across u and v it is.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, Vec3, f32, f32)> {
+        let denominator = self.normal.dot(r.direction());
+        if denominator == 0.0 {
+            return None;
+        }
+        let t = self.normal.dot(self.corner - r.origin()) / denominator;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let position = r.point_at_parameter(t);
+        let planar = position - self.corner;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some((t, position, alpha, beta))
+    }
+}
+
+impl Hitable for Quad {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, position, alpha, beta) = self.intersect(r, t_min, t_max)?;
+        Some(
+            HitRecord::new(t, position, r, self.normal, &*self.material)
+                .with_surface(alpha, beta, self.u, self.v),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    // padded a little, as the quad is flat.
+    fn bounding_box(&self) -> Option<AABB> {
+        let corners = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for c in corners.iter() {
+            min = Vec3::new(min.x().min(c.x()), min.y().min(c.y()), min.z().min(c.z()));
+            max = Vec3::new(max.x().max(c.x()), max.y().max(c.y()), max.z().max(c.z()));
+        }
+        let padding = Vec3::new(0.0001, 0.0001, 0.0001);
+        Some(AABB::new(min - padding, max + padding))
+    }
+}
+
+impl Sampleable for Quad {
+    fn area(&self) -> f32 {
+        self.u.cross(self.v).length()
+    }
+
+    fn sample(&self) -> (Vec3, Vec3) {
+        let p = self.corner + rand::random::<f32>() * self.u + rand::random::<f32>() * self.v;
+        (p, self.normal)
+    }
+}
@@ -95,8 +95,9 @@ impl XzRectangle {
     }
 }
 
-impl Hitable for XyRectangle {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl XyRectangle {
+    // where the ray crosses the rectangle, and the two coordinates of the point it crosses at.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
         let t = (self.k - r.origin().z()) / r.direction().z();
         if t < t_min || t > t_max {
             return None;
@@ -108,15 +109,35 @@ impl Hitable for XyRectangle {
             return None;
         }
 
+        Some((t, x, y))
+    }
+}
+
+impl Hitable for XyRectangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, x, y) = self.intersect(r, t_min, t_max)?;
+
         let outward_normal = Vec3::new(0.0, 0.0, 1.0);
 
-        Some(HitRecord::new(
-            t,
-            r.point_at_parameter(t),
-            r,
-            outward_normal,
-            &*self.material,
-        ))
+        Some(
+            HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                r,
+                outward_normal,
+                &*self.material,
+            )
+            .with_surface(
+                (x - self.x0) / (self.x1 - self.x0),
+                (y - self.y0) / (self.y1 - self.y0),
+                Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+                Vec3::new(0.0, self.y1 - self.y0, 0.0),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
     }
 
     fn bounding_box(&self) -> Option<AABB> {
@@ -127,8 +148,8 @@ impl Hitable for XyRectangle {
     }
 }
 
-impl Hitable for YzRectangle {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl YzRectangle {
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
         let t = (self.k - r.origin().x()) / r.direction().x();
         if t < t_min || t > t_max {
             return None;
@@ -140,15 +161,35 @@ impl Hitable for YzRectangle {
             return None;
         }
 
+        Some((t, y, z))
+    }
+}
+
+impl Hitable for YzRectangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, y, z) = self.intersect(r, t_min, t_max)?;
+
         let outward_normal = Vec3::new(1.0, 0.0, 0.0);
 
-        Some(HitRecord::new(
-            t,
-            r.point_at_parameter(t),
-            r,
-            outward_normal,
-            &*self.material,
-        ))
+        Some(
+            HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                r,
+                outward_normal,
+                &*self.material,
+            )
+            .with_surface(
+                (y - self.y0) / (self.y1 - self.y0),
+                (z - self.z0) / (self.z1 - self.z0),
+                Vec3::new(0.0, self.y1 - self.y0, 0.0),
+                Vec3::new(0.0, 0.0, self.z1 - self.z0),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
     }
 
     fn bounding_box(&self) -> Option<AABB> {
@@ -159,8 +200,8 @@ impl Hitable for YzRectangle {
     }
 }
 
-impl Hitable for XzRectangle {
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl XzRectangle {
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
         let t = (self.k - r.origin().y()) / r.direction().y();
         if t < t_min || t > t_max {
             return None;
@@ -172,15 +213,35 @@ impl Hitable for XzRectangle {
             return None;
         }
 
+        Some((t, x, z))
+    }
+}
+
+impl Hitable for XzRectangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t, x, z) = self.intersect(r, t_min, t_max)?;
+
         let outward_normal = Vec3::new(0.0, 1.0, 0.0);
 
-        Some(HitRecord::new(
-            t,
-            r.point_at_parameter(t),
-            r,
-            outward_normal,
-            &*self.material,
-        ))
+        Some(
+            HitRecord::new(
+                t,
+                r.point_at_parameter(t),
+                r,
+                outward_normal,
+                &*self.material,
+            )
+            .with_surface(
+                (x - self.x0) / (self.x1 - self.x0),
+                (z - self.z0) / (self.z1 - self.z0),
+                Vec3::new(self.x1 - self.x0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, self.z1 - self.z0),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
     }
 
     fn bounding_box(&self) -> Option<AABB> {
@@ -0,0 +1,460 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::*;
+
+/// A shape given by the distance to its surface, which is negative inside it.
+/// The distance can be an underestimate, but never more than the real distance,
+/// as the ray marcher relies on not being able to reach the surface in a smaller step.
+pub trait DistanceField: Sync + Send + std::fmt::Debug {
+    fn distance(&self, p: Vec3) -> f32;
+}
+
+/// Renders a distance field by sphere tracing it inside a box, which has to contain the whole
+/// surface. The box is what goes into the BVH, so keeping it tight saves a lot of marching.
+#[derive(Debug)]
+pub struct Sdf<F: DistanceField> {
+    field: F,
+    aa_box: AABB,
+    material: Arc<dyn Material>,
+    max_steps: usize,
+    epsilon: f32,
+}
+
+impl<F: DistanceField> Sdf<F> {
+    pub fn new(field: F, min: Vec3, max: Vec3, material: Arc<dyn Material>) -> Sdf<F> {
+        Sdf {
+            field,
+            aa_box: AABB::new(min, max),
+            material,
+            max_steps: 256,
+            epsilon: 1e-4,
+        }
+    }
+
+    /// How many steps a ray can take before it is counted as a miss, which is 256 by default.
+    /// Rays that graze the surface take the most steps.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Sdf<F> {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// How close to the surface counts as a hit, which is 0.0001 by default.
+    pub fn with_epsilon(mut self, epsilon: f32) -> Sdf<F> {
+        self.epsilon = epsilon;
+        self
+    }
+
+    pub fn field(&self) -> &F {
+        &self.field
+    }
+
+    // the outward normal, from the gradient of the field, using the four corners of a tetrahedron.
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let h = self.epsilon;
+        let corners = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        let gradient = corners.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &c| {
+            sum + c * self.field.distance(p + h * c)
+        });
+        if gradient.squared_length() > 0.0 {
+            gradient.unit_vector()
+        } else {
+            Vec3::new(0.0, 1.0, 0.0)
+        }
+    }
+
+    // the part of the ray between t_min and t_max that is inside the box.
+    fn clip(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for i in 0..3 {
+            let inv_d = r.inv_direction().get(i);
+            let mut near = (self.aa_box.min().get(i) - r.origin().get(i)) * inv_d;
+            let mut far = (self.aa_box.max().get(i) - r.origin().get(i)) * inv_d;
+            if r.dir_sign()[i] {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    // marches along the ray to the first t where it reaches the surface.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+        let (mut t, t_end) = self.clip(r, t_min, t_max)?;
+        let length = r.direction().length();
+
+        // rays leaving the surface start right on it, so they have to get clear of it before
+        // anything counts as a hit. Until then, which side they are on isn't known.
+        let mut side = 0.0;
+        for _ in 0..self.max_steps {
+            let distance = self.field.distance(r.point_at_parameter(t));
+            if side == 0.0 {
+                if distance.abs() >= self.epsilon {
+                    side = distance.signum();
+                } else {
+                    t += self.epsilon / length;
+                    if t >= t_end {
+                        return None;
+                    }
+                    continue;
+                }
+            }
+
+            // a negative distance means the step went through the surface, which can happen
+            // when the field isn't exact.
+            let distance = side * distance;
+            if distance < self.epsilon {
+                return Some(t);
+            }
+
+            t += distance / length;
+            if t >= t_end {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+impl<F: DistanceField> Hitable for Sdf<F> {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = self.intersect(r, t_min, t_max)?;
+        let position = r.point_at_parameter(t);
+        Some(HitRecord::new(
+            t,
+            position,
+            r,
+            self.normal(position),
+            &*self.material,
+        ))
+    }
+
+    // the normal takes several more steps through the field, which shadow rays can skip.
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.aa_box)
+    }
+}
+
+/// A smooth minimum of a and b, which rounds off the crease where they meet over a width of about k.
+pub fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + h * (a - b) - k * h * (1.0 - h)
+}
+
+#[derive(Debug)]
+pub struct SdfSphere {
+    radius: f32,
+}
+
+impl SdfSphere {
+    pub fn new(radius: f32) -> SdfSphere {
+        SdfSphere { radius }
+    }
+}
+
+impl DistanceField for SdfSphere {
+    fn distance(&self, p: Vec3) -> f32 {
+        p.length() - self.radius
+    }
+}
+
+/// A box centered on the origin, with its edges rounded off by radius.
+/// The radius is taken off the box, so it still fits inside half_extents.
+#[derive(Debug)]
+pub struct RoundedBox {
+    half_extents: Vec3,
+    radius: f32,
+}
+
+impl RoundedBox {
+    pub fn new(half_extents: Vec3, radius: f32) -> RoundedBox {
+        RoundedBox {
+            half_extents,
+            radius,
+        }
+    }
+}
+
+impl DistanceField for RoundedBox {
+    fn distance(&self, p: Vec3) -> f32 {
+        let q = Vec3::new(
+            p.x().abs() - self.half_extents.x() + self.radius,
+            p.y().abs() - self.half_extents.y() + self.radius,
+            p.z().abs() - self.half_extents.z() + self.radius,
+        );
+        let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0)).length();
+        let inside = q.x().max(q.y()).max(q.z()).min(0.0);
+        outside + inside - self.radius
+    }
+}
+
+/// A ring around the y axis.
+#[derive(Debug)]
+pub struct SdfTorus {
+    major_radius: f32,
+    minor_radius: f32,
+}
+
+impl SdfTorus {
+    pub fn new(major_radius: f32, minor_radius: f32) -> SdfTorus {
+        SdfTorus {
+            major_radius,
+            minor_radius,
+        }
+    }
+}
+
+impl DistanceField for SdfTorus {
+    fn distance(&self, p: Vec3) -> f32 {
+        let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (ring * ring + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+}
+
+/// The Mandelbulb fractal, which fits inside a sphere of radius 1.2 for the usual power of 8.
+#[derive(Debug)]
+pub struct Mandelbulb {
+    power: f32,
+    iterations: usize,
+}
+
+impl Mandelbulb {
+    pub fn new(power: f32, iterations: usize) -> Mandelbulb {
+        Mandelbulb { power, iterations }
+    }
+}
+
+impl DistanceField for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f32 {
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2.0 || r == 0.0 {
+                break;
+            }
+            // raise z to the power in spherical coordinates, keeping track of the derivative.
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            z = r.powf(self.power)
+                * Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                )
+                + p;
+        }
+        if r == 0.0 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+#[derive(Debug)]
+pub struct Translated<F: DistanceField> {
+    field: F,
+    offset: Vec3,
+}
+
+impl<F: DistanceField> Translated<F> {
+    pub fn new(field: F, offset: Vec3) -> Translated<F> {
+        Translated { field, offset }
+    }
+}
+
+impl<F: DistanceField> DistanceField for Translated<F> {
+    fn distance(&self, p: Vec3) -> f32 {
+        self.field.distance(p - self.offset)
+    }
+}
+
+/// Both fields blended together with smooth_min, so they melt into each other where they meet.
+/// With a k of 0, this is an ordinary union.
+#[derive(Debug)]
+pub struct SmoothUnion<A: DistanceField, B: DistanceField> {
+    a: A,
+    b: B,
+    k: f32,
+}
+
+impl<A: DistanceField, B: DistanceField> SmoothUnion<A, B> {
+    pub fn new(a: A, b: B, k: f32) -> SmoothUnion<A, B> {
+        SmoothUnion { a, b, k }
+    }
+}
+
+impl<A: DistanceField, B: DistanceField> DistanceField for SmoothUnion<A, B> {
+    fn distance(&self, p: Vec3) -> f32 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.k)
+    }
+}
+
+/// Twists the field around the y axis, by rate radians for each unit up it.
+#[derive(Debug)]
+pub struct Twist<F: DistanceField> {
+    field: F,
+    rate: f32,
+}
+
+impl<F: DistanceField> Twist<F> {
+    pub fn new(field: F, rate: f32) -> Twist<F> {
+        Twist { field, rate }
+    }
+}
+
+impl<F: DistanceField> DistanceField for Twist<F> {
+    fn distance(&self, p: Vec3) -> f32 {
+        let (sin, cos) = (self.rate * p.y()).sin_cos();
+        let q = Vec3::new(cos * p.x() - sin * p.z(), p.y(), sin * p.x() + cos * p.z());
+        // twisting stretches space further from the axis, so the distance is scaled down to
+        // stay an underestimate.
+        let radius2 = p.x() * p.x() + p.z() * p.z();
+        self.field.distance(q) / (1.0 + self.rate * self.rate * radius2).sqrt()
+    }
+}
+
+/// Repeats the field forever, once in each cell of size period. Axes with a period of 0 aren't
+/// repeated. The field should fit inside a cell centered on the origin.
+#[derive(Debug)]
+pub struct Repeat<F: DistanceField> {
+    field: F,
+    period: Vec3,
+}
+
+impl<F: DistanceField> Repeat<F> {
+    pub fn new(field: F, period: Vec3) -> Repeat<F> {
+        Repeat { field, period }
+    }
+}
+
+impl<F: DistanceField> DistanceField for Repeat<F> {
+    fn distance(&self, p: Vec3) -> f32 {
+        let wrap = |x: f32, period: f32| {
+            if period > 0.0 {
+                x - period * (x / period).round()
+            } else {
+                x
+            }
+        };
+        self.field.distance(Vec3::new(
+            wrap(p.x(), self.period.x()),
+            wrap(p.y(), self.period.y()),
+            wrap(p.z(), self.period.z()),
+        ))
+    }
+}
+
+/// Moves the surface out by the displacement at each point, or in where it is negative.
+/// max_slope bounds how fast the displacement can change, which keeps the steps safe.
+pub struct Displace<F: DistanceField, D: Fn(Vec3) -> f32 + Sync + Send> {
+    field: F,
+    displacement: D,
+    max_slope: f32,
+}
+
+impl<F: DistanceField, D: Fn(Vec3) -> f32 + Sync + Send> Displace<F, D> {
+    pub fn new(field: F, displacement: D, max_slope: f32) -> Displace<F, D> {
+        Displace {
+            field,
+            displacement,
+            max_slope,
+        }
+    }
+}
+
+impl<F: DistanceField, D: Fn(Vec3) -> f32 + Sync + Send> std::fmt::Debug for Displace<F, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Displace")
+            .field("field", &self.field)
+            .field("max_slope", &self.max_slope)
+            .finish()
+    }
+}
+
+impl<F: DistanceField, D: Fn(Vec3) -> f32 + Sync + Send> DistanceField for Displace<F, D> {
+    fn distance(&self, p: Vec3) -> f32 {
+        (self.field.distance(p) - (self.displacement)(p)) / (1.0 + self.max_slope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn sdf_hits_match_the_analytic_shapes() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let sphere = Sdf::new(
+            SdfSphere::new(1.0),
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Arc::clone(&mat),
+        );
+
+        let r = Ray::new(Vec3::new(-5.0, 0.2, 0.0), Vec3::new(2.0, 0.0, 0.0));
+        let rec = sphere.hit(&r, 0.001, f32::MAX).unwrap();
+        let expected = (5.0 - (1.0f32 - 0.04).sqrt()) / 2.0;
+        assert!((rec.t - expected).abs() < 1e-3, "hit at {}", rec.t);
+        assert!((rec.normal - rec.position).length() < 1e-2);
+
+        // leaving the surface from inside reaches the far side, rather than where it started.
+        let inside = Ray::new(rec.position, Vec3::new(1.0, 0.0, 0.0));
+        let exit = sphere.hit(&inside, 0.0, f32::MAX).unwrap();
+        assert!((exit.position.x() - (1.0f32 - 0.04).sqrt()).abs() < 1e-3);
+        assert!(!exit.front_face);
+
+        // repeated spheres, one every 4 units along x, seen end on.
+        let row = Sdf::new(
+            Repeat::new(SdfSphere::new(1.0), Vec3::new(4.0, 0.0, 0.0)),
+            Vec3::new(-100.0, -1.0, -1.0),
+            Vec3::new(100.0, 1.0, 1.0),
+            mat,
+        );
+        let r = Ray::new(Vec3::new(2.0, 5.0, 0.0), Vec3::new(4.0, -1.0, 0.0));
+        assert!(row.hit(&r, 0.001, f32::MAX).is_some());
+        let gap = Ray::new(Vec3::new(2.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(row.hit(&gap, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn smooth_min_blends_near_the_crease() {
+        assert_eq!(smooth_min(1.0, 3.0, 0.5), 1.0);
+        assert_eq!(smooth_min(1.0, 3.0, 0.0), 1.0);
+        assert!(smooth_min(1.0, 1.0, 0.5) < 1.0);
+    }
+
+    #[test]
+    fn sdf_occluded_agrees_with_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        check_occluded(&Sdf::new(
+            SdfSphere::new(1.0),
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            mat,
+        ));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::hit::*;
 use crate::ray::*;
+use crate::util::*;
 use crate::vec3::*;
 use std::sync::Arc;
 
@@ -22,9 +23,9 @@ impl Sphere {
     }
 }
 
-impl Hitable for Sphere {
-    //#[inline]
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+impl Sphere {
+    // the nearest place between t_min and t_max where the ray crosses the sphere.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
         let oc = r.origin() - self.center;
         let a = r.direction().dot(r.direction());
         let b = oc.dot(r.direction());
@@ -35,28 +36,67 @@ impl Hitable for Sphere {
             let temp = (-b - discriminant.sqrt()) / a;
 
             if temp < t_max && temp > t_min {
-                return Some(HitRecord::new(
-                    temp,
-                    r.point_at_parameter(temp),
-                    r,
-                    (r.point_at_parameter(temp) - self.center) / self.radius,
-                    &*self.material,
-                ));
+                return Some(temp);
             }
             let temp = (-b + discriminant.sqrt()) / a;
             if temp < t_max && temp > t_min {
-                return Some(HitRecord::new(
-                    temp,
-                    r.point_at_parameter(temp),
-                    r,
-                    (r.point_at_parameter(temp) - self.center) / self.radius,
-                    &*self.material,
-                ));
+                return Some(temp);
             }
         }
         None
     }
 
+    fn record(&self, r: &Ray, t: f32) -> HitRecord {
+        let position = r.point_at_parameter(t);
+        let (u, v, dpdu, dpdv) = self.surface(position);
+
+        HitRecord::new(
+            t,
+            position,
+            r,
+            (position - self.center) / self.radius,
+            &*self.material,
+        )
+        .with_surface(u, v, dpdu, dpdv)
+    }
+
+    // u goes around the sphere from -x, and v from the bottom to the top.
+    fn surface(&self, position: Vec3) -> (f32, f32, Vec3, Vec3) {
+        let radius = self.radius.abs();
+        let p = position - self.center;
+        let pi = std::f32::consts::PI;
+
+        let theta = (-p.y() / radius).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z()).atan2(p.x()) + pi;
+
+        let dpdu = 2.0 * pi * Vec3::new(p.z(), 0.0, -p.x());
+
+        //at the poles, there's no single way down the sphere.
+        let sin_theta = theta.sin();
+        let dpdv = if sin_theta > 0.0 {
+            pi * Vec3::new(
+                -p.x() * p.y() / (radius * sin_theta),
+                radius * sin_theta,
+                -p.z() * p.y() / (radius * sin_theta),
+            )
+        } else {
+            Vec3::new(0.0, 0.0, 0.0)
+        };
+
+        (phi / (2.0 * pi), theta / pi, dpdu, dpdv)
+    }
+}
+
+impl Hitable for Sphere {
+    //#[inline]
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.intersect(r, t_min, t_max).map(|t| self.record(r, t))
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
     fn bounding_box(&self) -> Option<AABB> {
         Some(AABB::new(
             self.center - Vec3::new(self.radius, self.radius, self.radius),
@@ -65,6 +105,47 @@ impl Hitable for Sphere {
     }
 }
 
+impl Sampleable for Sphere {
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * self.radius2
+    }
+
+    fn sample(&self) -> (Vec3, Vec3) {
+        let normal = random_in_unit_sphere().unit_vector();
+        (self.center + self.radius.abs() * normal, normal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::material::dielectric::*;
+
+    #[test]
+    fn sphere_derivatives_follow_uv() {
+        let sphere = Sphere::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            2.0,
+            Arc::new(Dielectric::new(1.5)),
+        );
+        let ray = Ray::new(Vec3::new(5.0, 3.0, 4.0), Vec3::new(-1.0, -0.2, -0.3));
+        let rec = sphere.hit(&ray, 0.001, 100.0).unwrap();
+
+        // a small step along dpdu or dpdv, pushed back on to the sphere, should move u or v by that step.
+        let step = 0.001;
+        let on_sphere = |p: Vec3| sphere.center + (p - sphere.center).unit_vector() * 2.0;
+
+        let (u, v, _, _) = sphere.surface(on_sphere(rec.position + step * rec.dpdu));
+        assert!((u - rec.u - step).abs() < 1e-4, "u moved by {}", u - rec.u);
+        assert!((v - rec.v).abs() < 1e-4, "v moved by {}", v - rec.v);
+
+        let (u, v, _, _) = sphere.surface(on_sphere(rec.position + step * rec.dpdv));
+        assert!((u - rec.u).abs() < 1e-4, "u moved by {}", u - rec.u);
+        assert!((v - rec.v - step).abs() < 1e-4, "v moved by {}", v - rec.v);
+    }
+}
+
 #[cfg(test)]
 mod benches {
     use super::*;
@@ -0,0 +1,432 @@
+use crate::camera::*;
+use crate::hit::mesh::*;
+use crate::material::*;
+use crate::vec3::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubdivisionScheme {
+    /// Loop subdivision, which needs a cage made only of triangles.
+    Loop,
+    /// Catmull-Clark subdivision, which works on any polygons and turns them all into quads.
+    CatmullClark,
+}
+
+/// The polygons of a control cage, to be smoothed by subdivision before being turned into a mesh.
+/// Edges on the boundary of the cage, and edges shared by more than two faces, are kept sharp,
+/// so the surface follows them like a B-spline curve.
+#[derive(Debug, Clone)]
+pub struct ControlCage {
+    positions: Vec<Vec3>,
+    faces: Vec<Vec<u32>>,
+    // how sharp each creased edge is, keyed by its vertices with the smallest first.
+    creases: HashMap<(u32, u32), f32>,
+}
+
+// what subdivision needs to know about an edge.
+#[derive(Debug)]
+struct EdgeInfo {
+    faces: Vec<usize>,
+    sharpness: f32,
+}
+
+fn edge_key(a: u32, b: u32) -> (u32, u32) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+impl ControlCage {
+    /// Each face lists the indices of its corners, going anticlockwise around its front.
+    pub fn new(positions: Vec<Vec3>, faces: Vec<Vec<u32>>) -> ControlCage {
+        for face in faces.iter() {
+            assert!(
+                face.len() >= 3,
+                "A face needs at least 3 corners, not {}!",
+                face.len()
+            );
+            for &i in face.iter() {
+                assert!(
+                    (i as usize) < positions.len(),
+                    "Face index {} is out of range for {} positions",
+                    i,
+                    positions.len()
+                );
+            }
+        }
+        ControlCage {
+            positions,
+            faces,
+            creases: HashMap::new(),
+        }
+    }
+
+    /// A cage made of the triangles of a mesh.
+    pub fn from_mesh(mesh: &Mesh) -> ControlCage {
+        ControlCage::new(
+            mesh.positions().to_vec(),
+            mesh.indices().iter().map(|t| t.to_vec()).collect(),
+        )
+    }
+
+    /// Creases the edge between a and b. A sharpness of 1 or more keeps the edge sharp for that
+    /// many levels of subdivision before it starts to smooth out, and fractions in between give
+    /// softer creases.
+    pub fn with_crease(mut self, a: u32, b: u32, sharpness: f32) -> ControlCage {
+        self.creases.insert(edge_key(a, b), sharpness);
+        self
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn faces(&self) -> &[Vec<u32>] {
+        &self.faces
+    }
+
+    /// Subdivides the cage levels times. Each level splits every edge in two.
+    pub fn subdivided(&self, scheme: SubdivisionScheme, levels: usize) -> ControlCage {
+        let mut cage = self.clone();
+        for _ in 0..levels {
+            cage = cage.subdivide(scheme);
+        }
+        cage
+    }
+
+    /// Keeps subdividing until no edge in front of the camera covers more than max_edge_pixels
+    /// on an image image_width pixels across, or until max_levels is reached. The whole cage is
+    /// subdivided to the same level, so there are no cracks between the levels of neighbouring faces.
+    pub fn subdivided_for_camera(
+        &self,
+        scheme: SubdivisionScheme,
+        camera: &Camera,
+        image_width: usize,
+        max_edge_pixels: f32,
+        max_levels: usize,
+    ) -> ControlCage {
+        let mut cage = self.clone();
+        for _ in 0..max_levels {
+            if cage.longest_projected_edge(camera, image_width) <= max_edge_pixels {
+                break;
+            }
+            cage = cage.subdivide(scheme);
+        }
+        cage
+    }
+
+    fn longest_projected_edge(&self, camera: &Camera, image_width: usize) -> f32 {
+        let mut longest = 0.0f32;
+        for face in self.faces.iter() {
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+                if let Some(pixels) =
+                    camera.projected_size(0.5 * (pa + pb), (pb - pa).length(), image_width)
+                {
+                    longest = longest.max(pixels);
+                }
+            }
+        }
+        longest
+    }
+
+    /// Triangulates the cage into a mesh, with smooth normals averaged from the faces around each
+    /// vertex, weighted by their area.
+    pub fn into_mesh(self, material: Arc<dyn Material>) -> Mesh {
+        let mut indices = Vec::with_capacity(self.faces.len() * 2);
+        let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for face in self.faces.iter() {
+            for pair in face[1..].windows(2) {
+                let triangle = [face[0], pair[0], pair[1]];
+                let [a, b, c] = triangle.map(|i| self.positions[i as usize]);
+                let normal = (b - a).cross(c - a);
+                for &i in triangle.iter() {
+                    normals[i as usize] = normals[i as usize] + normal;
+                }
+                indices.push(triangle);
+            }
+        }
+        let normals = normals
+            .into_iter()
+            .map(|n| {
+                if n.squared_length() > 0.0 {
+                    n.unit_vector()
+                } else {
+                    Vec3::new(0.0, 1.0, 0.0)
+                }
+            })
+            .collect();
+        Mesh::new(self.positions, indices, material).with_normals(normals)
+    }
+
+    fn edges(&self) -> HashMap<(u32, u32), EdgeInfo> {
+        let mut edges: HashMap<(u32, u32), EdgeInfo> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                let key = edge_key(a, face[(i + 1) % face.len()]);
+                edges
+                    .entry(key)
+                    .or_insert_with(|| EdgeInfo {
+                        faces: vec![],
+                        sharpness: 0.0,
+                    })
+                    .faces
+                    .push(f);
+            }
+        }
+        for (key, edge) in edges.iter_mut() {
+            // boundaries and non-manifold edges have no smooth rule, so they are always sharp.
+            edge.sharpness = if edge.faces.len() != 2 {
+                f32::INFINITY
+            } else {
+                self.creases.get(key).copied().unwrap_or(0.0)
+            };
+        }
+        edges
+    }
+
+    fn subdivide(&self, scheme: SubdivisionScheme) -> ControlCage {
+        let edges = self.edges();
+        // sorted, so the new vertices come out in the same order every time.
+        let mut keys: Vec<(u32, u32)> = edges.keys().copied().collect();
+        keys.sort_unstable();
+
+        // the edges leaving each vertex, and the faces around it.
+        let mut neighbours = vec![vec![]; self.positions.len()];
+        for &(a, b) in keys.iter() {
+            neighbours[a as usize].push(b);
+            neighbours[b as usize].push(a);
+        }
+        let mut vertex_faces = vec![vec![]; self.positions.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            for &v in face.iter() {
+                vertex_faces[v as usize].push(f);
+            }
+        }
+
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| {
+                face.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &v| {
+                    sum + self.positions[v as usize]
+                }) / face.len() as f32
+            })
+            .collect();
+
+        // the new vertices: first the moved old ones, then one for each edge, then one for each
+        // face for Catmull-Clark.
+        let mut positions =
+            Vec::with_capacity(self.positions.len() + edges.len() + self.faces.len());
+        for (v, &p) in self.positions.iter().enumerate() {
+            if neighbours[v].is_empty() {
+                // not part of any face, so there is nothing to smooth it towards.
+                positions.push(p);
+                continue;
+            }
+            let smooth = || match scheme {
+                SubdivisionScheme::Loop => {
+                    let n = neighbours[v].len() as f32;
+                    let c = 0.375 + 0.25 * (2.0 * std::f32::consts::PI / n).cos();
+                    let beta = (0.625 - c * c) / n;
+                    let sum = neighbours[v]
+                        .iter()
+                        .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &u| {
+                            sum + self.positions[u as usize]
+                        });
+                    (1.0 - n * beta) * p + beta * sum
+                }
+                SubdivisionScheme::CatmullClark => {
+                    let n = neighbours[v].len() as f32;
+                    let faces = vertex_faces[v]
+                        .iter()
+                        .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &f| sum + face_points[f])
+                        / vertex_faces[v].len() as f32;
+                    let midpoints = neighbours[v]
+                        .iter()
+                        .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &u| {
+                            sum + 0.5 * (p + self.positions[u as usize])
+                        })
+                        / n;
+                    (faces + 2.0 * midpoints + (n - 3.0) * p) / n
+                }
+            };
+            let sharp: Vec<(u32, f32)> = neighbours[v]
+                .iter()
+                .map(|&u| (u, edges[&edge_key(v as u32, u)].sharpness))
+                .filter(|&(_, s)| s > 0.0)
+                .collect();
+
+            positions.push(match sharp.len() {
+                0 | 1 => smooth(),
+                n => {
+                    // two sharp edges make a crease the vertex slides along, and more make a corner.
+                    let crease = if n == 2 {
+                        0.75 * p
+                            + 0.125
+                                * (self.positions[sharp[0].0 as usize]
+                                    + self.positions[sharp[1].0 as usize])
+                    } else {
+                        p
+                    };
+                    let weight = sharp.iter().map(|&(_, s)| s.min(1.0)).sum::<f32>() / n as f32;
+                    if weight >= 1.0 {
+                        crease
+                    } else {
+                        weight * crease + (1.0 - weight) * smooth()
+                    }
+                }
+            });
+        }
+
+        let mut edge_points = HashMap::with_capacity(edges.len());
+        let mut creases = HashMap::new();
+        for &(a, b) in keys.iter() {
+            let edge = &edges[&(a, b)];
+            let (pa, pb) = (self.positions[a as usize], self.positions[b as usize]);
+            let middle = 0.5 * (pa + pb);
+            let point = if edge.sharpness >= 1.0 {
+                middle
+            } else {
+                let smooth = match scheme {
+                    SubdivisionScheme::Loop => {
+                        // the corners of the two triangles across from the edge.
+                        let opposite =
+                            edge.faces.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &f| {
+                                let c = self.faces[f].iter().find(|&&c| c != a && c != b).unwrap();
+                                sum + self.positions[*c as usize]
+                            });
+                        0.375 * (pa + pb) + 0.125 * opposite
+                    }
+                    SubdivisionScheme::CatmullClark => {
+                        0.25 * (pa + pb + face_points[edge.faces[0]] + face_points[edge.faces[1]])
+                    }
+                };
+                edge.sharpness * middle + (1.0 - edge.sharpness) * smooth
+            };
+            let index = positions.len() as u32;
+            positions.push(point);
+            edge_points.insert((a, b), index);
+
+            // creases lose one level of sharpness each time they are split.
+            if edge.faces.len() == 2 && edge.sharpness > 1.0 {
+                creases.insert(edge_key(a, index), edge.sharpness - 1.0);
+                creases.insert(edge_key(index, b), edge.sharpness - 1.0);
+            }
+        }
+        let edge_point = |a: u32, b: u32| edge_points[&edge_key(a, b)];
+
+        let mut faces = Vec::with_capacity(4 * self.faces.len());
+        match scheme {
+            SubdivisionScheme::Loop => {
+                for face in self.faces.iter() {
+                    assert!(
+                        face.len() == 3,
+                        "Loop subdivision needs triangles, but there is a face with {} corners!",
+                        face.len()
+                    );
+                    let (a, b, c) = (face[0], face[1], face[2]);
+                    let (ab, bc, ca) = (edge_point(a, b), edge_point(b, c), edge_point(c, a));
+                    faces.push(vec![a, ab, ca]);
+                    faces.push(vec![b, bc, ab]);
+                    faces.push(vec![c, ca, bc]);
+                    faces.push(vec![ab, bc, ca]);
+                }
+            }
+            SubdivisionScheme::CatmullClark => {
+                for (f, face) in self.faces.iter().enumerate() {
+                    let center = positions.len() as u32;
+                    positions.push(face_points[f]);
+                    let k = face.len();
+                    for i in 0..k {
+                        let (previous, v, next) =
+                            (face[(i + k - 1) % k], face[i], face[(i + 1) % k]);
+                        faces.push(vec![
+                            v,
+                            edge_point(v, next),
+                            center,
+                            edge_point(previous, v),
+                        ]);
+                    }
+                }
+            }
+        }
+
+        ControlCage {
+            positions,
+            faces,
+            creases,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    fn cube() -> ControlCage {
+        let positions = (0..8)
+            .map(|i| {
+                Vec3::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                )
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        ControlCage::new(positions, faces)
+    }
+
+    #[test]
+    fn subdivision_smooths_closed_cages_and_keeps_creases() {
+        let smooth = cube().subdivided(SubdivisionScheme::CatmullClark, 3);
+        assert_eq!(smooth.faces().len(), 6 * 64);
+        // the corners are pulled in towards the middle of the cube, but the whole thing stays
+        // symmetric about the origin.
+        let radii: Vec<f32> = smooth.positions().iter().map(|p| p.length()).collect();
+        let max = radii.iter().cloned().fold(0.0, f32::max);
+        assert!(max < 1.2, "a point is {} from the center", max);
+        let sum = smooth
+            .positions()
+            .iter()
+            .fold(Vec3::new(0.0, 0.0, 0.0), |sum, &p| sum + p);
+        assert!(sum.length() < 1e-3);
+
+        // with every edge of one face creased for good, that face stays flat.
+        let mut creased = cube();
+        for &(a, b) in [(0, 2), (2, 3), (3, 1), (1, 0)].iter() {
+            creased = creased.with_crease(a, b, 10.0);
+        }
+        let creased = creased.subdivided(SubdivisionScheme::CatmullClark, 2);
+        let flat = creased
+            .positions()
+            .iter()
+            .filter(|p| (p.z() + 1.0).abs() < 1e-5)
+            .count();
+        assert!(flat >= 25, "only {} points are on the creased face", flat);
+
+        // the cube split into triangles, then through loop subdivision.
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let triangles = cube().into_mesh(Arc::clone(&mat));
+        let looped = ControlCage::from_mesh(&triangles).subdivided(SubdivisionScheme::Loop, 2);
+        assert_eq!(looped.faces().len(), 12 * 16);
+        let mesh = looped.into_mesh(mat);
+        assert_eq!(mesh.triangle_count(), 12 * 16);
+        assert!(mesh.normals().is_some());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::aabb::*;
+use crate::ray::*;
+use crate::util::*;
+use crate::vec3::*;
+use std::sync::Arc;
+
+use super::*;
+
+/// A ring around axis, where major_radius is from the center to the middle of the tube and
+/// minor_radius is the radius of the tube.
+#[derive(Debug)]
+pub struct Torus {
+    frame: Frame,
+    major_radius: f32,
+    minor_radius: f32,
+    material: Arc<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Vec3,
+        axis: Vec3,
+        major_radius: f32,
+        minor_radius: f32,
+        material: Arc<dyn Material>,
+    ) -> Torus {
+        assert!(
+            0.0 < minor_radius && minor_radius < major_radius,
+            "A torus's minor radius of {} must be between 0 and its major radius of {}!",
+            minor_radius,
+            major_radius
+        );
+        Torus {
+            frame: Frame::new(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+
+    // the angles around the axis and around the tube at a local point.
+    fn angles(&self, p: Vec3) -> (f32, f32) {
+        let two_pi = 2.0 * std::f32::consts::PI;
+        let phi = p.y().atan2(p.x());
+        let rho = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        let theta = p.z().atan2(rho - self.major_radius);
+        (
+            if phi < 0.0 { phi + two_pi } else { phi },
+            if theta < 0.0 { theta + two_pi } else { theta },
+        )
+    }
+
+    // the nearest t where the ray hits the torus.
+    fn intersect(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+        let length = r.direction().length();
+        let d = self.frame.to_local_vector(r.direction()) / length;
+        let o = self.frame.to_local(r.origin());
+
+        // the quartic loses precision when the ray starts far away, so start it on the bounding sphere.
+        let bound = self.major_radius + self.minor_radius;
+        let b = o.dot(d);
+        let discriminant = b * b - (o.dot(o) - bound * bound);
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let start = (-b - discriminant.sqrt()).max(0.0);
+        let o = o + start * d;
+
+        let (ox, oy, oz) = (o.x() as f64, o.y() as f64, o.z() as f64);
+        let (dx, dy, dz) = (d.x() as f64, d.y() as f64, d.z() as f64);
+        let major2 = (self.major_radius as f64).powi(2);
+        let minor2 = (self.minor_radius as f64).powi(2);
+        let n = ox * dx + oy * dy + oz * dz;
+        let k = ox * ox + oy * oy + oz * oz + major2 - minor2;
+
+        let roots = solve_quartic(
+            4.0 * n,
+            4.0 * n * n + 2.0 * k - 4.0 * major2 * (dx * dx + dy * dy),
+            4.0 * n * k - 8.0 * major2 * (ox * dx + oy * dy),
+            k * k - 4.0 * major2 * (ox * ox + oy * oy),
+        );
+        roots
+            .into_iter()
+            .map(|s| (start + s as f32) / length)
+            .find(|&t| t > t_min && t < t_max)
+    }
+}
+
+impl Hitable for Torus {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let t = self.intersect(r, t_min, t_max)?;
+        let position = r.point_at_parameter(t);
+        let p = self.frame.to_local(position);
+        let (phi, theta) = self.angles(p);
+        let two_pi = 2.0 * std::f32::consts::PI;
+        let normal = Vec3::new(
+            theta.cos() * phi.cos(),
+            theta.cos() * phi.sin(),
+            theta.sin(),
+        );
+        let dpdu = two_pi * Vec3::new(-p.y(), p.x(), 0.0);
+        let dpdv = two_pi
+            * self.minor_radius
+            * Vec3::new(
+                -theta.sin() * phi.cos(),
+                -theta.sin() * phi.sin(),
+                theta.cos(),
+            );
+
+        Some(
+            HitRecord::new(
+                t,
+                position,
+                r,
+                self.frame.to_world_vector(normal),
+                &*self.material,
+            )
+            .with_surface(
+                phi / two_pi,
+                theta / two_pi,
+                self.frame.to_world_vector(dpdu),
+                self.frame.to_world_vector(dpdv),
+            ),
+        )
+    }
+
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let bound = self.major_radius + self.minor_radius;
+        let extent = Vec3::new(bound, bound, self.minor_radius);
+        Some(self.frame.aa_box(-extent, extent))
+    }
+}
+
+impl Sampleable for Torus {
+    fn area(&self) -> f32 {
+        4.0 * std::f32::consts::PI * std::f32::consts::PI * self.major_radius * self.minor_radius
+    }
+
+    fn sample(&self) -> (Vec3, Vec3) {
+        let two_pi = 2.0 * std::f32::consts::PI;
+        // the outside of the ring has more area than the inside, so angles around the tube are
+        // kept in proportion to the distance from the axis.
+        let theta = loop {
+            let theta = two_pi * rand::random::<f32>();
+            let distance = self.major_radius + self.minor_radius * theta.cos();
+            if rand::random::<f32>() * (self.major_radius + self.minor_radius) < distance {
+                break theta;
+            }
+        };
+        let phi = two_pi * rand::random::<f32>();
+        let normal = Vec3::new(
+            theta.cos() * phi.cos(),
+            theta.cos() * phi.sin(),
+            theta.sin(),
+        );
+        let p =
+            self.major_radius * Vec3::new(phi.cos(), phi.sin(), 0.0) + self.minor_radius * normal;
+        (self.frame.to_world(p), self.frame.to_world_vector(normal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn torus_hits() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        let torus = Torus::new(Vec3::new(0.0, 0.0, 0.0), z, 2.0, 0.5, mat);
+
+        // from the side, onto the outside of the ring.
+        let r = Ray::new(Vec3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let rec = torus.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-4, "hit at {}", rec.t);
+        assert!((rec.normal.x() + 1.0).abs() < 1e-4);
+
+        // from above, onto the top of the tube, with a direction that isn't unit length.
+        let r = Ray::new(Vec3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0));
+        let rec = torus.hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 2.25).abs() < 1e-4, "hit at {}", rec.t);
+
+        // straight down through the hole.
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), -z);
+        assert!(torus.hit(&r, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn torus_occluded_agrees_with_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        check_occluded(&Torus::new(Vec3::new(0.0, 0.0, 0.0), y, 1.5, 0.3, mat));
+    }
+}
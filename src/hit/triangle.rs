@@ -5,13 +5,9 @@ use std::sync::Arc;
 
 use super::*;
 
-// Do we want to have triangles directly, or do we want to store them in some sort of mesh?
-// if we could just store references to materials inside objects, I would be happy to have only
-// triangles, and no such thing as a mesh. However, using meshes would eliminate most benefits of
-// the BVH (when using large meshes), as well as the fact that it would require significant refactoring
-// to support hitables only storing a material reference (the main difficulty comes in initializing such a structure,
-// due to the fact that once we create an object with a reference to a vector, we cannot add any more elements to it due to
-// it requiring a mutable reference).
+// Each triangle here stores its own corners, edges and material, which is simple but heavy.
+// Large meshes should use Mesh (in mesh.rs) instead, which shares its vertex buffers between its
+// triangles, and builds a BVH over them by index rather than boxing every triangle on its own.
 
 #[derive(Debug)]
 pub struct Triangle {
@@ -23,6 +19,8 @@ pub struct Triangle {
     edge2: Vec3,
 
     normal: Vec3,
+    vertex_normals: Option<[Vec3; 3]>,
+    uvs: Option<[[f32; 2]; 3]>,
 
     material: Arc<dyn Material>,
 }
@@ -35,75 +33,259 @@ impl Triangle {
             c,
             edge1: b - a,
             edge2: c - a,
-            normal: (b - a).cross(c - a),
+            normal: (b - a).cross(c - a).unit_vector(),
+            vertex_normals: None,
+            uvs: None,
             material,
         }
     }
+
+    /// A triangle which is smooth shaded, with a normal given for each corner.
+    pub fn new_smooth(
+        a: Vec3,
+        b: Vec3,
+        c: Vec3,
+        normals: [Vec3; 3],
+        material: Arc<dyn Material>,
+    ) -> Triangle {
+        Triangle {
+            vertex_normals: Some(normals),
+            ..Triangle::new(a, b, c, material)
+        }
+    }
+
+    /// Gives the triangle texture coordinates at each corner.
+    /// Without them, the barycentric coordinates of the hit are used instead.
+    pub fn with_uvs(self, uvs: [[f32; 2]; 3]) -> Triangle {
+        Triangle {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
 }
 
 impl Hitable for Triangle {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        //let edge1 = self.b - self.a;
-        //let edge2 = self.c - self.a;
+        let (t, u, v) = moller_trumbore(r, self.a, self.edge1, self.edge2, t_min, t_max)?;
 
-        let edge1 = self.edge1;
-        let edge2 = self.edge2;
+        // ray intersection!
+        let (tex_u, tex_v, dpdu, dpdv) = triangle_surface([self.a, self.b, self.c], self.uvs, u, v);
+        let rec = HitRecord::new(t, r.point_at_parameter(t), r, self.normal, &*self.material)
+            .with_surface(tex_u, tex_v, dpdu, dpdv)
+            .with_barycentric(u, v);
 
-        let h = r.direction().cross(edge2);
-        let a = edge1.dot(h);
-
-        if a > -0.0000001 && a < 0.0000001 {
-            // ray is parallel to triangle.
-            return None;
-        }
-        let f = 1.0 / a;
-        let s = r.origin() - self.a;
-        let u = f * s.dot(h);
-
-        if u < 0.0 || u > 1.0 {
-            return None;
-        }
-        let q = s.cross(edge1);
-        let v = f * r.direction().dot(q);
+        Some(match self.vertex_normals {
+            Some(normals) => rec.with_shading_normal(interpolate_normal(normals, u, v)),
+            None => rec,
+        })
+    }
 
-        if v < 0.0 || u + v > 1.0 {
-            return None;
-        }
-        // at this stage ,we comput t (position along vector) to find intersection point.
-
-        let t = f * edge2.dot(q);
-
-        if t > t_min && t < t_max {
-            // ray intersection!
-            Some(HitRecord::new(
-                t,
-                r.point_at_parameter(t),
-                r,
-                r.origin() + r.direction() * t,
-                &*self.material,
-            ))
-        } else {
-            None
-        }
+    fn occluded(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        moller_trumbore(r, self.a, self.edge1, self.edge2, t_min, t_max).is_some()
     }
 
+    // padded a little, so triangles lying flat along an axis still have a box that can be hit.
     fn bounding_box(&self) -> Option<AABB> {
         let a = self.a;
         let b = self.b;
         let c = self.c;
+        let pad = Vec3::new(0.0001, 0.0001, 0.0001);
         Some(AABB::new(
             Vec3::new(
                 a.x().min(b.x()).min(c.x()), //small
                 a.y().min(b.y()).min(c.y()),
                 a.z().min(b.z()).min(c.z()),
-            ),
+            ) - pad,
             Vec3::new(
                 a.x().max(b.x()).max(c.x()), // big
                 a.y().max(b.y()).max(c.y()),
                 a.z().max(b.z()).max(c.z()),
-            ),
+            ) + pad,
         ))
     }
+
+    fn clipped_box(&self, bounds: &AABB) -> Option<AABB> {
+        clip_triangle([self.a, self.b, self.c], bounds)
+    }
+}
+
+/// The box around the part of a triangle inside bounds, found by clipping the triangle against each
+/// side of the box in turn. Padded like the bounding box, as the clipped part is still flat.
+pub(crate) fn clip_triangle(p: [Vec3; 3], bounds: &AABB) -> Option<AABB> {
+    let mut polygon = p.to_vec();
+    for axis in 0..3 {
+        for &(plane, keep_below) in [
+            (bounds.min().get(axis), false),
+            (bounds.max().get(axis), true),
+        ]
+        .iter()
+        {
+            let inside = |p: Vec3| (p.get(axis) <= plane) == keep_below || p.get(axis) == plane;
+            let mut clipped = Vec::with_capacity(polygon.len() + 1);
+            for (i, &start) in polygon.iter().enumerate() {
+                let end = polygon[(i + 1) % polygon.len()];
+                if inside(start) {
+                    clipped.push(start);
+                }
+                if inside(start) != inside(end) {
+                    let t = (plane - start.get(axis)) / (end.get(axis) - start.get(axis));
+                    clipped.push(start + t * (end - start));
+                }
+            }
+            if clipped.is_empty() {
+                return None;
+            }
+            polygon = clipped;
+        }
+    }
+
+    let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+    let first = AABB::new(polygon[0], polygon[0]);
+    let aa_box = polygon
+        .iter()
+        .skip(1)
+        .fold(first, |acc, &p| AABB::surrounding_box(acc, AABB::new(p, p)));
+    Some(AABB::new(aa_box.min() - pad, aa_box.max() + pad))
+}
+
+/// Blends the normals at the corners of a triangle, using the barycentric coordinates of a hit on it.
+pub(crate) fn interpolate_normal(normals: [Vec3; 3], u: f32, v: f32) -> Vec3 {
+    ((1.0 - u - v) * normals[0] + u * normals[1] + v * normals[2]).unit_vector()
+}
+
+/// Finds the texture coordinates of a hit on a triangle from its barycentric coordinates,
+/// along with dpdu and dpdv. Triangles without uvs are mapped as (0, 0), (1, 0) and (0, 1).
+pub(crate) fn triangle_surface(
+    p: [Vec3; 3],
+    uvs: Option<[[f32; 2]; 3]>,
+    u: f32,
+    v: f32,
+) -> (f32, f32, Vec3, Vec3) {
+    let uvs = match uvs {
+        Some(uvs) => uvs,
+        None => return (u, v, p[1] - p[0], p[2] - p[0]),
+    };
+
+    let w = 1.0 - u - v;
+    let tex_u = w * uvs[0][0] + u * uvs[1][0] + v * uvs[2][0];
+    let tex_v = w * uvs[0][1] + u * uvs[1][1] + v * uvs[2][1];
+
+    // solve for the derivatives along the two edges leaving the last corner.
+    let duv02 = [uvs[0][0] - uvs[2][0], uvs[0][1] - uvs[2][1]];
+    let duv12 = [uvs[1][0] - uvs[2][0], uvs[1][1] - uvs[2][1]];
+    let dp02 = p[0] - p[2];
+    let dp12 = p[1] - p[2];
+
+    let det = duv02[0] * duv12[1] - duv02[1] * duv12[0];
+    if det.abs() < 1e-8 {
+        // the uvs don't span an area, so fall back to the edges.
+        return (tex_u, tex_v, p[1] - p[0], p[2] - p[0]);
+    }
+    let inv_det = 1.0 / det;
+    let dpdu = (duv12[1] * dp02 - duv02[1] * dp12) * inv_det;
+    let dpdv = (duv02[0] * dp12 - duv12[0] * dp02) * inv_det;
+
+    (tex_u, tex_v, dpdu, dpdv)
+}
+
+/// Intersects a ray with the triangle with corner a and edges edge1 and edge2 leaving it,
+/// returning t and the barycentric coordinates (u along edge1, v along edge2) of the hit.
+pub(crate) fn moller_trumbore(
+    r: &Ray,
+    a: Vec3,
+    edge1: Vec3,
+    edge2: Vec3,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    let h = r.direction().cross(edge2);
+    let det = edge1.dot(h);
+
+    if det > -0.0000001 && det < 0.0000001 {
+        // ray is parallel to triangle.
+        return None;
+    }
+    let f = 1.0 / det;
+    let s = r.origin() - a;
+    let u = f * s.dot(h);
+
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = f * r.direction().dot(q);
+
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    // at this stage ,we comput t (position along vector) to find intersection point.
+
+    let t = f * edge2.dot(q);
+
+    if t > t_min && t < t_max {
+        Some((t, u, v))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hit::bvh::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn flat_triangle_is_hit_through_bvh() {
+        let triangle = Triangle::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5))),
+        );
+        let bvh = BvhNode::new(vec![Box::new(triangle)]);
+
+        let r = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = bvh
+            .hit(&r, 0.001, f32::MAX)
+            .expect("The ray should hit the triangle!");
+        assert!((rec.t - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn smooth_triangles_interpolate_the_shading_normal() {
+        let normals = [
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0).unit_vector(),
+            Vec3::new(0.0, 1.0, 1.0).unit_vector(),
+        ];
+        let triangle = Triangle::new_smooth(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            normals,
+            Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5))),
+        );
+        // off center, 0.2 of the way towards b and 0.6 towards c.
+        let expected = (0.2 * normals[0] + 0.2 * normals[1] + 0.6 * normals[2]).unit_vector();
+        let face = Vec3::new(0.0, 0.0, 1.0);
+
+        // from the front, and then from behind, where both normals turn to face the ray.
+        for &side in [1.0, -1.0].iter() {
+            let r = Ray::new(Vec3::new(0.2, 0.6, side), Vec3::new(0.0, 0.0, -side));
+            let rec = triangle.hit(&r, 0.001, f32::MAX).unwrap();
+            assert!(
+                (rec.normal - side * expected).length() < 1e-5,
+                "normal {:?}",
+                rec.normal
+            );
+            assert!(
+                (rec.geometric_normal - side * face).length() < 1e-5,
+                "geometric normal {:?}",
+                rec.geometric_normal
+            );
+        }
+    }
 }
 
 #[cfg(test)]
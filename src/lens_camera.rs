@@ -0,0 +1,453 @@
+use super::camera::*;
+use super::ray::*;
+use super::util::*;
+use super::vec3::*;
+
+use std::path::Path;
+
+/// One surface of a lens prescription, in millimetres. Surfaces are listed from the front of the
+/// lens (the side facing the scene) to the back, as they are in patents and lens design books.
+#[derive(Debug, Clone, Copy)]
+pub struct LensElement {
+    curvature_radius: f32, // positive when the center of curvature is towards the film, 0 for the aperture stop.
+    thickness: f32, // distance along the axis to the next surface (or the film, for the last one).
+    ior: f32,       // index of refraction of the medium behind this surface, 0 or 1 for air.
+    aperture_radius: f32,
+}
+
+impl LensElement {
+    pub fn new(
+        curvature_radius: f32,
+        thickness: f32,
+        ior: f32,
+        aperture_diameter: f32,
+    ) -> LensElement {
+        LensElement {
+            curvature_radius,
+            thickness,
+            ior,
+            aperture_radius: aperture_diameter / 2.0,
+        }
+    }
+
+    fn is_stop(&self) -> bool {
+        self.curvature_radius == 0.0
+    }
+
+    fn ior(&self) -> f32 {
+        if self.ior == 0.0 {
+            1.0
+        } else {
+            self.ior
+        }
+    }
+
+    //intersects the surface with its vertex at z, returning t and the normal facing against the ray.
+    fn intersect(&self, r: &Ray, z: f32) -> Option<(f32, Vec3)> {
+        if self.is_stop() {
+            let t = (z - r.origin().z()) / r.direction().z();
+            if t <= 0.0 {
+                return None;
+            }
+            let normal = if r.direction().z() > 0.0 {
+                Vec3::new(0.0, 0.0, -1.0)
+            } else {
+                Vec3::new(0.0, 0.0, 1.0)
+            };
+            return Some((t, normal));
+        }
+
+        let radius = self.curvature_radius;
+        let center = Vec3::new(0.0, 0.0, z + radius);
+        let oc = r.origin() - center;
+        let a = r.direction().dot(r.direction());
+        let b = oc.dot(r.direction());
+        let c = oc.dot(oc) - radius * radius;
+        let discriminant = b * b - a * c;
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // the surface is only the half of the sphere around the vertex, which is the nearer
+        // intersection unless the ray is heading away from the center of curvature.
+        let use_closer = (r.direction().z() > 0.0) ^ (radius < 0.0);
+        let t = if use_closer {
+            (-b - discriminant.sqrt()) / a
+        } else {
+            (-b + discriminant.sqrt()) / a
+        };
+        if t <= 0.0 {
+            return None;
+        }
+
+        let normal = (r.point_at_parameter(t) - center) / radius.abs();
+        if normal.dot(r.direction()) > 0.0 {
+            Some((t, -normal))
+        } else {
+            Some((t, normal))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PrescriptionError {
+    Io(std::io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl std::fmt::Display for PrescriptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PrescriptionError::Io(err) => write!(f, "couldn't read lens prescription: {}", err),
+            PrescriptionError::Parse { line, message } => {
+                write!(f, "lens prescription line {}: {}", line, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrescriptionError {}
+
+impl From<std::io::Error> for PrescriptionError {
+    fn from(err: std::io::Error) -> PrescriptionError {
+        PrescriptionError::Io(err)
+    }
+}
+
+/// Reads a lens prescription table, with one surface per line given as
+/// `radius thickness ior aperture_diameter`. Everything after a '#' is a comment.
+pub fn load_prescription(path: &Path) -> Result<Vec<LensElement>, PrescriptionError> {
+    parse_prescription(&std::fs::read_to_string(path)?)
+}
+
+pub fn parse_prescription(text: &str) -> Result<Vec<LensElement>, PrescriptionError> {
+    let mut elements = vec![];
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let values = line
+            .split_whitespace()
+            .map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| PrescriptionError::Parse {
+                line: i + 1,
+                message: err.to_string(),
+            })?;
+
+        if values.len() != 4 {
+            return Err(PrescriptionError::Parse {
+                line: i + 1,
+                message: format!("expected 4 columns, but found {}", values.len()),
+            });
+        }
+        elements.push(LensElement::new(values[0], values[1], values[2], values[3]));
+    }
+
+    if elements.is_empty() {
+        return Err(PrescriptionError::Parse {
+            line: 0,
+            message: String::from("the prescription has no surfaces"),
+        });
+    }
+    Ok(elements)
+}
+
+/// A camera which traces rays through an actual lens design, so distortion, vignetting and focus
+/// breathing all come from the lens itself.
+///
+/// Internally the lens lives in its own space, measured in millimetres, with the film at z = 0 and the
+/// lens towards negative z.
+#[derive(Debug, Clone)]
+pub struct LensCamera {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    elements: Vec<LensElement>,
+    film_width: f32,
+    film_height: f32,
+    exposure: f32,
+    time0: f32, // the shutter is open from time0 to time1.
+    time1: f32,
+}
+
+impl LensCamera {
+    /// sensor_width is in millimetres, and focus_dist is in scene units (taken to be metres), measured
+    /// from the film. The lens is refocused by moving it away from the film, as a real lens would be.
+    /// Returns None if the lens can't focus that close, or no light makes it along its axis.
+    pub fn new(
+        lookfrom: Vec3,
+        lookat: Vec3,
+        vup: Vec3,
+        elements: Vec<LensElement>,
+        sensor_width: f32,
+        aspect: f32,
+        focus_dist: f32,
+    ) -> Option<LensCamera> {
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(w).unit_vector();
+        let v = w.cross(u);
+
+        let mut cam = LensCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            elements,
+            film_width: sensor_width,
+            film_height: sensor_width / aspect,
+            exposure: 1.0,
+            time0: 0.0,
+            time1: 0.0,
+        };
+
+        let back_focus = cam.focus_thick_lens(focus_dist * 1000.0)?;
+        cam.elements.last_mut().unwrap().thickness = back_focus;
+        cam.exposure = cam.axial_exposure();
+        Some(cam)
+    }
+
+    /// Returns a copy of this camera which sends out rays at random times between open and close.
+    pub fn with_shutter(&self, open: f32, close: f32) -> LensCamera {
+        LensCamera {
+            time0: open,
+            time1: close,
+            ..self.clone()
+        }
+    }
+
+    fn rear_z(&self) -> f32 {
+        -self.elements.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f32 {
+        -self.elements.iter().map(|e| e.thickness).sum::<f32>()
+    }
+
+    fn trace_from_film(&self, r: &Ray) -> Option<Ray> {
+        let mut r = *r;
+        let mut z = 0.0;
+
+        for i in (0..self.elements.len()).rev() {
+            let element = &self.elements[i];
+            z -= element.thickness;
+
+            let (t, normal) = element.intersect(&r, z)?;
+            let p = r.point_at_parameter(t);
+            if p.x() * p.x() + p.y() * p.y() > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            if element.is_stop() {
+                r = Ray::new(p, r.direction());
+                continue;
+            }
+
+            let eta_i = element.ior();
+            let eta_t = if i > 0 {
+                self.elements[i - 1].ior()
+            } else {
+                1.0
+            };
+            r = Ray::new(p, r.direction().refract(normal, eta_i / eta_t)?);
+        }
+        Some(r)
+    }
+
+    fn trace_from_scene(&self, r: &Ray) -> Option<Ray> {
+        let mut r = *r;
+        let mut z = self.front_z();
+
+        for (i, element) in self.elements.iter().enumerate() {
+            let (t, normal) = element.intersect(&r, z)?;
+            let p = r.point_at_parameter(t);
+            if p.x() * p.x() + p.y() * p.y() > element.aperture_radius * element.aperture_radius {
+                return None;
+            }
+
+            if element.is_stop() {
+                r = Ray::new(p, r.direction());
+            } else {
+                let eta_i = if i > 0 {
+                    self.elements[i - 1].ior()
+                } else {
+                    1.0
+                };
+                let eta_t = element.ior();
+                r = Ray::new(p, r.direction().refract(normal, eta_i / eta_t)?);
+            }
+            z += element.thickness;
+        }
+        Some(r)
+    }
+
+    // finds the z of the principal plane and the focal point, from a ray parallel to the axis
+    // and what came out the other side of the lens.
+    fn cardinal_points(r_in: &Ray, r_out: &Ray) -> (f32, f32) {
+        let tf = -r_out.origin().x() / r_out.direction().x();
+        let fz = r_out.point_at_parameter(tf).z();
+
+        let tp = (r_in.origin().x() - r_out.origin().x()) / r_out.direction().x();
+        let pz = r_out.point_at_parameter(tp).z();
+        (pz, fz)
+    }
+
+    // returns the distance from the rear surface to the film which brings focus_dist into focus,
+    // by treating the lens system as a single thick lens. Returns None if focus_dist is closer than
+    // the lens can focus, or a ray along the axis doesn't make it through the lens.
+    fn focus_thick_lens(&self, focus_dist: f32) -> Option<f32> {
+        let x = 0.001
+            * (self.film_width * self.film_width + self.film_height * self.film_height).sqrt();
+
+        let r_scene = Ray::new(
+            Vec3::new(x, 0.0, self.front_z() - 1.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let r_film = self.trace_from_scene(&r_scene)?;
+        let (pz0, fz0) = LensCamera::cardinal_points(&r_scene, &r_film);
+
+        let r_film = Ray::new(
+            Vec3::new(x, 0.0, self.rear_z() + 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let r_scene = self.trace_from_film(&r_film)?;
+        let (pz1, _) = LensCamera::cardinal_points(&r_film, &r_scene);
+
+        let f = fz0 - pz0;
+        let z = -focus_dist;
+        let c = (pz1 - z - pz0) * (pz1 - z - 4.0 * f - pz0);
+        if c <= 0.0 {
+            return None;
+        }
+        let delta = 0.5 * (pz1 - z + pz0 - c.sqrt());
+
+        Some(self.elements.last().unwrap().thickness + delta)
+    }
+
+    // samples the rear element uniformly, so only some of the rays make it through the lens,
+    // even in the middle of the frame. This finds the scale that keeps the middle of the image
+    // at the same brightness as an ideal lens, leaving the falloff towards the edges as it is.
+    fn axial_exposure(&self) -> f32 {
+        let n = 32;
+        let rear_radius = self.elements.last().unwrap().aperture_radius;
+        let mut total = 0;
+        let mut passed = 0;
+
+        for i in 0..n {
+            for j in 0..n {
+                let x = 2.0 * (i as f32 + 0.5) / n as f32 - 1.0;
+                let y = 2.0 * (j as f32 + 0.5) / n as f32 - 1.0;
+                if x * x + y * y > 1.0 {
+                    continue;
+                }
+                total += 1;
+
+                let rear = Vec3::new(x * rear_radius, y * rear_radius, self.rear_z());
+                let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), rear);
+                if self.trace_from_film(&r).is_some() {
+                    passed += 1;
+                }
+            }
+        }
+
+        if passed == 0 {
+            1.0
+        } else {
+            total as f32 / passed as f32
+        }
+    }
+}
+
+impl CameraModel for LensCamera {
+    fn generate_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        // the lens flips the image, so the film is read back to front.
+        let film = Vec3::new(
+            -(x - 0.5) * self.film_width,
+            -(y - 0.5) * self.film_height,
+            0.0,
+        );
+
+        let rd = self.elements.last().unwrap().aperture_radius * random_in_unit_disk();
+        let rear = Vec3::new(rd.x(), rd.y(), self.rear_z());
+
+        let r = self.trace_from_film(&Ray::new(film, rear - film))?;
+
+        let o = r.origin() * 0.001;
+        let d = r.direction();
+        let time = self.time0 + rand::random::<f32>() * (self.time1 - self.time0);
+        Some(Ray::new_at_time(
+            self.origin + o.x() * self.u + o.y() * self.v + o.z() * self.w,
+            d.x() * self.u + d.y() * self.v + d.z() * self.w,
+            time,
+        ))
+    }
+
+    fn exposure(&self) -> f32 {
+        self.exposure
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // double gauss 50mm f/2, from US patent 2,673,491.
+    const DGAUSS_50MM: &str = "
+        # radius  thickness  ior  aperture
+        29.475  3.76   1.67   25.2
+        84.83   0.12   1      25.2
+        19.275  4.025  1.67   23
+        40.77   3.275  1.699  23
+        12.75   5.705  1      18
+        0       4.5    0      17.1
+        -14.495 1.18   1.603  17
+        40.77   6.065  1.658  20
+        -20.385 0.19   1      20
+        437.065 3.22   1.717  20
+        -39.73  5      1      20
+    ";
+
+    #[test]
+    fn lens_focuses_at_focus_distance() {
+        let elements = parse_prescription(DGAUSS_50MM).unwrap();
+        let cam = LensCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            elements,
+            36.0,
+            1.5,
+            2.0,
+        )
+        .unwrap();
+
+        // rays leaving the center of the film should meet again on the axis, 2 metres away.
+        // they're kept close to the axis, where the spherical aberration of the lens is small.
+        for &h in [0.1, 0.25, 0.5].iter() {
+            let r = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(h, 0.0, cam.rear_z()));
+            let out = cam.trace_from_film(&r).unwrap();
+            let t = -out.origin().x() / out.direction().x();
+            let z = out.point_at_parameter(t).z();
+            assert!((z + 2000.0).abs() < 5.0, "rays met at z = {}", z);
+        }
+    }
+    #[test]
+    fn lens_cant_focus_closer_than_it_can() {
+        let elements = parse_prescription(DGAUSS_50MM).unwrap();
+        let cam = LensCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            elements,
+            36.0,
+            1.5,
+            0.01,
+        );
+        assert!(cam.is_none());
+    }
+}
@@ -4,20 +4,28 @@
 extern crate rand;
 
 use std::sync::Arc;
+use std::time::Instant;
 
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
+use rand::Rng;
 
 use indicatif::ParallelProgressIterator;
 use indicatif::ProgressBar;
 use rayon::prelude::*;
 
 mod aabb;
+pub mod aperture;
 pub mod camera;
 pub mod colour;
 pub mod hit;
+pub mod lens_camera;
+pub mod loader;
 pub mod material;
 pub mod ray;
+pub mod stereo;
+pub mod texture;
+pub mod transform;
 mod util;
 pub mod vec3;
 
@@ -25,56 +33,89 @@ use camera::*;
 use colour::*;
 use hit::bvh::*;
 use hit::hitable_list::*;
-use hit::triangle::*;
+use hit::instancing::*;
+use hit::mesh::*;
 use hit::*;
 use material::lambertian::*;
 use ray::*;
+use transform::*;
 use vec3::*;
 
-pub fn render(
+/// Renders the scene as seen by the camera.
+/// Diffuse surfaces sample the lights directly, which should be copies of every emitting shape in
+/// the world, as light from anything else is only found by scattering off other materials.
+pub fn render<C: CameraModel>(
     width: usize,
     height: usize,
     samples: usize,
-    cam: Camera,
+    cam: C,
     world: HitableList,
+    lights: &[Box<dyn Sampleable>],
 ) -> Vec<[f32; 3]> {
-    let bar = ProgressBar::new((height * width) as u64);
+    render_views(width, height, samples, &[&cam], world, lights).remove(0)
+}
+
+/// Renders the scene once for each camera, all sharing the same BVH.
+/// Returns one image per camera, in the same order as the cameras.
+pub fn render_views(
+    width: usize,
+    height: usize,
+    samples: usize,
+    cams: &[&dyn CameraModel],
+    world: HitableList,
+    lights: &[Box<dyn Sampleable>],
+) -> Vec<Vec<[f32; 3]>> {
+    let bar = ProgressBar::new((cams.len() * height * width) as u64);
     bar.set_style(indicatif::ProgressStyle::default_bar().progress_chars("=> "));
-    bar.set_draw_delta((height * width / 1000) as u64);
+    bar.set_draw_delta((cams.len() * height * width / 1000) as u64);
 
     println!("building BVH!");
+    let build_start = Instant::now();
     //let world: BvhNode = BvhNode::new(world.hitables);
+    //let world: BvhNode = BvhNode::new_sbvh(world.hitables, 0.3);
     let world: BvhNode = BvhNode::new_sah(world.hitables);
+    println!(
+        "built BVH in {:.2?}, with a SAH cost of {:.2}",
+        build_start.elapsed(),
+        world.sah_cost()
+    );
     //let world: Bvh = Bvh::new(world.hitables);
 
     //world.print_graph();
 
     println!("Starting raytracing!");
 
-    let buf: Vec<_> = (0..(width * height))
-        .into_par_iter()
-        .progress_with(bar)
-        .map(|i| {
-            let x = i % width;
-            let y = i / width;
-			
-            pixel(width, height, x, y, &cam, &world, samples)
-            //pixel
+    let bufs: Vec<Vec<_>> = cams
+        .iter()
+        .map(|&cam| {
+            (0..(width * height))
+                .into_par_iter()
+                .progress_with(bar.clone())
+                .map(|i| {
+                    let x = i % width;
+                    let y = i / width;
+
+                    pixel(width, height, x, y, cam, &world, lights, samples)
+                    //pixel
+                })
+                .collect()
         })
         .collect();
 
     println!("Done raytracing, finishing up!");
-	
-    buf
+
+    bufs
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn pixel(
     width: usize,
     height: usize,
     x: usize,
     y: usize,
-    cam: &Camera,
+    cam: &dyn CameraModel,
     world: &dyn Hitable,
+    lights: &[Box<dyn Sampleable>],
     samples: usize,
 ) -> [f32; 3] {
     let mut col = Colour::new(0.0, 0.0, 0.0);
@@ -83,26 +124,54 @@ pub fn pixel(
         let u = (x as f32 + r1) / (width as f32);
         let v = ((height - y) as f32 + r2) / (height as f32);
 
-        let r = cam.get_ray(u, v);
-
-        col = col + colour(&r, world, 0);
+        // rays blocked inside the camera carry no light.
+        if let Some(r) = cam.generate_ray(u, v) {
+            col = col + colour(&r, world, lights, 0);
+        }
     }
 
-    col = col / (samples as f32);
+    col = col * (cam.exposure() / samples as f32);
     col = Colour::new(col.r().sqrt(), col.g().sqrt(), col.b().sqrt());
 
     [col.r(), col.g(), col.b()]
 }
 
-pub fn colour(r: &Ray, world: &dyn Hitable, depth: u32) -> Colour {
+pub fn colour(r: &Ray, world: &dyn Hitable, lights: &[Box<dyn Sampleable>], depth: u32) -> Colour {
+    trace(r, world, lights, depth, false)
+}
+
+//follows a ray through the scene. When the surface the ray left from has already sampled the
+//lights directly, their light has been counted, so it isn't added again when the ray hits them.
+fn trace(
+    r: &Ray,
+    world: &dyn Hitable,
+    lights: &[Box<dyn Sampleable>],
+    depth: u32,
+    lights_sampled: bool,
+) -> Colour {
     match world.hit(r, 0.001, f32::MAX) {
         Some(record) => {
             if depth < 50 {
-                let emitted = record.material.emitted();
+                let emitted = if lights_sampled {
+                    Colour::new(0.0, 0.0, 0.0)
+                } else {
+                    record.material.emitted(&record)
+                };
 
                 match record.material.scatter(r, &record) {
                     Some(mat) => {
-                        emitted + colour(&mat.scattered(), world, depth + 1) * mat.attenuation()
+                        let albedo = record.material.diffuse_albedo(&record);
+                        let direct = match albedo {
+                            Some(albedo) if !lights.is_empty() => {
+                                albedo * direct_light(r, &record, world, lights)
+                            }
+                            _ => Colour::new(0.0, 0.0, 0.0),
+                        };
+                        let sampled = albedo.is_some() && !lights.is_empty();
+                        emitted
+                            + direct
+                            + trace(&mat.scattered(), world, lights, depth + 1, sampled)
+                                * mat.attenuation()
                     }
                     None => emitted,
                 }
@@ -121,22 +190,84 @@ pub fn colour(r: &Ray, world: &dyn Hitable, depth: u32) -> Colour {
     }
 }
 
+//estimates the light reaching a diffuse surface straight from a light picked at random, divided by
+//pi, so multiplying by the surface's albedo gives the light it scatters back along the ray.
+fn direct_light(
+    r: &Ray,
+    record: &HitRecord,
+    world: &dyn Hitable,
+    lights: &[Box<dyn Sampleable>],
+) -> Colour {
+    let black = Colour::new(0.0, 0.0, 0.0);
+    let light = &lights[rand::thread_rng().gen_range(0, lights.len())];
+    let (point, light_normal) = light.sample();
+
+    let to_light = point - record.position;
+    let distance = to_light.length();
+    let direction = to_light / distance;
+    let cos_surface = record.normal.dot(direction);
+    if cos_surface <= 0.0 || record.geometric_normal.dot(direction) <= 0.0 {
+        return black;
+    }
+
+    //the point can be hidden by the light itself, like the far side of a sphere.
+    let shadow = Ray::new_at_time(record.position, direction, r.time());
+    let light_record = match light.hit(&shadow, 0.001, f32::MAX) {
+        Some(rec) if rec.t > distance * (1.0 - 1e-3) => rec,
+        _ => return black,
+    };
+    if world.occluded(&shadow, 0.001, distance * (1.0 - 1e-3)) {
+        return black;
+    }
+
+    let cos_light = light_normal.dot(direction).abs();
+    let pdf = distance * distance / (cos_light * light.area());
+    light_record.material.emitted(&light_record)
+        * (cos_surface * lights.len() as f32 / (std::f32::consts::PI * pdf))
+}
+
+//an object from blender, as its world matrix, row by row, and its triangles in object space.
+//pyo3 turns away matrices that aren't 4x4 before they get here.
+type BlenderObject = ([[f32; 4]; 4], Vec<Vec<Vec3>>);
+
 #[pyfunction]
 fn py_render(
     width: usize,
     height: usize,
     samples: usize,
     cam: Camera,
-    world: Vec<Vec<Vec3>>,
-) -> Vec<[f32; 4]> {
+    world: Vec<BlenderObject>,
+) -> PyResult<Vec<[f32; 4]>> {
     let mat: Arc<dyn material::Material> = Arc::new(Lambertian::new(Colour::new(0.4, 0.2, 0.1)));
-    let l: Vec<_> = world
-        .iter()
-        .map(|x| Box::new(Triangle::new(x[0], x[1], x[2], Arc::clone(&mat))) as Box<dyn Hitable>)
-        .collect();
 
-    println!("length of triangles: {}", l.len());
-    let rend = render(width, height, samples, cam, HitableList::new(l));
+    let mut objects: Vec<Box<dyn Hitable>> = vec![];
+    for (rows, triangles) in world.iter() {
+        if let Some(corners) = triangles.iter().find(|x| x.len() != 3 && x.len() != 6) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "A triangle needs 3 corners, or 3 corners and 3 normals, not {} points!",
+                corners.len()
+            )));
+        }
+        let matrix = Matrix4::new(*rows);
+        //a mesh can't be empty, and an object scaled flat can't be seen.
+        if triangles.is_empty() || matrix.inverse().is_none() {
+            continue;
+        }
+        let mesh = triangle_mesh(triangles, Arc::clone(&mat));
+        objects.push(Box::new(Transformed::new(mesh, Transform::new(matrix))));
+    }
+
+    //with nothing to hit, every ray sees the black background.
+    if objects.is_empty() {
+        println!("no triangles to render!");
+        return Ok(vec![[0.0, 0.0, 0.0, 1.0]; width * height]);
+    }
+
+    println!(
+        "length of triangles: {}",
+        world.iter().map(|(_, t)| t.len()).sum::<usize>()
+    );
+    let rend = render(width, height, samples, cam, HitableList::new(objects), &[]);
 
     println!("{}, {}, {}", rend[0][0], rend[0][1], rend[0][2]);
 
@@ -149,7 +280,23 @@ fn py_render(
         )
     }
 
-    out.iter().map(|x| [(x[0]), (x[1]), (x[2]), 1.0]).collect()
+    Ok(out.iter().map(|x| [(x[0]), (x[1]), (x[2]), 1.0]).collect())
+}
+
+//makes a mesh from triangles given as their three corners, which can come with a normal for each
+//corner after their positions, for smooth shading.
+fn triangle_mesh(triangles: &[Vec<Vec3>], mat: Arc<dyn material::Material>) -> Mesh {
+    let positions: Vec<_> = triangles.iter().flat_map(|x| x[0..3].to_vec()).collect();
+    let indices: Vec<_> = (0..triangles.len() as u32)
+        .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+        .collect();
+
+    let mesh = Mesh::new(positions, indices, mat);
+    if triangles.iter().all(|x| x.len() == 6) {
+        mesh.with_normals(triangles.iter().flat_map(|x| x[3..6].to_vec()).collect())
+    } else {
+        mesh
+    }
 }
 
 #[pymodule]
@@ -161,6 +308,51 @@ fn ray_tracer(_py: Python, m: &PyModule) -> PyResult<()> {
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::hit::quad::*;
+    use crate::hit::sphere::*;
+    use crate::material::emission::*;
+
+    //a grey floor lit by a glowing sphere straight above the point the ray hits, which should
+    //look the same whether or not the sphere is sampled as a light.
+    #[test]
+    fn sampling_lights_matches_scattering() {
+        let grey: Arc<dyn material::Material> =
+            Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let glow: Arc<dyn material::Material> = Arc::new(Emission::new(Colour::new(2.0, 2.0, 2.0)));
+        let bulb = || Sphere::new(Vec3::new(0.0, 4.0, 0.0), 1.0, Arc::clone(&glow));
+        let floor = Quad::new(
+            Vec3::new(-50.0, 0.0, -50.0),
+            Vec3::new(0.0, 0.0, 100.0),
+            Vec3::new(100.0, 0.0, 0.0),
+            grey,
+        );
+        let world = HitableList::new(vec![Box::new(floor), Box::new(bulb())]);
+        let lights: Vec<Box<dyn Sampleable>> = vec![Box::new(bulb())];
+
+        //a lambertian surface under a sphere of radiance L, radius R and distance d gives back
+        //albedo * L * (R / d)^2.
+        let expected = 0.5 * 2.0 / 16.0;
+        let r = Ray::new(Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, -1.0, 0.0));
+        for lights in [&lights[..], &[]].iter() {
+            let samples = 100_000;
+            let total =
+                (0..samples).fold(0.0, |total, _| total + colour(&r, &world, lights, 0).r());
+            let mean = total / samples as f32;
+            assert!(
+                (mean - expected).abs() < 0.05 * expected,
+                "{} lights gave {}, not {}",
+                lights.len(),
+                mean,
+                expected
+            );
+        }
+    }
+}
+
 #[cfg(test)]
 mod benches {
     use super::*;
@@ -184,7 +376,7 @@ mod benches {
 
         let r = test::black_box(&ray);
 
-        b.iter(|| test::black_box(colour(r, &sphere, 48)));
+        b.iter(|| test::black_box(colour(r, &sphere, &[], 48)));
     }
 
     #[bench]
@@ -199,6 +391,6 @@ mod benches {
 
         let r = test::black_box(&ray);
 
-        b.iter(|| test::black_box(colour(r, &bvh, 0)));
+        b.iter(|| test::black_box(colour(r, &bvh, &[], 0)));
     }
 }
@@ -0,0 +1,603 @@
+use crate::camera::*;
+use crate::colour::*;
+use crate::hit::instancing::*;
+use crate::hit::mesh::*;
+use crate::hit::sphere::*;
+use crate::hit::*;
+use crate::material::emission::*;
+use crate::material::metallic_roughness::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::transform::*;
+use crate::vec3::*;
+
+use super::LoadError;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Everything brought in from a glTF scene. Cameras and lights are in world space, while meshes keep
+/// their node's transform, so nodes using the same mesh can share it.
+#[derive(Debug)]
+pub struct GltfScene {
+    pub meshes: Vec<GltfMesh>,
+    pub cameras: Vec<GltfCamera>,
+    pub lights: Vec<GltfLight>,
+}
+
+/// One primitive of a glTF mesh, in the mesh's own space, placed by the node that uses it.
+#[derive(Debug, Clone)]
+pub struct GltfMesh {
+    pub name: String,
+    pub mesh: Arc<Mesh>,
+    pub transform: Transform,
+}
+
+/// A perspective camera. Orthographic cameras are skipped, as the renderer has no way of drawing them.
+#[derive(Debug, Clone)]
+pub struct GltfCamera {
+    pub name: String,
+    pub position: Vec3,
+    pub forward: Vec3,
+    pub up: Vec3,
+    pub yfov: f32, // top to bottom, in radians.
+    pub aspect_ratio: Option<f32>,
+}
+
+impl GltfCamera {
+    /// A pinhole camera matching this one, for an image with the given aspect ratio.
+    pub fn to_camera(&self, aspect: f32) -> Camera {
+        Camera::new(
+            self.position,
+            self.position + self.forward,
+            self.up,
+            self.yfov.to_degrees(),
+            aspect,
+            0.0,
+            1.0,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    /// The cone angles are in radians, measured from the direction of the light.
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+/// A light from the KHR_lights_punctual extension.
+#[derive(Debug, Clone)]
+pub struct GltfLight {
+    pub name: String,
+    pub kind: LightKind,
+    pub colour: Colour,
+    pub intensity: f32, // candela for point and spot lights, lux for directional ones.
+    pub range: Option<f32>,
+    pub position: Vec3,
+    pub direction: Vec3,
+}
+
+impl GltfLight {
+    /// Point and spot lights can't be hit by rays, so this stands them in as a small glowing sphere,
+    /// as bright as the light in every direction. Directional lights have nowhere to put one.
+    pub fn to_sphere(&self, radius: f32) -> Option<Sphere> {
+        match self.kind {
+            LightKind::Directional => None,
+            LightKind::Point | LightKind::Spot { .. } => {
+                // a sphere seen from afar has an intensity of its radiance times its projected area.
+                let radiance =
+                    self.colour * (self.intensity / (std::f32::consts::PI * radius * radius));
+                Some(Sphere::new(
+                    self.position,
+                    radius,
+                    Arc::new(Emission::new(radiance)),
+                ))
+            }
+        }
+    }
+}
+
+impl GltfScene {
+    /// The meshes as instances, ready to go in to a HitableList.
+    pub fn into_hitables(self) -> Vec<Box<dyn Hitable>> {
+        self.meshes
+            .into_iter()
+            .map(|m| Box::new(Instance::new(m.mesh, m.transform)) as Box<dyn Hitable>)
+            .collect()
+    }
+}
+
+/// Loads the default scene, or else the first one, of a .gltf or .glb file. Buffers and images can
+/// be embedded or in files next to it, but nothing is fetched over the network.
+pub fn load_gltf(path: &Path) -> Result<GltfScene, LoadError> {
+    let ::gltf::Gltf { document, mut blob } = ::gltf::Gltf::open(path).map_err(|e| match e {
+        ::gltf::Error::Io(err) => LoadError::io(path, err),
+        e => LoadError::parse(path, 0, e.to_string()),
+    })?;
+
+    let buffers = document
+        .buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                ::gltf::buffer::Source::Uri(uri) => read_uri(path, uri)?,
+                ::gltf::buffer::Source::Bin => blob
+                    .take()
+                    .ok_or_else(|| LoadError::parse(path, 0, "the binary chunk is missing"))?,
+            };
+            if data.len() < buffer.length() {
+                return Err(LoadError::parse(
+                    path,
+                    0,
+                    format!(
+                        "buffer {} has {} bytes, but should have {}",
+                        buffer.index(),
+                        data.len(),
+                        buffer.length()
+                    ),
+                ));
+            }
+            Ok(data)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let images = document
+        .images()
+        .map(|image| {
+            let decoded = match image.source() {
+                ::gltf::image::Source::View { view, .. } => {
+                    let start = view.offset();
+                    let data = buffers[view.buffer().index()]
+                        .get(start..start + view.length())
+                        .ok_or_else(|| {
+                            LoadError::parse(
+                                path,
+                                0,
+                                format!("image {} is past the end of its buffer", image.index()),
+                            )
+                        })?;
+                    ::image::load_from_memory(data)
+                }
+                ::gltf::image::Source::Uri { uri, .. } => {
+                    ::image::load_from_memory(&read_uri(path, uri)?)
+                }
+            };
+            decoded
+                .map_err(|e| LoadError::parse(path, 0, format!("image {}: {}", image.index(), e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut importer = Importer {
+        buffers: &buffers,
+        images: &images,
+        prototypes: HashMap::new(),
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        scene: GltfScene {
+            meshes: vec![],
+            cameras: vec![],
+            lights: vec![],
+        },
+    };
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| LoadError::parse(path, 0, "there are no scenes in the file"))?;
+    for node in scene.nodes() {
+        importer.visit(&node, Matrix4::identity());
+    }
+    Ok(importer.scene)
+}
+
+// reads a buffer or image, either embedded in the uri as base64 or from a file next to the scene.
+fn read_uri(path: &Path, uri: &str) -> Result<Vec<u8>, LoadError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let encoded = data
+            .split(";base64,")
+            .nth(1)
+            .ok_or_else(|| LoadError::parse(path, 0, "a data uri isn't base64"))?;
+        return base64::decode(encoded)
+            .map_err(|e| LoadError::parse(path, 0, format!("a data uri has bad base64: {}", e)));
+    }
+    let file = path.with_file_name(uri);
+    std::fs::read(&file).map_err(|e| LoadError::io(&file, e))
+}
+
+struct Importer<'a> {
+    buffers: &'a [Vec<u8>],
+    images: &'a [::image::DynamicImage],
+    prototypes: HashMap<usize, Vec<Arc<Mesh>>>, // the loaded primitives of each mesh, by index.
+    textures: HashMap<(usize, bool), Arc<ImageTexture>>, // by image index, and whether it's sRGB.
+    materials: HashMap<Option<usize>, Arc<dyn Material>>,
+    scene: GltfScene,
+}
+
+impl<'a> Importer<'a> {
+    fn visit(&mut self, node: &::gltf::Node, parent: Matrix4) {
+        let world = parent * Matrix4::from_column_major(node.transform().matrix());
+        let name = node
+            .name()
+            .map(String::from)
+            .unwrap_or_else(|| format!("node {}", node.index()));
+
+        // a node that squashes its mesh flat can't be seen.
+        if let (Some(mesh), Some(_)) = (node.mesh(), world.inverse()) {
+            let transform = Transform::new(world);
+            for prototype in self.mesh(&mesh) {
+                self.scene.meshes.push(GltfMesh {
+                    name: name.clone(),
+                    mesh: prototype,
+                    transform,
+                });
+            }
+        }
+
+        // cameras and lights look down their node's -z axis.
+        let position = world.transform_point(Vec3::new(0.0, 0.0, 0.0));
+        let forward = world
+            .transform_vector(Vec3::new(0.0, 0.0, -1.0))
+            .unit_vector();
+
+        if let Some(camera) = node.camera() {
+            if let ::gltf::camera::Projection::Perspective(p) = camera.projection() {
+                self.scene.cameras.push(GltfCamera {
+                    name: name.clone(),
+                    position,
+                    forward,
+                    up: world
+                        .transform_vector(Vec3::new(0.0, 1.0, 0.0))
+                        .unit_vector(),
+                    yfov: p.yfov(),
+                    aspect_ratio: p.aspect_ratio(),
+                });
+            }
+        }
+
+        if let Some(light) = node.light() {
+            use ::gltf::khr_lights_punctual::Kind;
+            let kind = match light.kind() {
+                Kind::Directional => LightKind::Directional,
+                Kind::Point => LightKind::Point,
+                Kind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => LightKind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                },
+            };
+            let [r, g, b] = light.color();
+            self.scene.lights.push(GltfLight {
+                name: name.clone(),
+                kind,
+                colour: Colour::new(r, g, b),
+                intensity: light.intensity(),
+                range: light.range(),
+                position,
+                direction: forward,
+            });
+        }
+
+        for child in node.children() {
+            self.visit(&child, world);
+        }
+    }
+
+    // the primitives of a mesh, which are only loaded the first time a node uses it.
+    fn mesh(&mut self, mesh: &::gltf::Mesh) -> Vec<Arc<Mesh>> {
+        if let Some(prototypes) = self.prototypes.get(&mesh.index()) {
+            return prototypes.clone();
+        }
+        let prototypes: Vec<Arc<Mesh>> = mesh
+            .primitives()
+            .filter_map(|primitive| self.primitive(&primitive))
+            .map(Arc::new)
+            .collect();
+        self.prototypes.insert(mesh.index(), prototypes.clone());
+        prototypes
+    }
+
+    // the primitive as a mesh, or None if it isn't made of triangles.
+    fn primitive(&mut self, primitive: &::gltf::Primitive) -> Option<Mesh> {
+        if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+            return None;
+        }
+        let buffers = self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()][..]));
+
+        let positions: Vec<Vec3> = reader
+            .read_positions()?
+            .map(|[x, y, z]| Vec3::new(x, y, z))
+            .collect();
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .filter(|t| t.iter().all(|&i| (i as usize) < positions.len()))
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+        if triangles.is_empty() {
+            return None;
+        }
+
+        let normals = reader.read_normals().map(|normals| {
+            normals
+                .map(|[x, y, z]| Vec3::new(x, y, z).unit_vector())
+                .collect::<Vec<_>>()
+        });
+        let uvs = reader
+            .read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().collect::<Vec<_>>());
+
+        let vertex_count = positions.len();
+        let mut mesh = Mesh::new(positions, triangles, self.material(&primitive.material()));
+        if let Some(normals) = normals.filter(|n| n.len() == vertex_count) {
+            mesh = mesh.with_normals(normals);
+        }
+        if let Some(uvs) = uvs.filter(|uv| uv.len() == vertex_count) {
+            mesh = mesh.with_uvs(uvs);
+        }
+        Some(mesh)
+    }
+
+    fn material(&mut self, material: &::gltf::Material) -> Arc<dyn Material> {
+        if let Some(m) = self.materials.get(&material.index()) {
+            return Arc::clone(m);
+        }
+
+        // primitives without a material get the default one from the spec, which is what this
+        // gives for an index of None.
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let mut out = MetallicRoughness::new(
+            Colour::new(r, g, b),
+            pbr.metallic_factor(),
+            pbr.roughness_factor(),
+        );
+        if let Some(info) = pbr.base_color_texture() {
+            out = out.with_base_colour_texture(self.texture(&info.texture(), true));
+        }
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            out = out.with_metallic_roughness_texture(self.texture(&info.texture(), false));
+        }
+        let [r, g, b] = material.emissive_factor();
+        let emissive_texture = material
+            .emissive_texture()
+            .map(|info| self.texture(&info.texture(), true));
+        out = out.with_emissive(Colour::new(r, g, b), emissive_texture);
+
+        let out: Arc<dyn Material> = Arc::new(out);
+        self.materials.insert(material.index(), Arc::clone(&out));
+        out
+    }
+
+    fn texture(&mut self, texture: &::gltf::Texture, srgb: bool) -> Arc<ImageTexture> {
+        let index = texture.source().index();
+        let images = self.images;
+        let texture = self
+            .textures
+            .entry((index, srgb))
+            .or_insert_with(|| Arc::new(image_texture(&images[index], srgb)));
+        Arc::clone(texture)
+    }
+}
+
+// 16 bit images keep their full precision, and everything else is read as 8 bits.
+fn image_texture(image: &::image::DynamicImage, srgb: bool) -> ImageTexture {
+    use ::image::DynamicImage::*;
+    use ::image::GenericImageView;
+
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    match image {
+        ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => {
+            let samples = image.to_rgba16().into_raw();
+            ImageTexture::from_channels(width, height, 4, &samples, u16::MAX, srgb)
+        }
+        _ => {
+            let samples: Vec<u16> = image.to_rgba8().iter().map(|&b| b as u16).collect();
+            ImageTexture::from_channels(width, height, 4, &samples, u8::MAX as u16, srgb)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::*;
+
+    // a triangle with corners at the origin, along x and along y, facing +z.
+    fn triangle_bytes() -> Vec<u8> {
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        positions.iter().flat_map(|p| p.to_le_bytes()).collect()
+    }
+
+    const TRIANGLE: &str = r#"
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}}]}],
+        "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                       "min": [0, 0, 0], "max": [1, 1, 0]}],
+        "bufferViews": [{"buffer": 0, "byteLength": 36}],"#;
+
+    // writes the files into a directory of their own, and loads the first of them.
+    fn load(files: &[(&str, &[u8])]) -> GltfScene {
+        let dir =
+            std::env::temp_dir().join(format!("gltf_test_{}_{}", std::process::id(), files[0].0));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let scene = load_gltf(&dir.join(files[0].0));
+        std::fs::remove_dir_all(&dir).unwrap();
+        scene.unwrap()
+    }
+
+    #[test]
+    fn gltf_places_shared_meshes_with_node_transforms() {
+        // the triangle under a translated parent and a scaled child, and again mirrored in x.
+        let json = format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            "scene": 0,
+            "scenes": [{{"nodes": [0, 2]}}],
+            "nodes": [
+                {{"translation": [0, 0, -5], "children": [1]}},
+                {{"scale": [2, 2, 2], "mesh": 0}},
+                {{"translation": [10, 0, 0], "scale": [-1, 1, 1], "mesh": 0}}
+            ],{}
+            "buffers": [{{"uri": "tri.bin", "byteLength": 36}}]
+        }}"#,
+            TRIANGLE
+        );
+        let scene = load(&[
+            ("shared.gltf", json.as_bytes()),
+            ("tri.bin", &triangle_bytes()),
+        ]);
+
+        assert_eq!(scene.meshes.len(), 2);
+        assert!(Arc::ptr_eq(&scene.meshes[0].mesh, &scene.meshes[1].mesh));
+        assert_eq!(
+            scene.meshes[0].mesh.positions()[1],
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+
+        let instances = scene.into_hitables();
+        let down = Vec3::new(0.0, 0.0, -1.0);
+
+        let r = Ray::new(Vec3::new(1.5, 0.3, 0.0), down);
+        let rec = instances[0].hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-5, "hit at {}", rec.t);
+        assert!(rec.front_face);
+        assert!(instances[1].hit(&r, 0.001, f32::MAX).is_none());
+
+        // mirroring keeps the triangle facing +z.
+        let r = Ray::new(Vec3::new(9.7, 0.2, 1.0), down);
+        let rec = instances[1].hit(&r, 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-5, "hit at {}", rec.t);
+        assert!(rec.front_face);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn gltf_places_cameras_and_lights() {
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        // a camera turned a quarter of the way around y, an orthographic camera that is skipped, a
+        // point light, a spot light pointing down, and a directional light left as it is. The
+        // triangle comes from base64 data in the file.
+        let json = format!(
+            r#"{{
+            "asset": {{"version": "2.0"}},
+            "extensionsUsed": ["KHR_lights_punctual"],
+            "extensions": {{"KHR_lights_punctual": {{"lights": [
+                {{"type": "point", "color": [1, 0.5, 0.25], "intensity": 10, "range": 5}},
+                {{"type": "spot", "spot": {{"innerConeAngle": 0.2, "outerConeAngle": 0.4}}}},
+                {{"type": "directional", "intensity": 3}}
+            ]}}}},
+            "scenes": [{{"nodes": [0, 1, 2, 3, 4, 5]}}],
+            "nodes": [
+                {{"name": "cam", "translation": [1, 2, 3], "rotation": [0, {s}, 0, {s}], "camera": 0}},
+                {{"camera": 1}},
+                {{"translation": [0, 4, 0],
+                  "extensions": {{"KHR_lights_punctual": {{"light": 0}}}}}},
+                {{"rotation": [-{s}, 0, 0, {s}],
+                  "extensions": {{"KHR_lights_punctual": {{"light": 1}}}}}},
+                {{"extensions": {{"KHR_lights_punctual": {{"light": 2}}}}}},
+                {{"mesh": 0}}
+            ],
+            "cameras": [
+                {{"type": "perspective",
+                  "perspective": {{"yfov": 0.5, "aspectRatio": 1.5, "znear": 0.1}}}},
+                {{"type": "orthographic",
+                  "orthographic": {{"xmag": 1, "ymag": 1, "zfar": 10, "znear": 0.1}}}}
+            ],{triangle}
+            "buffers": [{{"uri": "data:application/octet-stream;base64,{data}", "byteLength": 36}}]
+        }}"#,
+            s = s,
+            triangle = TRIANGLE,
+            data = base64::encode(triangle_bytes())
+        );
+        let scene = load(&[("lit.gltf", json.as_bytes())]);
+
+        assert_eq!(scene.meshes.len(), 1);
+
+        assert_eq!(scene.cameras.len(), 1);
+        let cam = &scene.cameras[0];
+        assert_eq!(cam.name, "cam");
+        assert!((cam.position - Vec3::new(1.0, 2.0, 3.0)).length() < 1e-5);
+        assert!(
+            (cam.forward - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-5,
+            "{:?}",
+            cam.forward
+        );
+        assert!(
+            (cam.up - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-5,
+            "{:?}",
+            cam.up
+        );
+        assert_eq!(cam.yfov, 0.5);
+        assert_eq!(cam.aspect_ratio, Some(1.5));
+
+        assert_eq!(scene.lights.len(), 3);
+        let point = &scene.lights[0];
+        assert_eq!(point.kind, LightKind::Point);
+        assert_eq!(point.colour.g(), 0.5);
+        assert_eq!(point.intensity, 10.0);
+        assert_eq!(point.range, Some(5.0));
+        assert!((point.position - Vec3::new(0.0, 4.0, 0.0)).length() < 1e-5);
+
+        let spot = &scene.lights[1];
+        assert_eq!(
+            spot.kind,
+            LightKind::Spot {
+                inner_cone_angle: 0.2,
+                outer_cone_angle: 0.4
+            }
+        );
+        assert!(
+            (spot.direction - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-5,
+            "{:?}",
+            spot.direction
+        );
+
+        let sun = &scene.lights[2];
+        assert_eq!(sun.kind, LightKind::Directional);
+        assert_eq!(sun.intensity, 3.0);
+        assert!((sun.direction - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-5);
+        assert!(sun.to_sphere(0.1).is_none());
+    }
+
+    #[test]
+    fn gltf_reads_16_bit_textures() {
+        // a single pixel, with a different byte set in each channel, so swapped bytes would show.
+        let mut png_bytes = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut png_bytes, 1, 1);
+            encoder.set_color(png::ColorType::RGB);
+            encoder.set_depth(png::BitDepth::Sixteen);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[0x80, 0x00, 0x00, 0xff, 0xff, 0x00])
+                .unwrap();
+        }
+        let image = ::image::load_from_memory(&png_bytes).unwrap();
+        let colour = image_texture(&image, false).colour(0.5, 0.5);
+
+        let expected = [0x8000, 0x00ff, 0xff00].map(|c| c as f32 / 65535.0);
+        for (channel, &value) in [colour.r(), colour.g(), colour.b()].iter().enumerate() {
+            assert!(
+                (value - expected[channel]).abs() < 1e-6,
+                "channel {} is {}, not {}",
+                channel,
+                value,
+                expected[channel]
+            );
+        }
+    }
+}
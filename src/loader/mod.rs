@@ -0,0 +1,94 @@
+pub mod gltf;
+pub mod obj;
+pub mod ply;
+pub mod stl;
+
+use std::path::{Path, PathBuf};
+
+//an error from loading a scene file, pointing at where in which file it went wrong.
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        path: PathBuf,
+        err: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize, //0 when the problem isn't on any one line.
+        message: String,
+    },
+}
+
+impl LoadError {
+    pub(crate) fn io(path: &Path, err: std::io::Error) -> LoadError {
+        LoadError::Io {
+            path: path.to_path_buf(),
+            err,
+        }
+    }
+
+    pub(crate) fn parse(path: &Path, line: usize, message: impl Into<String>) -> LoadError {
+        LoadError::Parse {
+            path: path.to_path_buf(),
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io { path, err } => write!(f, "{}: {}", path.display(), err),
+            LoadError::Parse {
+                path,
+                line: 0,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+            LoadError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { err, .. } => Some(err),
+            LoadError::Parse { .. } => None,
+        }
+    }
+}
+
+//parses every token as a float, making sure there are between min and max of them.
+pub(crate) fn parse_floats(
+    path: &Path,
+    line: usize,
+    tokens: &[&str],
+    min: usize,
+    max: usize,
+) -> Result<Vec<f32>, LoadError> {
+    if tokens.len() < min || tokens.len() > max {
+        let expected = if min == max {
+            format!("{}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("expected {} numbers, but found {}", expected, tokens.len()),
+        ));
+    }
+
+    tokens
+        .iter()
+        .map(|t| {
+            t.parse::<f32>()
+                .map_err(|_| LoadError::parse(path, line, format!("'{}' is not a number", t)))
+        })
+        .collect()
+}
@@ -0,0 +1,545 @@
+use crate::colour::*;
+use crate::hit::mesh::*;
+use crate::hit::subdivision::*;
+use crate::material::dielectric::*;
+use crate::material::emission::*;
+use crate::material::glossy::*;
+use crate::material::lambertian::*;
+use crate::material::*;
+use crate::vec3::*;
+
+use super::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::sync::Arc;
+
+/// The triangles of one group of an OBJ file that share a material, as a single mesh.
+#[derive(Debug)]
+pub struct ObjGroup {
+    pub name: String,
+    pub material_name: Option<String>,
+    pub mesh: Mesh,
+}
+
+/// The groups of an OBJ file, along with anything that was wrong with the file but didn't stop it
+/// loading, such as a missing MTL file, for the caller to show.
+#[derive(Debug)]
+pub struct ObjScene {
+    pub groups: Vec<ObjGroup>,
+    pub warnings: Vec<String>,
+}
+
+/// Loads a Wavefront OBJ file, along with any MTL files it references, into a mesh for every
+/// group and material. Polygons are triangulated as fans, so they should be convex.
+pub fn load_obj(path: &Path) -> Result<ObjScene, LoadError> {
+    let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+    parse_obj(BufReader::new(file), path)
+}
+
+/// Loads the polygons of an OBJ file as a control cage for subdivision, keeping quads and other
+/// polygons whole. Every group goes into the one cage, and texture coordinates, normals and
+/// materials are left out, as subdivision makes its own normals.
+pub fn load_obj_cage(path: &Path) -> Result<ControlCage, LoadError> {
+    let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+    parse_obj_cage(BufReader::new(file), path)
+}
+
+/// Loads the materials of an MTL file by name.
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, LoadError> {
+    let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+    parse_mtl(BufReader::new(file), path)
+}
+
+// a corner of a face, as zero based indices into the position, uv and normal lists.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+// the faces collected so far for one group and material.
+#[derive(Default)]
+struct Builder {
+    positions: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    normals: Vec<Vec3>,
+    has_uvs: bool,
+    has_normals: bool,
+    indices: Vec<[u32; 3]>,
+    unique: HashMap<Corner, u32>,
+}
+
+impl Builder {
+    fn vertex(&mut self, corner: Corner, obj: &ObjData) -> u32 {
+        if let Some(&i) = self.unique.get(&corner) {
+            return i;
+        }
+        let (p, t, n) = corner;
+        let i = self.positions.len() as u32;
+        self.positions.push(obj.positions[p]);
+        self.uvs.push(t.map_or([0.0, 0.0], |t| obj.uvs[t]));
+        self.normals
+            .push(n.map_or(Vec3::new(0.0, 0.0, 0.0), |n| obj.normals[n]));
+        // only keep uvs and normals if every vertex has them.
+        if self.positions.len() == 1 {
+            self.has_uvs = t.is_some();
+            self.has_normals = n.is_some();
+        } else {
+            self.has_uvs &= t.is_some();
+            self.has_normals &= n.is_some();
+        }
+        self.unique.insert(corner, i);
+        i
+    }
+
+    fn build(self, material: Arc<dyn Material>) -> Mesh {
+        let mut mesh = Mesh::new(self.positions, self.indices, material);
+        if self.has_normals {
+            mesh = mesh.with_normals(self.normals);
+        }
+        if self.has_uvs {
+            mesh = mesh.with_uvs(self.uvs);
+        }
+        mesh
+    }
+}
+
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Vec3>,
+    uvs: Vec<[f32; 2]>,
+    normals: Vec<Vec3>,
+}
+
+// calls handle with the line number, keyword and arguments of every line that isn't blank or a comment.
+fn for_each_line<R: BufRead>(
+    reader: R,
+    path: &Path,
+    mut handle: impl FnMut(usize, &str, &[&str]) -> Result<(), LoadError>,
+) -> Result<(), LoadError> {
+    for (number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| LoadError::io(path, e))?;
+        let line = line.split('#').next().unwrap();
+        let mut tokens = line.split_whitespace();
+        if let Some(keyword) = tokens.next() {
+            let args: Vec<&str> = tokens.collect();
+            handle(number + 1, keyword, &args)?;
+        }
+    }
+    Ok(())
+}
+
+impl ObjData {
+    // reads a v, vt or vn line into the lists, returning false for any other keyword.
+    fn read_vertex_data(
+        &mut self,
+        keyword: &str,
+        args: &[&str],
+        path: &Path,
+        number: usize,
+    ) -> Result<bool, LoadError> {
+        match keyword {
+            "v" => {
+                // a fourth weight, or vertex colours after it, are ignored.
+                let v = parse_floats(path, number, args, 3, 7)?;
+                self.positions.push(Vec3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let t = parse_floats(path, number, args, 1, 3)?;
+                self.uvs.push([t[0], t.get(1).copied().unwrap_or(0.0)]);
+            }
+            "vn" => {
+                let n = parse_floats(path, number, args, 3, 3)?;
+                let normal = Vec3::new(n[0], n[1], n[2]);
+                if normal.squared_length() == 0.0 {
+                    return Err(LoadError::parse(
+                        path,
+                        number,
+                        "a normal can't be zero length",
+                    ));
+                }
+                self.normals.push(normal.unit_vector());
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+// parses the corners of an f line.
+fn parse_face(
+    args: &[&str],
+    obj: &ObjData,
+    path: &Path,
+    number: usize,
+) -> Result<Vec<Corner>, LoadError> {
+    if args.len() < 3 {
+        return Err(LoadError::parse(
+            path,
+            number,
+            format!("a face needs at least 3 vertices, but has {}", args.len()),
+        ));
+    }
+    args.iter()
+        .map(|a| parse_corner(a, obj, path, number))
+        .collect()
+}
+
+fn parse_obj<R: BufRead>(reader: R, path: &Path) -> Result<ObjScene, LoadError> {
+    let default_material: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.8, 0.8, 0.8)));
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+
+    let mut obj = ObjData::default();
+    let mut group = String::from("default");
+    let mut material_name: Option<String> = None;
+
+    // builders are kept in the order they were first used, so the groups come out in file order.
+    let mut keys: Vec<(String, Option<String>)> = vec![];
+    let mut builders: HashMap<(String, Option<String>), Builder> = HashMap::new();
+    let mut warnings = vec![];
+
+    for_each_line(reader, path, |number, keyword, args| {
+        if obj.read_vertex_data(keyword, args, path, number)? {
+            return Ok(());
+        }
+        match keyword {
+            "f" => {
+                let corners = parse_face(args, &obj, path, number)?;
+
+                let key = (group.clone(), material_name.clone());
+                if !builders.contains_key(&key) {
+                    keys.push(key.clone());
+                }
+                let builder = builders.entry(key).or_default();
+                let first = builder.vertex(corners[0], &obj);
+                for pair in corners[1..].windows(2) {
+                    let b = builder.vertex(pair[0], &obj);
+                    let c = builder.vertex(pair[1], &obj);
+                    builder.indices.push([first, b, c]);
+                }
+            }
+            "g" | "o" => {
+                group = if args.is_empty() {
+                    String::from("default")
+                } else {
+                    args.join(" ")
+                };
+            }
+            // exported files often name materials that were never written out, so these fall back
+            // to the default material rather than failing the whole load.
+            "usemtl" => {
+                let name = args.join(" ");
+                if materials.contains_key(&name) {
+                    material_name = Some(name);
+                } else {
+                    warnings.push(format!(
+                        "{}:{}: unknown material '{}', using the default",
+                        path.display(),
+                        number,
+                        name
+                    ));
+                    material_name = None;
+                }
+            }
+            "mtllib" => {
+                for file in args.iter() {
+                    let mtl_path = path.with_file_name(file);
+                    match load_mtl(&mtl_path) {
+                        Ok(loaded) => materials.extend(loaded),
+                        Err(err) => warnings.push(format!("{}, using the default material", err)),
+                    }
+                }
+            }
+            // smoothing groups, lines, points and the like don't make triangles.
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    let mut groups = Vec::with_capacity(keys.len());
+    for key in keys {
+        let builder = builders.remove(&key).unwrap();
+        let (name, material_name) = key;
+        let material = match &material_name {
+            Some(m) => Arc::clone(&materials[m]),
+            None => Arc::clone(&default_material),
+        };
+        groups.push(ObjGroup {
+            name,
+            material_name,
+            mesh: builder.build(material),
+        });
+    }
+
+    if groups.is_empty() {
+        return Err(LoadError::parse(path, 0, "there are no faces in the file"));
+    }
+    Ok(ObjScene { groups, warnings })
+}
+
+fn parse_obj_cage<R: BufRead>(reader: R, path: &Path) -> Result<ControlCage, LoadError> {
+    let mut obj = ObjData::default();
+    let mut faces = vec![];
+
+    for_each_line(reader, path, |number, keyword, args| {
+        if !obj.read_vertex_data(keyword, args, path, number)? && keyword == "f" {
+            let face = parse_face(args, &obj, path, number)?;
+            faces.push(face.iter().map(|&(p, _, _)| p as u32).collect());
+        }
+        Ok(())
+    })?;
+
+    if faces.is_empty() {
+        return Err(LoadError::parse(path, 0, "there are no faces in the file"));
+    }
+    Ok(ControlCage::new(obj.positions, faces))
+}
+
+// parses a face corner in any of the forms v, v/vt, v//vn or v/vt/vn.
+fn parse_corner(
+    corner: &str,
+    obj: &ObjData,
+    path: &Path,
+    line: usize,
+) -> Result<Corner, LoadError> {
+    let mut parts = corner.split('/');
+    let p = parse_index(parts.next(), obj.positions.len(), "vertex", path, line)?
+        .ok_or_else(|| LoadError::parse(path, line, format!("'{}' has no vertex index", corner)))?;
+    let t = parse_index(
+        parts.next(),
+        obj.uvs.len(),
+        "texture coordinate",
+        path,
+        line,
+    )?;
+    let n = parse_index(parts.next(), obj.normals.len(), "normal", path, line)?;
+    if parts.next().is_some() {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!("'{}' has too many parts", corner),
+        ));
+    }
+    Ok((p, t, n))
+}
+
+// turns a one based index, or a negative index counting back from the end, into a zero based one.
+fn parse_index(
+    part: Option<&str>,
+    count: usize,
+    kind: &str,
+    path: &Path,
+    line: usize,
+) -> Result<Option<usize>, LoadError> {
+    let part = match part {
+        Some(p) if !p.is_empty() => p,
+        _ => return Ok(None),
+    };
+    let index: i64 = part
+        .parse()
+        .map_err(|_| LoadError::parse(path, line, format!("'{}' is not an index", part)))?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(LoadError::parse(
+            path,
+            line,
+            format!(
+                "{} index {} is out of range of the {} so far",
+                kind, index, count
+            ),
+        ));
+    }
+    Ok(Some(resolved as usize))
+}
+
+// the settings of an MTL material that are used to pick a material for it.
+struct MtlSettings {
+    diffuse: Colour,
+    specular: Colour,
+    shininess: f32,
+    ior: f32,
+    dissolve: f32,
+    emission: Colour,
+}
+
+impl Default for MtlSettings {
+    fn default() -> MtlSettings {
+        MtlSettings {
+            diffuse: Colour::new(0.8, 0.8, 0.8),
+            specular: Colour::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            emission: Colour::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl MtlSettings {
+    fn to_material(&self) -> Arc<dyn Material> {
+        let is_black = |c: Colour| c.r() <= 0.0 && c.g() <= 0.0 && c.b() <= 0.0;
+
+        if !is_black(self.emission) {
+            Arc::new(Emission::new(self.emission))
+        } else if self.dissolve < 1.0 {
+            Arc::new(Dielectric::new(self.ior))
+        } else if !is_black(self.specular) {
+            // the usual conversion from a Phong exponent to a roughness.
+            let roughness = (2.0 / (self.shininess + 2.0)).sqrt();
+            Arc::new(Glossy::new(self.diffuse, self.specular, roughness))
+        } else {
+            Arc::new(Lambertian::new(self.diffuse))
+        }
+    }
+}
+
+fn parse_mtl<R: BufRead>(
+    reader: R,
+    path: &Path,
+) -> Result<HashMap<String, Arc<dyn Material>>, LoadError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlSettings)> = None;
+
+    for_each_line(reader, path, |number, keyword, args| {
+        if keyword == "newmtl" {
+            if let Some((name, settings)) = current.take() {
+                materials.insert(name, settings.to_material());
+            }
+            current = Some((args.join(" "), MtlSettings::default()));
+            return Ok(());
+        }
+
+        let settings = match &mut current {
+            Some((_, s)) => s,
+            None if keyword.starts_with("map_") => return Ok(()),
+            None => {
+                return Err(LoadError::parse(
+                    path,
+                    number,
+                    format!("'{}' comes before any newmtl", keyword),
+                ))
+            }
+        };
+        let colour = |args: &[&str]| -> Result<Colour, LoadError> {
+            let c = parse_floats(path, number, args, 1, 3)?;
+            // a single value is a grey.
+            Ok(match c.len() {
+                3 => Colour::new(c[0], c[1], c[2]),
+                _ => Colour::new(c[0], c[0], c[0]),
+            })
+        };
+
+        match keyword {
+            "Kd" => settings.diffuse = colour(args)?,
+            "Ks" => settings.specular = colour(args)?,
+            "Ke" => settings.emission = colour(args)?,
+            "Ns" => settings.shininess = parse_floats(path, number, args, 1, 1)?[0],
+            "Ni" => settings.ior = parse_floats(path, number, args, 1, 1)?[0],
+            "d" => settings.dissolve = parse_floats(path, number, args, 1, 1)?[0],
+            "Tr" => settings.dissolve = 1.0 - parse_floats(path, number, args, 1, 1)?[0],
+            // texture maps, illumination models and the rest aren't supported.
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    if let Some((name, settings)) = current {
+        materials.insert(name, settings.to_material());
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_parses_faces_and_reports_errors() {
+        let text = "\
+# a quad and a triangle, using negative indices
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+g quad
+f 1//1 2//1 3//1 4//1
+g tri
+f -4 -3 -2
+";
+        let groups = parse_obj(text.as_bytes(), Path::new("test.obj"))
+            .unwrap()
+            .groups;
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "quad");
+        assert_eq!(groups[0].mesh.triangle_count(), 2);
+        assert!(groups[0].mesh.normals().is_some());
+        assert_eq!(groups[1].mesh.triangle_count(), 1);
+        assert!(groups[1].mesh.normals().is_none());
+
+        let bad = "v 0 0 0\nv 1 0\n";
+        match parse_obj(bad.as_bytes(), Path::new("bad.obj")) {
+            Err(LoadError::Parse { line, .. }) => assert_eq!(line, 2),
+            other => panic!(
+                "expected a parse error, got {:?}",
+                other.map(|s| s.groups.len())
+            ),
+        }
+
+        let zero_normal = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 0\nf 1//1 2//1 3//1\n";
+        match parse_obj(zero_normal.as_bytes(), Path::new("bad.obj")) {
+            Err(LoadError::Parse { line, .. }) => assert_eq!(line, 4),
+            other => panic!(
+                "expected a parse error, got {:?}",
+                other.map(|s| s.groups.len())
+            ),
+        }
+    }
+
+    #[test]
+    fn obj_cage_keeps_polygons_whole() {
+        let text = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+f 1/1 2/1 3/1 4/1
+f -4 -3 -2
+";
+        let cage = parse_obj_cage(text.as_bytes(), Path::new("test.obj")).unwrap();
+        assert_eq!(cage.faces(), &[vec![0, 1, 2, 3], vec![0, 1, 2]][..]);
+
+        let bad = "v 0 0 0\nv 1 0 0\nf 1 2\n";
+        match parse_obj_cage(bad.as_bytes(), Path::new("bad.obj")) {
+            Err(LoadError::Parse { line, .. }) => assert_eq!(line, 3),
+            other => panic!(
+                "expected a parse error, got {:?}",
+                other.map(|c| c.faces().len())
+            ),
+        }
+    }
+
+    #[test]
+    fn obj_falls_back_to_default_material() {
+        let text = "mtllib does_not_exist.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+usemtl missing
+f 1 2 3
+";
+        let scene = parse_obj(text.as_bytes(), Path::new("test.obj")).unwrap();
+        assert_eq!(scene.groups.len(), 1);
+        assert_eq!(scene.groups[0].material_name, None);
+        assert_eq!(scene.groups[0].mesh.triangle_count(), 1);
+
+        // both the missing file and the missing material are passed back.
+        assert_eq!(scene.warnings.len(), 2, "{:?}", scene.warnings);
+        assert!(scene.warnings[0].contains("does_not_exist.mtl"));
+        assert!(scene.warnings[1].starts_with("test.obj:5: unknown material 'missing'"));
+    }
+}
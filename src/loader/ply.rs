@@ -0,0 +1,514 @@
+use crate::colour::*;
+use crate::hit::mesh::*;
+use crate::material::*;
+use crate::texture::*;
+use crate::vec3::*;
+
+use super::LoadError;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+// the most vertices or faces space is set aside for up front. The counts in the header aren't
+// checked against anything, so a corrupt one could otherwise ask for gigabytes before any are read.
+const MAX_RESERVE: usize = 1 << 20;
+
+/// Loads an ASCII or binary PLY file as a mesh. Per-vertex normals and colours are kept if the file
+/// has them, and polygons are triangulated as fans.
+///
+/// Vertices and faces are read straight into the mesh's buffers, so the only other memory needed
+/// is what the mesh uses to build its BVH.
+pub fn load_ply(path: &Path, material: Arc<dyn Material>) -> Result<Mesh, LoadError> {
+    let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+    read_ply(BufReader::new(file), path, material)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // what full brightness is for a colour stored in this type.
+    fn colour_scale(self) -> f32 {
+        match self {
+            Scalar::U8 | Scalar::I8 => 1.0 / 255.0,
+            Scalar::U16 | Scalar::I16 => 1.0 / 65535.0,
+            Scalar::U32 | Scalar::I32 => 1.0 / u32::MAX as f32,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: Scalar,
+    list_count: Option<Scalar>, // the type of the length of a list property.
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+// reads one element at a time, in whichever format the file is in.
+struct ElementReader<'a, R> {
+    reader: R,
+    format: Format,
+    path: &'a Path,
+    line: usize, // for ASCII files.
+    text: String,
+    values: Vec<f64>,   // the values of the last element read, with lists flattened.
+    starts: Vec<usize>, // where each property's values start.
+}
+
+impl<'a, R: BufRead> ElementReader<'a, R> {
+    fn error(&self, message: String) -> LoadError {
+        match self.format {
+            Format::Ascii => LoadError::parse(self.path, self.line, message),
+            _ => LoadError::parse(self.path, 0, message),
+        }
+    }
+
+    fn read(&mut self, element: &Element, index: usize) -> Result<(), LoadError> {
+        self.values.clear();
+        self.starts.clear();
+
+        if self.format == Format::Ascii {
+            self.text.clear();
+            self.line += 1;
+            let read = self
+                .reader
+                .read_line(&mut self.text)
+                .map_err(|e| LoadError::io(self.path, e))?;
+            if read == 0 {
+                return Err(self.error(format!("the file ends before {} {}", element.name, index)));
+            }
+            let text = std::mem::take(&mut self.text);
+            let result = self.read_ascii(element, &text);
+            self.text = text;
+            return result;
+        }
+
+        for property in element.properties.iter() {
+            self.starts.push(self.values.len());
+            match property.list_count {
+                Some(count_kind) => {
+                    let count = self.read_binary(count_kind, element, index)?;
+                    self.values.push(count);
+                    for _ in 0..count as usize {
+                        let value = self.read_binary(property.kind, element, index)?;
+                        self.values.push(value);
+                    }
+                }
+                None => {
+                    let value = self.read_binary(property.kind, element, index)?;
+                    self.values.push(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_ascii(&mut self, element: &Element, text: &str) -> Result<(), LoadError> {
+        let mut tokens = text.split_whitespace();
+        let mut next = |this: &Self| -> Result<f64, LoadError> {
+            let token = tokens
+                .next()
+                .ok_or_else(|| this.error(format!("{} has too few values", element.name)))?;
+            token
+                .parse::<f64>()
+                .map_err(|_| this.error(format!("'{}' is not a number", token)))
+        };
+
+        for property in element.properties.iter() {
+            self.starts.push(self.values.len());
+            let first = next(self)?;
+            self.values.push(first);
+            if property.list_count.is_some() {
+                for _ in 0..first as usize {
+                    let value = next(self)?;
+                    self.values.push(value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_binary(
+        &mut self,
+        kind: Scalar,
+        element: &Element,
+        index: usize,
+    ) -> Result<f64, LoadError> {
+        let mut bytes = [0u8; 8];
+        let bytes = &mut bytes[..kind.size()];
+        if let Err(e) = self.reader.read_exact(bytes) {
+            return Err(if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                self.error(format!(
+                    "the file ends part way through {} {}",
+                    element.name, index
+                ))
+            } else {
+                LoadError::io(self.path, e)
+            });
+        }
+        if self.format == Format::BinaryBigEndian {
+            bytes.reverse();
+        }
+
+        // the bytes are little endian now.
+        Ok(match kind {
+            Scalar::I8 => bytes[0] as i8 as f64,
+            Scalar::U8 => bytes[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7],
+            ]),
+        })
+    }
+}
+
+fn read_header<R: BufRead>(
+    reader: &mut R,
+    path: &Path,
+) -> Result<(Format, Vec<Element>, usize), LoadError> {
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut text = String::new();
+    let mut line = 0;
+
+    loop {
+        text.clear();
+        line += 1;
+        let read = reader
+            .read_line(&mut text)
+            .map_err(|e| LoadError::io(path, e))?;
+        if read == 0 {
+            return Err(LoadError::parse(path, line, "the header has no end_header"));
+        }
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+
+        if line == 1 {
+            if tokens != ["ply"] {
+                return Err(LoadError::parse(path, line, "this isn't a PLY file"));
+            }
+            continue;
+        }
+
+        match tokens.as_slice() {
+            ["format", kind, _version] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => {
+                        return Err(LoadError::parse(
+                            path,
+                            line,
+                            format!("unknown format '{}'", kind),
+                        ))
+                    }
+                })
+            }
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| {
+                    LoadError::parse(path, line, format!("'{}' is not a count", count))
+                })?;
+                elements.push(Element {
+                    name: name.to_string(),
+                    count,
+                    properties: vec![],
+                });
+            }
+            ["property", rest @ ..] => {
+                let element = elements.last_mut().ok_or_else(|| {
+                    LoadError::parse(path, line, "a property comes before any element")
+                })?;
+                let scalar = |name: &str| {
+                    Scalar::parse(name).ok_or_else(|| {
+                        LoadError::parse(path, line, format!("unknown type '{}'", name))
+                    })
+                };
+                let property = match rest {
+                    ["list", count_kind, kind, name] => Property {
+                        name: name.to_string(),
+                        kind: scalar(kind)?,
+                        list_count: Some(scalar(count_kind)?),
+                    },
+                    [kind, name] => Property {
+                        name: name.to_string(),
+                        kind: scalar(kind)?,
+                        list_count: None,
+                    },
+                    _ => return Err(LoadError::parse(path, line, "malformed property")),
+                };
+                element.properties.push(property);
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => {
+                return Err(LoadError::parse(
+                    path,
+                    line,
+                    format!("unexpected '{}' in the header", text.trim()),
+                ))
+            }
+        }
+    }
+
+    let format =
+        format.ok_or_else(|| LoadError::parse(path, line, "the header has no format line"))?;
+    Ok((format, elements, line))
+}
+
+fn read_ply<R: BufRead>(
+    mut reader: R,
+    path: &Path,
+    material: Arc<dyn Material>,
+) -> Result<Mesh, LoadError> {
+    let (format, elements, header_lines) = read_header(&mut reader, path)?;
+
+    let mut elements_reader = ElementReader {
+        reader,
+        format,
+        path,
+        line: header_lines,
+        text: String::new(),
+        values: vec![],
+        starts: vec![],
+    };
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut colours: Vec<Colour> = vec![];
+    let mut triangles: Vec<[u32; 3]> = vec![];
+    let mut vertex_count = None;
+
+    for element in elements.iter() {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: &[&str]| {
+                    element.find(names).ok_or_else(|| {
+                        LoadError::parse(
+                            path,
+                            0,
+                            format!("vertices have no '{}' property", names[0]),
+                        )
+                    })
+                };
+                let xyz = [find(&["x"])?, find(&["y"])?, find(&["z"])?];
+                let normal = match (
+                    element.find(&["nx"]),
+                    element.find(&["ny"]),
+                    element.find(&["nz"]),
+                ) {
+                    (Some(x), Some(y), Some(z)) => Some([x, y, z]),
+                    _ => None,
+                };
+                let colour = match (
+                    element.find(&["red", "diffuse_red", "r"]),
+                    element.find(&["green", "diffuse_green", "g"]),
+                    element.find(&["blue", "diffuse_blue", "b"]),
+                ) {
+                    (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+                    _ => None,
+                };
+
+                let reserve = element.count.min(MAX_RESERVE);
+                positions.reserve_exact(reserve);
+                if normal.is_some() {
+                    normals.reserve_exact(reserve);
+                }
+                if colour.is_some() {
+                    colours.reserve_exact(reserve);
+                }
+
+                for i in 0..element.count {
+                    elements_reader.read(element, i)?;
+                    let (values, starts) = (&elements_reader.values, &elements_reader.starts);
+                    let get = |[x, y, z]: [usize; 3]| {
+                        [
+                            values[starts[x]] as f32,
+                            values[starts[y]] as f32,
+                            values[starts[z]] as f32,
+                        ]
+                    };
+
+                    let [x, y, z] = get(xyz);
+                    positions.push(Vec3::new(x, y, z));
+                    if let Some(n) = normal {
+                        let [x, y, z] = get(n);
+                        normals.push(Vec3::new(x, y, z).unit_vector());
+                    }
+                    if let Some(c) = colour {
+                        // colours are stored in sRGB.
+                        let scale = element.properties[c[0]].kind.colour_scale();
+                        let [r, g, b] = get(c);
+                        colours.push(Colour::new(
+                            srgb_to_linear(r * scale),
+                            srgb_to_linear(g * scale),
+                            srgb_to_linear(b * scale),
+                        ));
+                    }
+                }
+                vertex_count = Some(positions.len());
+            }
+            "face" => {
+                let vertex_count = vertex_count
+                    .ok_or_else(|| LoadError::parse(path, 0, "faces come before the vertices"))?;
+                let list = element
+                    .find(&["vertex_indices", "vertex_index"])
+                    .ok_or_else(|| {
+                        LoadError::parse(path, 0, "faces have no vertex_indices property")
+                    })?;
+
+                triangles.reserve(element.count.min(MAX_RESERVE));
+                for i in 0..element.count {
+                    elements_reader.read(element, i)?;
+                    let start = elements_reader.starts[list];
+                    let count = elements_reader.values[start] as usize;
+                    let corners = &elements_reader.values[start + 1..start + 1 + count];
+
+                    if corners
+                        .iter()
+                        .any(|&c| c < 0.0 || c as usize >= vertex_count)
+                    {
+                        return Err(elements_reader
+                            .error(format!("face {} uses a vertex that isn't in the file", i)));
+                    }
+                    for pair in corners.get(1..).unwrap_or(&[]).windows(2) {
+                        triangles.push([corners[0] as u32, pair[0] as u32, pair[1] as u32]);
+                    }
+                }
+            }
+            // anything else, like edges, still has to be read past.
+            _ => {
+                for i in 0..element.count {
+                    elements_reader.read(element, i)?;
+                }
+            }
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err(LoadError::parse(path, 0, "there are no faces in the file"));
+    }
+
+    let mut mesh = Mesh::new(positions, triangles, material);
+    if !normals.is_empty() {
+        mesh = mesh.with_normals(normals);
+    }
+    if !colours.is_empty() {
+        mesh = mesh.with_colours(colours);
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn ply_ascii_and_binary_match() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+
+        let header = |format: &str| {
+            format!(
+                "ply\nformat {} 1.0\ncomment a square\nelement vertex 4\n\
+                 property float x\nproperty float y\nproperty float z\n\
+                 property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                 element face 1\nproperty list uchar int vertex_indices\nend_header\n",
+                format
+            )
+        };
+        let corners = [
+            [0.0f32, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+
+        let mut ascii = header("ascii");
+        let mut binary = header("binary_big_endian").into_bytes();
+        for c in corners.iter() {
+            ascii += &format!("{} {} {} 255 0 0\n", c[0], c[1], c[2]);
+            for x in c.iter() {
+                binary.extend_from_slice(&x.to_be_bytes());
+            }
+            binary.extend_from_slice(&[255, 0, 0]);
+        }
+        ascii += "4 0 1 2 3\n";
+        binary.push(4);
+        for i in 0..4i32 {
+            binary.extend_from_slice(&i.to_be_bytes());
+        }
+
+        let path = Path::new("square.ply");
+        let a = read_ply(ascii.as_bytes(), path, Arc::clone(&mat)).unwrap();
+        let b = read_ply(&binary[..], path, Arc::clone(&mat)).unwrap();
+
+        assert_eq!(a.triangle_count(), 2);
+        assert_eq!(a.positions(), b.positions());
+        assert_eq!(a.colours().unwrap()[0].r(), 1.0);
+        assert_eq!(b.colours().unwrap()[3].g(), 0.0);
+
+        // cutting the binary file short is an error, not a panic.
+        assert!(read_ply(&binary[..binary.len() - 2], path, mat).is_err());
+    }
+}
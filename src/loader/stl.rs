@@ -0,0 +1,251 @@
+use crate::hit::mesh::*;
+use crate::material::*;
+use crate::vec3::*;
+
+use super::LoadError;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Loads an ASCII or binary STL file as a mesh. Corners in the same place are merged into one vertex,
+/// and the stored facet normals are ignored, as they are just the flat normals of the triangles.
+pub fn load_stl(path: &Path, material: Arc<dyn Material>) -> Result<Mesh, LoadError> {
+    let file = File::open(path).map_err(|e| LoadError::io(path, e))?;
+    let length = file.metadata().map_err(|e| LoadError::io(path, e))?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; 84];
+    let read = read_fully(&mut reader, &mut header).map_err(|e| LoadError::io(path, e))?;
+
+    // binary files can start with "solid" too, so only trust it if the size doesn't fit a binary file.
+    let count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as u64;
+    let is_binary = read == 84 && length == 84 + 50 * count;
+    if !is_binary && header.starts_with(b"solid") {
+        read_ascii_stl(header[..read].chain(reader), path, material)
+    } else {
+        read_binary_stl(header[..read].chain(reader), length, path, material)
+    }
+}
+
+// like read_exact, but returns how much was read if the file is shorter than buf.
+fn read_fully<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+// collects triangles, sharing vertices between the corners that are in the same place.
+struct Welder {
+    positions: Vec<Vec3>,
+    triangles: Vec<[u32; 3]>,
+    unique: HashMap<[u32; 3], u32>,
+}
+
+impl Welder {
+    fn new(triangles: usize) -> Welder {
+        Welder {
+            // closed meshes have about half as many vertices as triangles.
+            positions: Vec::with_capacity(triangles / 2),
+            triangles: Vec::with_capacity(triangles),
+            unique: HashMap::with_capacity(triangles / 2),
+        }
+    }
+
+    fn vertex(&mut self, p: Vec3) -> u32 {
+        // adding 0 turns -0 into 0, so both are welded together.
+        let key = [
+            (p.x() + 0.0).to_bits(),
+            (p.y() + 0.0).to_bits(),
+            (p.z() + 0.0).to_bits(),
+        ];
+        let positions = &mut self.positions;
+        *self.unique.entry(key).or_insert_with(|| {
+            positions.push(p);
+            positions.len() as u32 - 1
+        })
+    }
+
+    fn triangle(&mut self, corners: [Vec3; 3]) {
+        let a = self.vertex(corners[0]);
+        let b = self.vertex(corners[1]);
+        let c = self.vertex(corners[2]);
+        // triangles squashed to a line or a point can never be hit.
+        if a != b && b != c && a != c {
+            self.triangles.push([a, b, c]);
+        }
+    }
+
+    fn build(self, path: &Path, material: Arc<dyn Material>) -> Result<Mesh, LoadError> {
+        if self.triangles.is_empty() {
+            return Err(LoadError::parse(
+                path,
+                0,
+                "there are no triangles in the file",
+            ));
+        }
+        Ok(Mesh::new(self.positions, self.triangles, material))
+    }
+}
+
+// length is the size of the whole file in bytes, which the triangle count is checked against
+// before any space is set aside for the triangles.
+fn read_binary_stl<R: Read>(
+    mut reader: R,
+    length: u64,
+    path: &Path,
+    material: Arc<dyn Material>,
+) -> Result<Mesh, LoadError> {
+    let mut header = [0u8; 84];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| LoadError::parse(path, 0, "the file is too short to be an STL file"))?;
+    let count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as usize;
+    if 84 + 50 * count as u64 > length {
+        return Err(LoadError::parse(
+            path,
+            0,
+            format!(
+                "the header says there are {} triangles, but the file only has room for {}",
+                count,
+                (length - 84) / 50
+            ),
+        ));
+    }
+
+    let mut welder = Welder::new(count);
+    let mut facet = [0u8; 50];
+    for i in 0..count {
+        reader.read_exact(&mut facet).map_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                LoadError::parse(
+                    path,
+                    0,
+                    format!("the file ends at triangle {} of {}", i, count),
+                )
+            } else {
+                LoadError::io(path, e)
+            }
+        })?;
+
+        // the facet normal comes first, then the three corners, then two unused bytes.
+        let float = |offset: usize| {
+            f32::from_le_bytes([
+                facet[offset],
+                facet[offset + 1],
+                facet[offset + 2],
+                facet[offset + 3],
+            ])
+        };
+        let corner = |c: usize| {
+            let offset = 12 + 12 * c;
+            Vec3::new(float(offset), float(offset + 4), float(offset + 8))
+        };
+        welder.triangle([corner(0), corner(1), corner(2)]);
+    }
+    welder.build(path, material)
+}
+
+fn read_ascii_stl<R: BufRead>(
+    reader: R,
+    path: &Path,
+    material: Arc<dyn Material>,
+) -> Result<Mesh, LoadError> {
+    let mut welder = Welder::new(0);
+    let mut corners = Vec::with_capacity(3);
+    let mut in_loop = false;
+
+    for (number, line) in reader.lines().enumerate() {
+        let number = number + 1;
+        let line = line.map_err(|e| LoadError::io(path, e))?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            ["outer", "loop"] => {
+                corners.clear();
+                in_loop = true;
+            }
+            ["vertex", rest @ ..] => {
+                if !in_loop {
+                    return Err(LoadError::parse(path, number, "a vertex outside of a loop"));
+                }
+                let p = super::parse_floats(path, number, rest, 3, 3)?;
+                corners.push(Vec3::new(p[0], p[1], p[2]));
+            }
+            ["endloop"] => {
+                if corners.len() != 3 {
+                    return Err(LoadError::parse(
+                        path,
+                        number,
+                        format!("a facet has {} corners, not 3", corners.len()),
+                    ));
+                }
+                welder.triangle([corners[0], corners[1], corners[2]]);
+                in_loop = false;
+            }
+            ["solid", ..] | ["facet", ..] | ["endfacet"] | ["endsolid", ..] | [] => {}
+            _ => {
+                return Err(LoadError::parse(
+                    path,
+                    number,
+                    format!("unexpected '{}'", line.trim()),
+                ))
+            }
+        }
+    }
+    welder.build(path, material)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colour::*;
+    use crate::material::lambertian::*;
+
+    #[test]
+    fn stl_welds_shared_corners() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let text = "solid square
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid square
+";
+        let mesh = read_ascii_stl(text.as_bytes(), Path::new("square.stl"), mat).unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.positions().len(), 4);
+    }
+
+    #[test]
+    fn stl_rejects_count_longer_than_file() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.5, 0.5, 0.5)));
+        let mut bytes = [0u8; 84 + 50];
+        bytes[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        let length = bytes.len() as u64;
+        match read_binary_stl(&bytes[..], length, Path::new("bad.stl"), mat) {
+            Err(LoadError::Parse { message, .. }) => assert!(message.contains("room for 1")),
+            other => panic!(
+                "expected a parse error, got {:?}",
+                other.map(|m| m.triangle_count())
+            ),
+        }
+    }
+}
@@ -13,11 +13,14 @@ use rand::{Rng, SeedableRng};
 
 use ray_tracer::camera::*;
 use ray_tracer::colour::*;
+use ray_tracer::stereo::*;
+use ray_tracer::transform::*;
 use ray_tracer::vec3::*;
 
 use ray_tracer::hit::constant_medium::*;
 use ray_tracer::hit::hitable_list::*;
 use ray_tracer::hit::instancing::*;
+use ray_tracer::hit::quad::*;
 use ray_tracer::hit::rectangle::*;
 use ray_tracer::hit::sphere::*;
 use ray_tracer::hit::triangle::*;
@@ -31,13 +34,13 @@ use ray_tracer::material::metal::*;
 use std::time::Instant;
 
 fn main() {
-    let width = 500*4;
-    let height = 500*4;
+    let width = 500 * 4;
+    let height = 500 * 4;
 
     //let width = 1920;
     //let height = 1080;
 
-    let samples = 500 ;
+    let samples = 500;
     let _seed: u64 = 0;
 
     let lookfrom = Vec3::new(13.0, 2.0, 3.0);
@@ -70,17 +73,34 @@ fn main() {
     );
 
     let mut rng = SmallRng::seed_from_u64(_seed);
-    //let scene = random_scene(&mut rng);
-    //let scene = static_scene();
-    let scene = cornell_box();
-    //let scene = simple_light();
+    //let (scene, lights) = (random_scene(&mut rng), vec![]);
+    //let (scene, lights) = (static_scene(), vec![]);
+    let (scene, lights) = cornell_box();
+    //let (scene, lights) = (simple_light(), vec![]);
+
+    //--stereo renders both eyes side by side, and --stereo-separate renders a file per eye.
+    let args: Vec<String> = std::env::args().collect();
+    let stereo = args.iter().any(|a| a == "--stereo");
+    let separate = args.iter().any(|a| a == "--stereo-separate");
 
     let time_start = Instant::now();
 
-    let buf = ray_tracer::render(width, height, samples, cam, scene);
+    if stereo || separate {
+        render_stereo(width, height, samples, &cam, scene, &lights, !separate);
+    } else {
+        let buf = ray_tracer::render(width, height, samples, cam, scene, &lights);
+        write_png(Path::new("out.png"), width, height, &buf);
+    }
 
     let time_end = Instant::now();
 
+    println!(
+        "it took {} seconds to render this image",
+        time_end.duration_since(time_start).as_secs()
+    );
+}
+
+fn write_png(path: &Path, width: usize, height: usize, buf: &[[f32; 3]]) {
     let buf: Vec<[u8; 3]> = buf
         .iter()
         .map(|x| {
@@ -97,18 +117,37 @@ fn main() {
     for i in buf.iter() {
         buff.extend(i);
     }
-    let file = File::create(Path::new("out.png")).unwrap();
+    let file = File::create(path).unwrap();
     let mut encoder = Encoder::new(BufWriter::new(file), width as u32, height as u32);
     encoder.set_color(ColorType::RGB);
     encoder.set_depth(BitDepth::Eight);
     let mut writer = encoder.write_header().unwrap();
 
     writer.write_image_data(&buff).unwrap();
+}
 
-    println!(
-        "it took {} seconds to render this image",
-        time_end.duration_since(time_start).as_secs()
-    );
+//renders both eyes in one go, either as one side-by-side image or as a file per eye.
+fn render_stereo(
+    width: usize,
+    height: usize,
+    samples: usize,
+    cam: &Camera,
+    scene: HitableList,
+    lights: &[Box<dyn Sampleable>],
+    side_by_side: bool,
+) {
+    //eyes 6.5 units apart (the cornell box is about 555 across), converging on the back wall.
+    let rig = StereoRig::new(cam, 6.5, 800.0 + 555.0);
+
+    let views = ray_tracer::render_views(width, height, samples, &rig.views(), scene, lights);
+
+    if side_by_side {
+        let buf = tile_views(&views, width, height, 2);
+        write_png(Path::new("out_stereo.png"), width * 2, height, &buf);
+    } else {
+        write_png(Path::new("out_left.png"), width, height, &views[0]);
+        write_png(Path::new("out_right.png"), width, height, &views[1]);
+    }
 }
 
 #[allow(dead_code)]
@@ -231,13 +270,22 @@ fn static_scene() -> HitableList {
 }
 
 #[allow(dead_code)]
-fn cornell_box() -> HitableList {
+//returns the box along with its ceiling light, for the renderer to sample.
+fn cornell_box() -> (HitableList, Vec<Box<dyn Sampleable>>) {
     use ray_tracer::material::Material;
 
     let red: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.65, 0.05, 0.05)));
     let white: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.73, 0.73, 0.73)));
     let green: Arc<dyn Material> = Arc::new(Lambertian::new(Colour::new(0.12, 0.45, 0.15)));
     let light: Arc<dyn Material> = Arc::new(Emission::new(Colour::new(7.0, 7.0, 7.0)));
+    let ceiling_light = || {
+        Quad::new(
+            Vec3::new(113.0, 554.0, 127.0),
+            Vec3::new(330.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 305.0),
+            Arc::clone(&light),
+        )
+    };
 
     let world: Vec<Box<dyn Hitable>> = vec![
         Box::new(FlipFace::new(YzRectangle::new(
@@ -256,14 +304,7 @@ fn cornell_box() -> HitableList {
             0.0,
             Arc::clone(&red),
         )),
-        Box::new(XzRectangle::new(
-            113.0,
-            443.0,
-            127.0,
-            432.0,
-            554.0,
-            Arc::clone(&light),
-        )),
+        Box::new(ceiling_light()),
         Box::new(FlipFace::new(XzRectangle::new(
             0.0,
             555.0,
@@ -288,61 +329,57 @@ fn cornell_box() -> HitableList {
             555.0,
             Arc::clone(&white),
         ))),
-        /*Box::new(Translate::new(
-            RotateY::new(
-                RectangularBox::new(
-                    Vec3::new(0.0, 0.0, 0.0),
-                    Vec3::new(165.0, 330.0, 165.0),
-                    Arc::clone(&white),
-                ),
-                15.0,
+        /*Box::new(Transformed::new(
+            RectangularBox::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(165.0, 330.0, 165.0),
+                Arc::clone(&white),
+            ),
+            Transform::new(
+                Matrix4::translation(Vec3::new(265.0, 0.0, 295.0)) * Matrix4::rotation_y(15.0),
             ),
-            Vec3::new(265.0, 0.0, 295.0),
         )),
-        Box::new(Translate::new(
-            RotateY::new(
+        Box::new(Transformed::new(
+            RectangularBox::new(
+                Vec3::new(0.0, 0.0, 0.0),
+                Vec3::new(165.0, 165.0, 165.0),
+                Arc::clone(&white),
+            ),
+            Transform::new(
+                Matrix4::translation(Vec3::new(130.0, 0.0, 65.0)) * Matrix4::rotation_y(-18.0),
+            ),
+        )),*/
+        Box::new(ConstantMedium::new(
+            Box::new(Transformed::new(
                 RectangularBox::new(
                     Vec3::new(0.0, 0.0, 0.0),
-                    Vec3::new(165.0, 165.0, 165.0),
+                    Vec3::new(165.0, 330.0, 165.0),
                     Arc::clone(&white),
                 ),
-                -18.0,
-            ),
-            Vec3::new(130.0, 0.0, 65.0),
-        )),*/
-        Box::new(ConstantMedium::new(
-            Box::new(Translate::new(
-                RotateY::new(
-                    RectangularBox::new(
-                        Vec3::new(0.0, 0.0, 0.0),
-                        Vec3::new(165.0, 330.0, 165.0),
-                        Arc::clone(&white),
-                    ),
-                    15.0,
+                Transform::new(
+                    Matrix4::translation(Vec3::new(265.0, 0.0, 295.0)) * Matrix4::rotation_y(15.0),
                 ),
-                Vec3::new(265.0, 0.0, 295.0),
             )),
             0.01,
             Colour::new(0.0, 0.0, 0.0),
         )),
         Box::new(ConstantMedium::new(
-            Box::new(Translate::new(
-                RotateY::new(
-                    RectangularBox::new(
-                        Vec3::new(0.0, 0.0, 0.0),
-                        Vec3::new(165.0, 165.0, 165.0),
-                        Arc::clone(&white),
-                    ),
-                    -18.0,
+            Box::new(Transformed::new(
+                RectangularBox::new(
+                    Vec3::new(0.0, 0.0, 0.0),
+                    Vec3::new(165.0, 165.0, 165.0),
+                    Arc::clone(&white),
+                ),
+                Transform::new(
+                    Matrix4::translation(Vec3::new(130.0, 0.0, 65.0)) * Matrix4::rotation_y(-18.0),
                 ),
-                Vec3::new(130.0, 0.0, 65.0),
             )),
             0.01,
             Colour::new(1.0, 1.0, 1.0),
         )),
     ];
 
-    HitableList::new(world)
+    (HitableList::new(world), vec![Box::new(ceiling_light())])
 }
 
 #[allow(dead_code)]
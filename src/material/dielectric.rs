@@ -54,9 +54,9 @@ impl Material for Dielectric {
         }
 
         if rand::random::<f32>() < reflect_prob {
-            scattered = Ray::new(record.position, reflected);
+            scattered = Ray::new_at_time(record.position, reflected, r_in.time());
         } else {
-            scattered = Ray::new(record.position, refracted);
+            scattered = Ray::new_at_time(record.position, refracted, r_in.time());
         }
         Some(MaterialReturn::new(attenuation, scattered))
     }
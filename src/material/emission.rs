@@ -17,7 +17,7 @@ impl Material for Emission {
         None
     }
 
-    fn emitted(&self) -> Colour {
+    fn emitted(&self, _record: &HitRecord) -> Colour {
         self.emit
     }
 }
@@ -0,0 +1,60 @@
+use crate::colour::*;
+use crate::hit::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::util::*;
+
+/// A diffuse surface with a glossy coat, like plastic or varnished wood.
+/// Each ray either reflects off the coat or scatters diffusely, picked in proportion to how bright
+/// the specular and diffuse colours are.
+#[derive(Debug, Clone, Copy)]
+pub struct Glossy {
+    diffuse: Colour,
+    specular: Colour,
+    roughness: f32,
+}
+
+impl Glossy {
+    pub fn new(diffuse: Colour, specular: Colour, roughness: f32) -> Glossy {
+        Glossy {
+            diffuse,
+            specular,
+            roughness: roughness.clamp(0.0, 1.0),
+        }
+    }
+
+    fn specular_chance(&self) -> f32 {
+        let brightness = |c: Colour| c.r() + c.g() + c.b();
+        let total = brightness(self.diffuse) + brightness(self.specular);
+        if total > 0.0 {
+            brightness(self.specular) / total
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Material for Glossy {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
+        if rand::random::<f32>() < self.specular_chance() {
+            let reflected = r_in.direction().unit_vector().reflect(record.normal);
+            let scattered = Ray::new_at_time(
+                record.position,
+                reflected + self.roughness * random_in_unit_sphere(),
+                r_in.time(),
+            );
+            if scattered.direction().dot(record.geometric_normal) > 0.0 {
+                Some(MaterialReturn::new(self.specular, scattered))
+            } else {
+                None
+            }
+        } else {
+            let scatter_direction = keep_above_surface(
+                record.normal + random_in_unit_sphere().unit_vector(),
+                record,
+            );
+            let scattered = Ray::new_at_time(record.position, scatter_direction, r_in.time());
+            Some(MaterialReturn::new(self.diffuse, scattered))
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use crate::colour::*;
+use crate::hit::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+use std::f32::consts::PI;
+
+// how many bounces inside the hair are followed separately. Any after that are lumped together.
+const P_MAX: usize = 3;
+
+/// Hair and fur, following the model of d'Eon et al. and Chiang et al. as set out in pbrt.
+/// Light either reflects off the cuticle, passes through the hair, or bounces around inside
+/// it before leaving, and each of these takes on a different amount of the hair's colour.
+///
+/// This needs to be put on curves, which give the tangent along the hair and v across it.
+/// Ribbon curves work best, as their normals face the ray like the model assumes.
+#[derive(Debug, Clone, Copy)]
+pub struct Hair {
+    sigma_a: Colour,
+    eta: f32,
+    // the longitudinal variance for each bounce, and the azimuthal logistic scale.
+    v: [f32; P_MAX + 1],
+    s: f32,
+    // the sines and cosines of alpha, 2 alpha and 4 alpha, for tilting the scales.
+    sin_2k_alpha: [f32; 3],
+    cos_2k_alpha: [f32; 3],
+}
+
+impl Hair {
+    /// sigma_a is how much light is absorbed inside the hair, beta_m and beta_n are the
+    /// longitudinal and azimuthal roughnesses between 0 and 1, and alpha is the angle of the
+    /// scales on the hair in degrees, which is usually about 2.
+    pub fn new(sigma_a: Colour, beta_m: f32, beta_n: f32, alpha: f32) -> Hair {
+        // perfectly smooth hair would divide by zero below.
+        let beta_m = beta_m.clamp(0.01, 1.0);
+        let beta_n = beta_n.clamp(0.01, 1.0);
+
+        let v0 = (0.726 * beta_m + 0.812 * beta_m * beta_m + 3.7 * beta_m.powi(20)).powi(2);
+        let s = (PI / 8.0).sqrt()
+            * (0.265 * beta_n + 1.194 * beta_n * beta_n + 5.372 * beta_n.powi(22));
+
+        let mut sin_2k_alpha = [alpha.to_radians().sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0] * sin_2k_alpha[0]), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        Hair {
+            sigma_a,
+            eta: 1.55,
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// Hair coloured by its pigments, where an eumelanin concentration of about 0.3 gives blonde
+    /// hair, 1.3 brown and 8 black. Pheomelanin makes it redder.
+    pub fn from_melanin(
+        eumelanin: f32,
+        pheomelanin: f32,
+        beta_m: f32,
+        beta_n: f32,
+        alpha: f32,
+    ) -> Hair {
+        let sigma_a = eumelanin * Colour::new(0.419, 0.697, 1.37)
+            + pheomelanin * Colour::new(0.187, 0.4, 1.05);
+        Hair::new(sigma_a, beta_m, beta_n, alpha)
+    }
+
+    /// Hair that comes out roughly the given colour, after all its bounces.
+    pub fn from_colour(colour: Colour, beta_m: f32, beta_n: f32, alpha: f32) -> Hair {
+        let b = beta_n;
+        let scale = 5.969 - 0.215 * b + 2.532 * b.powi(2) - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5);
+        let sigma = |c: f32| (c.max(1e-4).ln() / scale).powi(2);
+        Hair::new(
+            Colour::new(sigma(colour.r()), sigma(colour.g()), sigma(colour.b())),
+            beta_m,
+            beta_n,
+            alpha,
+        )
+    }
+
+    /// The index of refraction of the hair, which is 1.55 by default.
+    pub fn with_eta(mut self, eta: f32) -> Hair {
+        self.eta = eta;
+        self
+    }
+
+    // sin and cos of theta_o, tilted by the scales for bounce p.
+    fn tilt(&self, p: usize, sin_theta_o: f32, cos_theta_o: f32) -> (f32, f32) {
+        let (sin_a, cos_a, sign) = match p {
+            0 => (self.sin_2k_alpha[1], self.cos_2k_alpha[1], -1.0),
+            1 => (self.sin_2k_alpha[0], self.cos_2k_alpha[0], 1.0),
+            2 => (self.sin_2k_alpha[2], self.cos_2k_alpha[2], 1.0),
+            _ => return (sin_theta_o, cos_theta_o),
+        };
+        (
+            sin_theta_o * cos_a + sign * cos_theta_o * sin_a,
+            (cos_theta_o * cos_a - sign * sin_theta_o * sin_a).abs(),
+        )
+    }
+
+    // how much light leaves after each bounce, for a ray at offset h across the hair.
+    fn attenuation(&self, sin_theta_o: f32, cos_theta_o: f32, h: f32) -> [Colour; P_MAX + 1] {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = h / etap;
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let path = 2.0 * cos_gamma_t / cos_theta_t;
+        let transmittance = Colour::new(
+            (-self.sigma_a.r() * path).exp(),
+            (-self.sigma_a.g() * path).exp(),
+            (-self.sigma_a.b() * path).exp(),
+        );
+
+        let f = fresnel_dielectric(cos_theta_o * safe_sqrt(1.0 - h * h), self.eta);
+        let mut ap = [Colour::new(0.0, 0.0, 0.0); P_MAX + 1];
+        ap[0] = Colour::new(f, f, f);
+        ap[1] = (1.0 - f) * (1.0 - f) * transmittance;
+        for p in 2..P_MAX {
+            ap[p] = ap[p - 1] * transmittance * f;
+        }
+        // the rest of the bounces form a geometric series.
+        let rest = |a: f32, t: f32| a * f * t / (1.0 - t * f);
+        ap[P_MAX] = Colour::new(
+            rest(ap[P_MAX - 1].r(), transmittance.r()),
+            rest(ap[P_MAX - 1].g(), transmittance.g()),
+            rest(ap[P_MAX - 1].b(), transmittance.b()),
+        );
+        ap
+    }
+
+    // the chance of sampling each bounce, in proportion to how bright it is.
+    fn bounce_pdf(&self, sin_theta_o: f32, cos_theta_o: f32, h: f32) -> [f32; P_MAX + 1] {
+        let ap = self.attenuation(sin_theta_o, cos_theta_o, h);
+        let mut pdf = [0.0; P_MAX + 1];
+        let brightness = |c: Colour| 0.2126 * c.r() + 0.7152 * c.g() + 0.0722 * c.b();
+        let total: f32 = ap.iter().map(|&c| brightness(c)).sum();
+        for p in 0..=P_MAX {
+            pdf[p] = if total > 0.0 {
+                brightness(ap[p]) / total
+            } else {
+                1.0 / (P_MAX + 1) as f32
+            };
+        }
+        pdf
+    }
+
+    // sums each bounce's lobe for light going from wi to wo, both in the hair's frame, weighted by
+    // either the attenuation or the chance of picking the bounce.
+    fn lobes<W: Fn(usize) -> Colour>(&self, wo: Vec3, wi: Vec3, h: f32, weight: W) -> Colour {
+        let sin_theta_o = wo.x();
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let phi_o = wo.z().atan2(wo.y());
+        let sin_theta_i = wi.x();
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let phi_i = wi.z().atan2(wi.y());
+
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let gamma_o = safe_asin(h);
+        let gamma_t = safe_asin(h / etap);
+        let phi = phi_i - phi_o;
+
+        let mut sum = Colour::new(0.0, 0.0, 0.0);
+        for p in 0..P_MAX {
+            let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            let mp = longitudinal(
+                cos_theta_i,
+                cos_theta_op,
+                sin_theta_i,
+                sin_theta_op,
+                self.v[p],
+            );
+            let np = azimuthal(phi, p, self.s, gamma_o, gamma_t);
+            sum = sum + mp * np * weight(p);
+        }
+        let mp = longitudinal(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        );
+        sum + mp / (2.0 * PI) * weight(P_MAX)
+    }
+
+    // samples a direction for light coming in, given the outgoing direction in the hair's frame,
+    // returning it along with f * |cos| / pdf.
+    fn sample(&self, wo: Vec3, h: f32) -> (Vec3, Colour) {
+        let sin_theta_o = wo.x();
+        let cos_theta_o = safe_sqrt(1.0 - sin_theta_o * sin_theta_o);
+        let phi_o = wo.z().atan2(wo.y());
+
+        // pick which bounce to follow.
+        let bounce_pdf = self.bounce_pdf(sin_theta_o, cos_theta_o, h);
+        let mut pick = rand::random::<f32>();
+        let mut p = 0;
+        while p < P_MAX && pick >= bounce_pdf[p] {
+            pick -= bounce_pdf[p];
+            p += 1;
+        }
+
+        // sample the longitudinal angle around the tilted reflection.
+        let (sin_theta_op, cos_theta_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let u = rand::random::<f32>().max(1e-5);
+        let v = self.v[p];
+        let cos_theta = 1.0 + v * (u + (1.0 - u) * (-2.0 / v).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * rand::random::<f32>()).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = safe_sqrt(1.0 - sin_theta_i * sin_theta_i);
+
+        // then the azimuthal angle, around where the bounce leaves the hair.
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let gamma_o = safe_asin(h);
+        let gamma_t = safe_asin(h / etap);
+        let dphi = if p < P_MAX {
+            exit_angle(p, gamma_o, gamma_t)
+                + sample_trimmed_logistic(rand::random::<f32>(), self.s, -PI, PI)
+        } else {
+            2.0 * PI * rand::random::<f32>()
+        };
+        let phi_i = phi_o + dphi;
+        let wi = Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+
+        let ap = self.attenuation(sin_theta_o, cos_theta_o, h);
+        let f = self.lobes(wo, wi, h, |p| ap[p]);
+        let pdf = self.lobes(wo, wi, h, |p| {
+            Colour::new(bounce_pdf[p], bounce_pdf[p], bounce_pdf[p])
+        });
+        // the pdf is the same in every channel.
+        if pdf.r() > 0.0 {
+            (wi, f / pdf.r())
+        } else {
+            (wi, Colour::new(0.0, 0.0, 0.0))
+        }
+    }
+}
+
+impl Material for Hair {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
+        // x runs along the hair, and z faces back along the ray.
+        let x = record.tangent();
+        let y = record.bitangent();
+        let z = record.normal;
+        let wo = -r_in.direction().unit_vector();
+        let wo = Vec3::new(wo.dot(x), wo.dot(y), wo.dot(z));
+        let h = (2.0 * record.v - 1.0).clamp(-1.0, 1.0);
+
+        let (wi, weight) = self.sample(wo, h);
+        let direction = wi.x() * x + wi.y() * y + wi.z() * z;
+        Some(MaterialReturn::new(
+            weight,
+            Ray::new_at_time(record.position, direction, r_in.time()),
+        ))
+    }
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.0).sqrt()
+}
+
+fn safe_asin(x: f32) -> f32 {
+    x.clamp(-1.0, 1.0).asin()
+}
+
+// the fraction of light reflected by a dielectric, for light arriving from outside it.
+fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let sin_theta_t = safe_sqrt(1.0 - cos_theta_i * cos_theta_i) / eta;
+    if sin_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = safe_sqrt(1.0 - sin_theta_t * sin_theta_t);
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+// the modified Bessel function of the first kind, of order 0.
+fn bessel_i0(x: f32) -> f32 {
+    let mut value = 0.0;
+    let mut x2i = 1.0;
+    let mut factorial = 1.0f32;
+    let mut four_i = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            factorial *= i as f32;
+        }
+        value += x2i / (four_i * factorial * factorial);
+        x2i *= x * x;
+        four_i *= 4.0;
+    }
+    value
+}
+
+fn log_bessel_i0(x: f32) -> f32 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+// the longitudinal scattering function, which spreads light around the cone of reflection.
+fn longitudinal(
+    cos_theta_i: f32,
+    cos_theta_o: f32,
+    sin_theta_i: f32,
+    sin_theta_o: f32,
+    v: f32,
+) -> f32 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        // the direct form overflows for small variances.
+        (log_bessel_i0(a) - b - 1.0 / v + std::f32::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        ((-b).exp() * bessel_i0(a)) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+// the angle around the hair that light leaves at after bounce p.
+fn exit_angle(p: usize, gamma_o: f32, gamma_t: f32) -> f32 {
+    2.0 * p as f32 * gamma_t - 2.0 * gamma_o + p as f32 * PI
+}
+
+// the azimuthal scattering function for bounce p.
+fn azimuthal(phi: f32, p: usize, s: f32, gamma_o: f32, gamma_t: f32) -> f32 {
+    let mut dphi = phi - exit_angle(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+fn logistic(x: f32, s: f32) -> f32 {
+    let e = (-x.abs() / s).exp();
+    e / (s * (1.0 + e) * (1.0 + e))
+}
+
+fn logistic_cdf(x: f32, s: f32) -> f32 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f32, s: f32, a: f32, b: f32) -> f32 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f32, s: f32, a: f32, b: f32) -> f32 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hair_sampling_weights_stay_bounded() {
+        // with no absorption, hair loses no light, so the average weight should be close to 1.
+        let hair = Hair::new(Colour::new(0.0, 0.0, 0.0), 0.3, 0.3, 2.0);
+        let wo = Vec3::new(0.3, 0.5, 0.8).unit_vector();
+        let n = 20000;
+        let mut total = 0.0;
+        for i in 0..n {
+            let h = -1.0 + 2.0 * (i as f32 + 0.5) / n as f32;
+            let (wi, weight) = hair.sample(wo, h);
+            assert!((wi.length() - 1.0).abs() < 1e-3);
+            total += weight.g();
+        }
+        let average = total / n as f32;
+        assert!(
+            (average - 1.0).abs() < 0.05,
+            "average weight is {}",
+            average
+        );
+    }
+}
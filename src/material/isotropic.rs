@@ -14,9 +14,9 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, _r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
         Some(MaterialReturn {
-            scattered: Ray::new(record.position, random_in_unit_sphere()),
+            scattered: Ray::new_at_time(record.position, random_in_unit_sphere(), r_in.time()),
             attenuation: self.albedo,
         })
     }
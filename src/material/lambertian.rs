@@ -4,6 +4,7 @@ use crate::material::*;
 use crate::ray::*;
 use crate::util::*;
 
+//vertex colours, where the surface has them, tint the albedo.
 #[derive(Debug, Clone, Copy)]
 pub struct Lambertian {
     albedo: Colour,
@@ -13,13 +14,26 @@ impl Lambertian {
     pub fn new(albedo: Colour) -> Lambertian {
         Lambertian { albedo }
     }
+
+    fn albedo(&self, record: &HitRecord) -> Colour {
+        match record.colour {
+            Some(colour) => self.albedo * colour,
+            None => self.albedo,
+        }
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
-        let scatter_direction = record.normal + random_in_unit_sphere().unit_vector();
-        let scattered = Ray::new(record.position, scatter_direction);
-        let attenuation = self.albedo;
-        Some(MaterialReturn::new(attenuation, scattered))
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
+        let scatter_direction = keep_above_surface(
+            record.normal + random_in_unit_sphere().unit_vector(),
+            record,
+        );
+        let scattered = Ray::new_at_time(record.position, scatter_direction, r_in.time());
+        Some(MaterialReturn::new(self.albedo(record), scattered))
+    }
+
+    fn diffuse_albedo(&self, record: &HitRecord) -> Option<Colour> {
+        Some(self.albedo(record))
     }
 }
@@ -26,12 +26,15 @@ impl Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
         let reflected = r_in.direction().unit_vector().reflect(record.normal);
-        let scattered = Ray::new(
+        let scattered = Ray::new_at_time(
             record.position,
             reflected + self.fuzz * random_in_unit_sphere(),
+            r_in.time(),
         );
         let attenuation = self.albedo;
-        if scattered.direction().dot(record.normal) > 0.0 {
+        if scattered.direction().dot(record.normal) > 0.0
+            && scattered.direction().dot(record.geometric_normal) > 0.0
+        {
             Some(MaterialReturn::new(attenuation, scattered))
         } else {
             None
@@ -0,0 +1,122 @@
+use crate::colour::*;
+use crate::hit::*;
+use crate::material::dielectric::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::texture::*;
+use crate::util::*;
+
+use std::sync::Arc;
+
+/// The metallic-roughness material from glTF. Metals reflect tinted by the base colour, and everything
+/// else is diffuse under a clear coat that reflects about 4% of light head on, more at grazing angles.
+/// Textures are looked up with the hit's u and v, and multiply the matching factors.
+#[derive(Debug, Clone)]
+pub struct MetallicRoughness {
+    base_colour: Colour,
+    metallic: f32,
+    roughness: f32,
+    emissive: Colour,
+    base_colour_texture: Option<Arc<ImageTexture>>,
+    metallic_roughness_texture: Option<Arc<ImageTexture>>, // roughness in green, metallic in blue.
+    emissive_texture: Option<Arc<ImageTexture>>,
+}
+
+impl MetallicRoughness {
+    pub fn new(base_colour: Colour, metallic: f32, roughness: f32) -> MetallicRoughness {
+        MetallicRoughness {
+            base_colour,
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            emissive: Colour::new(0.0, 0.0, 0.0),
+            base_colour_texture: None,
+            metallic_roughness_texture: None,
+            emissive_texture: None,
+        }
+    }
+
+    pub fn with_base_colour_texture(mut self, texture: Arc<ImageTexture>) -> MetallicRoughness {
+        self.base_colour_texture = Some(texture);
+        self
+    }
+
+    pub fn with_metallic_roughness_texture(
+        mut self,
+        texture: Arc<ImageTexture>,
+    ) -> MetallicRoughness {
+        self.metallic_roughness_texture = Some(texture);
+        self
+    }
+
+    pub fn with_emissive(
+        mut self,
+        emissive: Colour,
+        texture: Option<Arc<ImageTexture>>,
+    ) -> MetallicRoughness {
+        self.emissive = emissive;
+        self.emissive_texture = texture;
+        self
+    }
+
+    // the base colour, metallic and roughness where the ray hit.
+    fn surface(&self, record: &HitRecord) -> (Colour, f32, f32) {
+        let base_colour = match &self.base_colour_texture {
+            Some(texture) => self.base_colour * texture.colour(record.u, record.v),
+            None => self.base_colour,
+        };
+        match &self.metallic_roughness_texture {
+            Some(texture) => {
+                let [_, roughness, metallic, _] = texture.sample(record.u, record.v);
+                (
+                    base_colour,
+                    self.metallic * metallic,
+                    self.roughness * roughness,
+                )
+            }
+            None => (base_colour, self.metallic, self.roughness),
+        }
+    }
+}
+
+impl Material for MetallicRoughness {
+    fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn> {
+        let (base_colour, metallic, roughness) = self.surface(record);
+
+        let unit_direction = r_in.direction().unit_vector();
+        let cosine = (-unit_direction.dot(record.normal)).max(0.0);
+        // an index of refraction of 1.5 gives the 4% reflectance glTF uses for non-metals.
+        let fresnel = Dielectric::schlick(cosine, 1.5);
+        let specular_chance = metallic + (1.0 - metallic) * fresnel;
+
+        if rand::random::<f32>() < specular_chance {
+            let reflected = unit_direction.reflect(record.normal);
+            let scattered = Ray::new_at_time(
+                record.position,
+                reflected + roughness * random_in_unit_sphere(),
+                r_in.time(),
+            );
+            if scattered.direction().dot(record.geometric_normal) <= 0.0 {
+                return None;
+            }
+            // metals tint the reflection, the coat on everything else doesn't.
+            let attenuation = (metallic * base_colour
+                + (1.0 - metallic) * fresnel * Colour::new(1.0, 1.0, 1.0))
+                / specular_chance;
+            Some(MaterialReturn::new(attenuation, scattered))
+        } else {
+            let scatter_direction = keep_above_surface(
+                record.normal + random_in_unit_sphere().unit_vector(),
+                record,
+            );
+            let scattered = Ray::new_at_time(record.position, scatter_direction, r_in.time());
+            Some(MaterialReturn::new(base_colour, scattered))
+        }
+    }
+
+    fn emitted(&self, record: &HitRecord) -> Colour {
+        match &self.emissive_texture {
+            Some(texture) => self.emissive * texture.colour(record.u, record.v),
+            None => self.emissive,
+        }
+    }
+}
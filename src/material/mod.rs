@@ -1,20 +1,30 @@
 pub mod dielectric;
 pub mod emission;
+pub mod glossy;
+pub mod hair;
 pub mod isotropic;
 pub mod lambertian;
 pub mod metal;
+pub mod metallic_roughness;
 
 use super::colour::*;
 use super::hit::*;
 use super::ray::*;
+use super::vec3::*;
 
 pub trait Material: Sync + Send + std::fmt::Debug {
     fn scatter(&self, r_in: &Ray, record: &HitRecord) -> Option<MaterialReturn>;
 
     //by default, emit no light.
-    fn emitted(&self) -> Colour {
+    fn emitted(&self, _record: &HitRecord) -> Colour {
         Colour::new(0.0, 0.0, 0.0)
     }
+
+    //materials that scatter like lambertian return their albedo, so the renderer can sample the
+    //lights directly from them rather than waiting for a scattered ray to find one.
+    fn diffuse_albedo(&self, _record: &HitRecord) -> Option<Colour> {
+        None
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -38,3 +48,14 @@ impl MaterialReturn {
         self.scattered
     }
 }
+
+/// Mirrors a scattered direction back out through the surface if it points into it, which a
+/// direction picked around a smoothed normal can.
+pub fn keep_above_surface(direction: Vec3, record: &HitRecord) -> Vec3 {
+    let below = direction.dot(record.geometric_normal);
+    if below < 0.0 {
+        direction - 2.0 * below * record.geometric_normal
+    } else {
+        direction
+    }
+}
@@ -6,10 +6,15 @@ pub struct Ray {
     direction: Vec3,
     inv_direction: Vec3, // stores the inverse of the direction, for speed.
     dir_sign: [bool; 3], //stores the sign of the direction, for speed.
+    time: f32,           //when the ray was sent out, used for motion blur.
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+        Ray::new_at_time(origin, direction, 0.0)
+    }
+
+    pub fn new_at_time(origin: Vec3, direction: Vec3, time: f32) -> Ray {
         let inv_direction = Vec3::new(
             1.0 / direction.x(),
             1.0 / direction.y(),
@@ -25,6 +30,7 @@ impl Ray {
             direction,
             inv_direction,
             dir_sign,
+            time,
         }
     }
 
@@ -44,6 +50,10 @@ impl Ray {
         self.dir_sign
     }
 
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
         self.origin + self.direction * t
     }
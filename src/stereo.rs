@@ -0,0 +1,284 @@
+use super::camera::*;
+use super::ray::*;
+use super::vec3::*;
+
+/// A pair of cameras for the left and right eyes, for rendering with render_views.
+///
+/// The eyes look straight ahead, parallel to each other, and the image is shifted across each sensor
+/// so that the views line up at the convergence distance. This keeps vertical parallax out of the
+/// corners, which turning the cameras in towards each other would add.
+#[derive(Debug, Clone)]
+pub struct StereoRig {
+    left: Camera,
+    right: Camera,
+}
+
+impl StereoRig {
+    /// center is the camera halfway between the eyes. interaxial is the distance between the eyes,
+    /// and objects at convergence_dist appear at the depth of the screen.
+    pub fn new(center: &Camera, interaxial: f32, convergence_dist: f32) -> StereoRig {
+        let half = interaxial / 2.0;
+        let shift = half / center.frame_width_at(convergence_dist);
+
+        StereoRig {
+            left: center.with_offset(-half, 0.0).with_lens_shift(shift, 0.0),
+            right: center.with_offset(half, 0.0).with_lens_shift(-shift, 0.0),
+        }
+    }
+
+    pub fn left(&self) -> &Camera {
+        &self.left
+    }
+
+    pub fn right(&self) -> &Camera {
+        &self.right
+    }
+
+    /// The left and right cameras, in that order.
+    pub fn views(&self) -> [&dyn CameraModel; 2] {
+        [&self.left, &self.right]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// One eye of an omni-directional stereo panorama, in the equirectangular projection.
+///
+/// Each column of the image is seen from a different point on a circle, with the eyes at either end of
+/// a diameter, so the stereo effect holds in whichever direction the panorama is viewed.
+#[derive(Debug, Clone)]
+pub struct OdsCamera {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    eye: Eye,
+    radius: f32,
+    time0: f32, // the shutter is open from time0 to time1.
+    time1: f32,
+}
+
+impl OdsCamera {
+    /// lookat sets the direction in the middle of the panorama, and ipd is the distance between the eyes.
+    pub fn new(lookfrom: Vec3, lookat: Vec3, vup: Vec3, ipd: f32, eye: Eye) -> OdsCamera {
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(w).unit_vector();
+        let v = w.cross(u);
+
+        OdsCamera {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            eye,
+            radius: ipd / 2.0,
+            time0: 0.0,
+            time1: 0.0,
+        }
+    }
+
+    /// Returns a copy of this camera which sends out rays at random times between open and close,
+    /// blurring anything that moves in that time.
+    pub fn with_shutter(&self, open: f32, close: f32) -> OdsCamera {
+        OdsCamera {
+            time0: open,
+            time1: close,
+            ..self.clone()
+        }
+    }
+}
+
+impl CameraModel for OdsCamera {
+    fn generate_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let theta = (x - 0.5) * 2.0 * std::f32::consts::PI;
+        let phi = (y - 0.5) * std::f32::consts::PI;
+
+        let direction = theta.sin() * phi.cos() * self.u + phi.sin() * self.v
+            - theta.cos() * phi.cos() * self.w;
+
+        // the eye sits on the circle, at right angles to the horizontal direction it's looking.
+        let side = theta.cos() * self.u + theta.sin() * self.w;
+        let offset = match self.eye {
+            Eye::Left => -self.radius * side,
+            Eye::Right => self.radius * side,
+        };
+
+        let time = self.time0 + rand::random::<f32>() * (self.time1 - self.time0);
+        Some(Ray::new_at_time(self.origin + offset, direction, time))
+    }
+}
+
+/// A grid of parallel cameras, for capturing light fields. The grid is centered on the given camera,
+/// with spacing between neighbours, and is returned row by row starting from the top left.
+pub fn camera_array(center: &Camera, columns: usize, rows: usize, spacing: f32) -> Vec<Camera> {
+    let mut cams = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            let right = (column as f32 - (columns - 1) as f32 / 2.0) * spacing;
+            let up = ((rows - 1) as f32 / 2.0 - row as f32) * spacing;
+            cams.push(center.with_offset(right, up));
+        }
+    }
+    cams
+}
+
+/// Lays out images of the same size in a grid, left to right and then top to bottom.
+/// Two views with two columns gives a side-by-side stereo image. columns must be above 0.
+pub fn tile_views(
+    views: &[Vec<[f32; 3]>],
+    width: usize,
+    height: usize,
+    columns: usize,
+) -> Vec<[f32; 3]> {
+    assert!(columns > 0, "Can't tile views into 0 columns!");
+    let rows = views.len().div_ceil(columns);
+    let mut out = vec![[0.0; 3]; width * columns * height * rows];
+
+    for (i, view) in views.iter().enumerate() {
+        let (tile_x, tile_y) = (i % columns, i / columns);
+        for y in 0..height {
+            let start = (tile_y * height + y) * width * columns + tile_x * width;
+            out[start..start + width].copy_from_slice(&view[y * width..(y + 1) * width]);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pinhole(lookfrom: Vec3) -> Camera {
+        Camera::new(
+            lookfrom,
+            lookfrom + Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.5,
+            0.0,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn stereo_eyes_converge_on_the_screen() {
+        let rig = StereoRig::new(&pinhole(Vec3::new(1.0, 2.0, 3.0)), 0.1, 5.0);
+
+        for (cam, x) in [(rig.left(), 0.95), (rig.right(), 1.05)].iter() {
+            let r = cam.get_ray(0.5, 0.5).unwrap();
+            assert!(
+                (r.origin() - Vec3::new(*x, 2.0, 3.0)).length() < 1e-5,
+                "eye at {:?}",
+                r.origin()
+            );
+
+            // both eyes look through the middle of the frame at the same point, 5 in front.
+            let converged = r.point_at_parameter(-5.0 / r.direction().z());
+            assert!(
+                (converged - Vec3::new(1.0, 2.0, -2.0)).length() < 1e-4,
+                "converged at {:?}",
+                converged
+            );
+
+            // and the corners of the frame line up there too, so there is no vertical parallax.
+            let r = cam.get_ray(0.0, 1.0).unwrap();
+            let corner = r.point_at_parameter(-5.0 / r.direction().z());
+            let center = pinhole(Vec3::new(1.0, 2.0, 3.0)).get_ray(0.0, 1.0).unwrap();
+            let expected = center.point_at_parameter(-5.0 / center.direction().z());
+            assert!(
+                (corner - expected).length() < 1e-4,
+                "corner at {:?}, not {:?}",
+                corner,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn ods_eyes_sit_on_the_circle() {
+        let lookfrom = Vec3::new(1.0, 2.0, 3.0);
+        let ods = |eye| {
+            OdsCamera::new(
+                lookfrom,
+                Vec3::new(1.0, 2.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                0.064,
+                eye,
+            )
+            .with_shutter(0.25, 0.5)
+        };
+        let (left, right) = (ods(Eye::Left), ods(Eye::Right));
+
+        // looking straight ahead, the left eye is to the left.
+        let r = left.generate_ray(0.5, 0.5).unwrap();
+        assert!(
+            (r.origin() - Vec3::new(0.968, 2.0, 3.0)).length() < 1e-5,
+            "eye at {:?}",
+            r.origin()
+        );
+
+        for i in 0..50 {
+            let (x, y) = (i as f32 / 49.0, (i as f32 * 0.37).fract());
+            let l = left.generate_ray(x, y).unwrap();
+            let r = right.generate_ray(x, y).unwrap();
+            assert!((l.direction() - r.direction()).length() < 1e-5);
+
+            // each eye is half the ipd from the center, at right angles to the way it looks.
+            let offset = l.origin() - lookfrom;
+            assert!((offset.length() - 0.032).abs() < 1e-5);
+            assert!((r.origin() - lookfrom + offset).length() < 1e-5);
+            assert!(offset.dot(l.direction()).abs() < 1e-5);
+
+            assert!(
+                l.time() >= 0.25 && l.time() <= 0.5,
+                "ray at time {}",
+                l.time()
+            );
+        }
+    }
+
+    #[test]
+    fn camera_arrays_are_centered_grids() {
+        let center = pinhole(Vec3::new(1.0, 2.0, 3.0));
+        let cams = camera_array(&center, 3, 2, 0.5);
+        assert_eq!(cams.len(), 6);
+
+        let expected = center.get_ray(0.3, 0.6).unwrap();
+        for (i, cam) in cams.iter().enumerate() {
+            let (column, row) = ((i % 3) as f32, (i / 3) as f32);
+            let r = cam.get_ray(0.3, 0.6).unwrap();
+            let offset = Vec3::new(0.5 * (column - 1.0), 0.5 * (0.5 - row), 0.0);
+            assert!(
+                (r.origin() - expected.origin() - offset).length() < 1e-5,
+                "camera {} at {:?}",
+                i,
+                r.origin()
+            );
+            assert!((r.direction() - expected.direction()).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn views_tile_left_to_right_then_down() {
+        // three 2x1 views into two columns, leaving the last tile black.
+        let views: Vec<Vec<[f32; 3]>> = (0..3)
+            .map(|i| vec![[i as f32, 0.0, 0.0], [i as f32, 1.0, 0.0]])
+            .collect();
+        let out = tile_views(&views, 2, 1, 2);
+        let expected = [
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [2.0, 0.0, 0.0],
+            [2.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0],
+        ];
+        assert_eq!(out, expected.to_vec());
+    }
+}
@@ -0,0 +1,123 @@
+use super::colour::*;
+
+/// An image that's looked up by texture coordinates, and repeats outside of [0, 1].
+/// Texels are stored as linear RGBA, with v = 0 at the top row of the image.
+#[derive(Debug)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>,
+}
+
+impl ImageTexture {
+    /// texels are given row by row, starting from the top of the image.
+    pub fn new(width: usize, height: usize, texels: Vec<[f32; 4]>) -> ImageTexture {
+        assert!(
+            texels.len() == width * height && !texels.is_empty(),
+            "Texture has {} texels, but should have {}",
+            texels.len(),
+            width * height
+        );
+        ImageTexture {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    /// Builds a texture from interleaved 8 or 16 bit channels. One channel is grey, two is grey and alpha,
+    /// three is RGB and four is RGBA. Colour data is usually stored in sRGB, which is turned into linear
+    /// values when srgb is set. Alpha is always linear.
+    pub fn from_channels(
+        width: usize,
+        height: usize,
+        channels: usize,
+        samples: &[u16],
+        max_value: u16,
+        srgb: bool,
+    ) -> ImageTexture {
+        assert!(
+            (1..=4).contains(&channels),
+            "Textures have 1 to 4 channels, not {}",
+            channels
+        );
+
+        let scale = 1.0 / max_value as f32;
+        let to_linear = |s: u16| {
+            let c = s as f32 * scale;
+            if srgb {
+                srgb_to_linear(c)
+            } else {
+                c
+            }
+        };
+
+        let texels = samples
+            .chunks_exact(channels)
+            .map(|t| match channels {
+                1 => [to_linear(t[0]), to_linear(t[0]), to_linear(t[0]), 1.0],
+                2 => [
+                    to_linear(t[0]),
+                    to_linear(t[0]),
+                    to_linear(t[0]),
+                    t[1] as f32 * scale,
+                ],
+                3 => [to_linear(t[0]), to_linear(t[1]), to_linear(t[2]), 1.0],
+                _ => [
+                    to_linear(t[0]),
+                    to_linear(t[1]),
+                    to_linear(t[2]),
+                    t[3] as f32 * scale,
+                ],
+            })
+            .collect();
+        ImageTexture::new(width, height, texels)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the bilinearly filtered RGBA value at (u, v).
+    pub fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        // texel centers are at half integer coordinates.
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            self.texels[y * self.width + x]
+        };
+        let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+        let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+
+        let mut out = [0.0; 4];
+        for i in 0..4 {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            out[i] = top + (bottom - top) * fy;
+        }
+        out
+    }
+
+    /// The filtered colour at (u, v), without the alpha.
+    pub fn colour(&self, u: f32, v: f32) -> Colour {
+        let [r, g, b, _] = self.sample(u, v);
+        Colour::new(r, g, b)
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
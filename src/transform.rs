@@ -0,0 +1,355 @@
+use super::aabb::*;
+use super::ray::*;
+use super::vec3::*;
+
+/// A 4x4 matrix, stored row by row, acting on column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    m: [[f32; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(rows: [[f32; 4]; 4]) -> Matrix4 {
+        Matrix4 { m: rows }
+    }
+
+    /// For matrices stored column by column, like glTF and OpenGL do.
+    pub fn from_column_major(columns: [[f32; 4]; 4]) -> Matrix4 {
+        Matrix4 { m: columns }.transpose()
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vec3) -> Matrix4 {
+        Matrix4::new([
+            [1.0, 0.0, 0.0, offset.x()],
+            [0.0, 1.0, 0.0, offset.y()],
+            [0.0, 0.0, 1.0, offset.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scale(factors: Vec3) -> Matrix4 {
+        Matrix4::new([
+            [factors.x(), 0.0, 0.0, 0.0],
+            [0.0, factors.y(), 0.0, 0.0],
+            [0.0, 0.0, factors.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // a matrix with the given 3x3 matrix in the top left, and no translation.
+    fn from_3x3(r: [[f32; 3]; 3]) -> Matrix4 {
+        Matrix4::new([
+            [r[0][0], r[0][1], r[0][2], 0.0],
+            [r[1][0], r[1][1], r[1][2], 0.0],
+            [r[2][0], r[2][1], r[2][2], 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// A rotation of angle degrees around axis, anticlockwise when looking down the axis towards the origin.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Matrix4 {
+        let a = axis.unit_vector();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+
+        Matrix4::from_3x3([
+            [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y],
+            [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x],
+            [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos],
+        ])
+    }
+
+    pub fn rotation_x(angle: f32) -> Matrix4 {
+        Matrix4::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), angle)
+    }
+
+    pub fn rotation_y(angle: f32) -> Matrix4 {
+        Matrix4::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), angle)
+    }
+
+    pub fn rotation_z(angle: f32) -> Matrix4 {
+        Matrix4::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), angle)
+    }
+
+    /// Rotates around x, then y, then z, by angles in degrees. This is Blender's default XYZ Euler order.
+    pub fn from_euler(x: f32, y: f32, z: f32) -> Matrix4 {
+        Matrix4::rotation_z(z) * Matrix4::rotation_y(y) * Matrix4::rotation_x(x)
+    }
+
+    /// The rotation for the quaternion x i + y j + z k + w, which doesn't need to be normalized.
+    pub fn from_quaternion(x: f32, y: f32, z: f32, w: f32) -> Matrix4 {
+        let length = (x * x + y * y + z * z + w * w).sqrt();
+        let (x, y, z, w) = (x / length, y / length, z / length, w / length);
+
+        Matrix4::from_3x3([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - z * w),
+                2.0 * (x * z + y * w),
+            ],
+            [
+                2.0 * (x * y + z * w),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - x * w),
+            ],
+            [
+                2.0 * (x * z - y * w),
+                2.0 * (y * z + x * w),
+                1.0 - 2.0 * (x * x + y * y),
+            ],
+        ])
+    }
+
+    /// Places something at eye, with its -z axis pointing at target and its y axis as close to up as it
+    /// can be. This is the same way round as the cameras, so it can aim objects the way a camera would.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Matrix4 {
+        let w = (eye - target).unit_vector();
+        let u = up.cross(w).unit_vector();
+        let v = w.cross(u);
+
+        Matrix4::new([
+            [u.x(), v.x(), w.x(), eye.x()],
+            [u.y(), v.y(), w.y(), eye.y()],
+            [u.z(), v.z(), w.z(), eye.z()],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn get(&self, row: usize, column: usize) -> f32 {
+        self.m[row][column]
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut out = [[0.0; 4]; 4];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4::new(out)
+    }
+
+    /// Returns None if the matrix squashes space flat, and so can't be undone.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        // Gauss-Jordan elimination, with partial pivoting to keep it stable.
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4::new(inv))
+    }
+
+    /// The determinant of the top left 3x3, which is negative if the matrix mirrors things.
+    pub fn determinant3(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1.0 {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x, y, z) / w
+        }
+    }
+
+    //directions aren't moved by the translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl std::ops::Mul for Matrix4 {
+    type Output = Matrix4;
+
+    //the result applies other first, then self.
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut out = [[0.0; 4]; 4];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4::new(out)
+    }
+}
+
+/// An invertible affine transform, keeping its inverse alongside it so it never needs working out again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix4) -> Transform {
+        let inverse = matrix
+            .inverse()
+            .expect("Transform matrix can't be inverted, as it squashes space flat!");
+        Transform { matrix, inverse }
+    }
+
+    pub fn identity() -> Transform {
+        Transform::new(Matrix4::identity())
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    /// The transform that applies other, and then self.
+    pub fn compose(&self, other: &Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+
+    /// Whether this turns right handed coordinates into left handed ones, like a mirror.
+    pub fn swaps_handedness(&self) -> bool {
+        self.matrix.determinant3() < 0.0
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Normals stay at right angles to the surface by using the inverse transpose. The result isn't normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let inv = &self.inverse;
+        Vec3::new(
+            inv.get(0, 0) * n.x() + inv.get(1, 0) * n.y() + inv.get(2, 0) * n.z(),
+            inv.get(0, 1) * n.x() + inv.get(1, 1) * n.y() + inv.get(2, 1) * n.z(),
+            inv.get(0, 2) * n.x() + inv.get(1, 2) * n.y() + inv.get(2, 2) * n.z(),
+        )
+    }
+
+    /// The direction isn't normalized, so distances along the ray stay the same as before.
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::new_at_time(self.point(r.origin()), self.vector(r.direction()), r.time())
+    }
+
+    /// The smallest box around the transformed box. Each corner of the new box is found from
+    /// whichever of the old box's extents makes it the furthest out, rather than moving all 8 corners.
+    pub fn aa_box(&self, aa_box: &AABB) -> AABB {
+        let m = &self.matrix;
+        let mut min = Vec3::new(m.get(0, 3), m.get(1, 3), m.get(2, 3));
+        let mut max = min;
+
+        for i in 0..3 {
+            for j in 0..3 {
+                let a = m.get(i, j) * aa_box.min().get(j);
+                let b = m.get(i, j) * aa_box.max().get(j);
+                min.set(i, min.get(i) + a.min(b));
+                max.set(i, max.get(i) + a.max(b));
+            }
+        }
+        AABB::new(min, max)
+    }
+}
+
+impl From<Matrix4> for Transform {
+    fn from(matrix: Matrix4) -> Transform {
+        Transform::new(matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn rotations_agree_and_invert() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+
+        // a quarter turn around y, three ways.
+        let axis_angle = Matrix4::rotation_y(90.0);
+        let euler = Matrix4::from_euler(0.0, 90.0, 0.0);
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let quaternion = Matrix4::from_quaternion(0.0, half, 0.0, half);
+        assert!(close(
+            axis_angle.transform_point(p),
+            Vec3::new(3.0, 2.0, -1.0)
+        ));
+        assert!(close(
+            euler.transform_point(p),
+            axis_angle.transform_point(p)
+        ));
+        assert!(close(
+            quaternion.transform_point(p),
+            axis_angle.transform_point(p)
+        ));
+
+        let t = Transform::new(
+            Matrix4::translation(Vec3::new(4.0, -1.0, 2.0))
+                * Matrix4::from_euler(30.0, 45.0, 60.0)
+                * Matrix4::scale(Vec3::new(2.0, 1.0, 0.5)),
+        );
+        assert!(close(t.inverse().point(t.point(p)), p));
+
+        // normals stay at right angles to the surface they belong to, even with uneven scaling.
+        let (tangent, normal) = (Vec3::new(1.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 0.0));
+        assert!(t.vector(tangent).dot(t.normal(normal)).abs() < 1e-4);
+    }
+}
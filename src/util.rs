@@ -22,3 +22,183 @@ pub fn random_in_unit_disk() -> Vec3 {
     }
     p
 }
+
+/// Returns two unit vectors which, along with the unit vector n, make an orthonormal basis.
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    // from "Building an Orthonormal Basis, Revisited" (Duff et al. 2017).
+    let sign = 1.0f32.copysign(n.z());
+    let a = -1.0 / (sign + n.z());
+    let b = n.x() * n.y() * a;
+    (
+        Vec3::new(1.0 + sign * n.x() * n.x() * a, sign * b, -sign * n.x()),
+        Vec3::new(b, sign + n.y() * n.y() * a, -n.y()),
+    )
+}
+
+/// Loads a png as a grid of luminance values in [0, 1], row by row from the top of the image.
+/// Colour images are reduced to luminance, alpha is ignored and 16 bit images keep their precision.
+pub fn load_luminance_png(
+    path: &std::path::Path,
+) -> Result<(usize, usize, Vec<f32>), png::DecodingError> {
+    let mut decoder = png::Decoder::new(std::fs::File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let channels = info.color_type.samples();
+    let (bytes, max) = match info.bit_depth {
+        png::BitDepth::Sixteen => (2, 65535.0),
+        _ => (1, 255.0),
+    };
+
+    let sample = |i: usize| -> f32 {
+        if bytes == 2 {
+            u16::from_be_bytes([buf[i * 2], buf[i * 2 + 1]]) as f32 / max
+        } else {
+            buf[i] as f32 / max
+        }
+    };
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut values = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = y * info.line_size / bytes;
+        for x in 0..width {
+            let i = row + x * channels;
+            let lum = match channels {
+                3 | 4 => 0.2126 * sample(i) + 0.7152 * sample(i + 1) + 0.0722 * sample(i + 2),
+                _ => sample(i),
+            };
+            values.push(lum);
+        }
+    }
+    Ok((width, height, values))
+}
+
+/// A local coordinate frame with its z axis along w, for shapes that are simplest to hit when they
+/// stand upright at the origin. The frame is rigid, so distances along rays are the same in both.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    pub fn new(origin: Vec3, axis: Vec3) -> Frame {
+        let w = axis.unit_vector();
+        let (u, v) = orthonormal_basis(w);
+        Frame { origin, u, v, w }
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    pub fn to_local(&self, p: Vec3) -> Vec3 {
+        self.to_local_vector(p - self.origin)
+    }
+
+    pub fn to_local_vector(&self, d: Vec3) -> Vec3 {
+        Vec3::new(d.dot(self.u), d.dot(self.v), d.dot(self.w))
+    }
+
+    pub fn to_world(&self, p: Vec3) -> Vec3 {
+        self.origin + self.to_world_vector(p)
+    }
+
+    pub fn to_world_vector(&self, d: Vec3) -> Vec3 {
+        d.x() * self.u + d.y() * self.v + d.z() * self.w
+    }
+
+    /// A world space box around the local box from min to max.
+    pub fn aa_box(&self, min: Vec3, max: Vec3) -> crate::aabb::AABB {
+        let center = self.to_world(0.5 * (min + max));
+        let half = 0.5 * (max - min);
+        let extent = |i: usize| {
+            self.u.get(i).abs() * half.x()
+                + self.v.get(i).abs() * half.y()
+                + self.w.get(i).abs() * half.z()
+        };
+        let extent = Vec3::new(extent(0), extent(1), extent(2));
+        crate::aabb::AABB::new(center - extent, center + extent)
+    }
+}
+
+/// The real roots of x^2 + b x + c, smallest first.
+fn solve_quadratic(b: f64, c: f64) -> Vec<f64> {
+    let discriminant = b * b - 4.0 * c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+    // avoids cancellation between b and the square root.
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return vec![0.0, 0.0];
+    }
+    let (r0, r1) = (q, c / q);
+    vec![r0.min(r1), r0.max(r1)]
+}
+
+/// The largest real root of x^3 + a x^2 + b x + c.
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // with x = y - a/3, this becomes y^3 + p y + q.
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+
+    let y = if discriminant >= 0.0 {
+        let s = discriminant.sqrt();
+        (-q / 2.0 + s).cbrt() + (-q / 2.0 - s).cbrt()
+    } else {
+        // three real roots, and the first of the trigonometric ones is the largest.
+        let r = (-p / 3.0).sqrt();
+        2.0 * r * ((-q / (2.0 * r * r * r)).clamp(-1.0, 1.0).acos() / 3.0).cos()
+    };
+    y - a / 3.0
+}
+
+/// The real roots of x^4 + a x^3 + b x^2 + c x + d, smallest first, using Ferrari's method.
+/// Each root is polished with a couple of Newton steps, which cleans up most of the rounding error.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // with x = y - a/4, this becomes y^4 + p y^2 + q y + r.
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+
+    let mut roots = if q.abs() < 1e-12 {
+        // a quadratic in y^2.
+        solve_quadratic(p, r)
+            .into_iter()
+            .filter(|&z| z >= 0.0)
+            .flat_map(|z| vec![-z.sqrt(), z.sqrt()])
+            .collect::<Vec<_>>()
+    } else {
+        // m is a root of the resolvent cubic, which splits the quartic into two quadratics.
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return vec![];
+        }
+        let s = (2.0 * m).sqrt();
+        let mut roots = solve_quadratic(s, p / 2.0 + m - q / (2.0 * s));
+        roots.extend(solve_quadratic(-s, p / 2.0 + m + q / (2.0 * s)));
+        roots
+    };
+
+    for x in roots.iter_mut() {
+        *x -= a / 4.0;
+        for _ in 0..2 {
+            let f = (((*x + a) * *x + b) * *x + c) * *x + d;
+            let df = ((4.0 * *x + 3.0 * a) * *x + 2.0 * b) * *x + c;
+            if df != 0.0 {
+                *x -= f / df;
+            }
+        }
+    }
+    roots.sort_by(|l, r| l.partial_cmp(r).unwrap());
+    roots
+}